@@ -0,0 +1,135 @@
+//! `SharedWriter` against a `RollingBuffer` behind a `Mutex`, the way they are shared between a
+//! thread reading the RTDE stream and threads displaying it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use universal_robot::rolling_buffer::RollingBuffer;
+use universal_robot::shared_buffer::{NoPadding, SharedWriter};
+
+const CAPACITY: usize = 500;
+
+/// Roughly the size of a joint health sample, only ever copied
+#[allow(dead_code)]
+#[derive(Default, Clone, Copy)]
+struct Sample {
+    timestamp: f64,
+    currents: [f64; 6],
+    temperatures: [f64; 6],
+}
+
+// SAFETY: only f64 fields
+unsafe impl NoPadding for Sample {}
+
+fn sample(index: usize) -> Sample {
+    Sample {
+        timestamp: index as f64 * 0.002,
+        ..Sample::default()
+    }
+}
+
+fn push(c: &mut Criterion) {
+    let mut group = c.benchmark_group("push");
+    let buffer = Mutex::new(RollingBuffer::new(CAPACITY));
+    let mut index = 0;
+    group.bench_function("mutex_rolling_buffer", |b| {
+        b.iter(|| {
+            index += 1;
+            buffer.lock().unwrap().add(black_box(sample(index)));
+        })
+    });
+    let mut writer = SharedWriter::new(CAPACITY);
+    let mut index = 0;
+    group.bench_function("shared_buffer", |b| {
+        b.iter(|| {
+            index += 1;
+            writer.push(black_box(sample(index)));
+        })
+    });
+    group.finish();
+}
+
+fn snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot");
+    let mut buffer = RollingBuffer::new(CAPACITY);
+    let mut writer = SharedWriter::new(CAPACITY);
+    for index in 0..CAPACITY {
+        buffer.add(sample(index));
+        writer.push(sample(index));
+    }
+    let buffer = Mutex::new(buffer);
+    group.bench_function("mutex_rolling_buffer", |b| {
+        b.iter(|| black_box(buffer.lock().unwrap().values()))
+    });
+    let reader = writer.reader();
+    let mut values = Vec::with_capacity(CAPACITY);
+    group.bench_function("shared_buffer", |b| {
+        b.iter(|| {
+            reader.snapshot_into(&mut values);
+            black_box(&values);
+        })
+    });
+    group.finish();
+}
+
+/// Push while two other threads snapshot continuously, the case where the mutex makes the
+/// writer wait
+fn contended_push(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contended_push");
+    let running = Arc::new(AtomicBool::new(true));
+
+    let buffer = Arc::new(Mutex::new(RollingBuffer::new(CAPACITY)));
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let (buffer, running) = (buffer.clone(), running.clone());
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    black_box(buffer.lock().unwrap().values());
+                }
+            })
+        })
+        .collect();
+    let mut index = 0;
+    group.bench_function("mutex_rolling_buffer", |b| {
+        b.iter(|| {
+            index += 1;
+            buffer.lock().unwrap().add(black_box(sample(index)));
+        })
+    });
+    running.store(false, Ordering::Relaxed);
+    readers
+        .into_iter()
+        .for_each(|reader| reader.join().unwrap());
+
+    running.store(true, Ordering::Relaxed);
+    let mut writer = SharedWriter::new(CAPACITY);
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let (reader, running) = (writer.reader(), running.clone());
+            thread::spawn(move || {
+                let mut values = Vec::with_capacity(CAPACITY);
+                while running.load(Ordering::Relaxed) {
+                    reader.snapshot_into(&mut values);
+                    black_box(&values);
+                }
+            })
+        })
+        .collect();
+    let mut index = 0;
+    group.bench_function("shared_buffer", |b| {
+        b.iter(|| {
+            index += 1;
+            writer.push(black_box(sample(index)));
+        })
+    });
+    running.store(false, Ordering::Relaxed);
+    readers
+        .into_iter()
+        .for_each(|reader| reader.join().unwrap());
+    group.finish();
+}
+
+criterion_group!(benches, push, snapshot, contended_push);
+criterion_main!(benches);
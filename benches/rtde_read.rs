@@ -0,0 +1,194 @@
+//! RTDE read paths against a local fake robot streaming the joint health recipe.
+//!
+//! Before the criterion benchmarks, the stream is read at a real 500 Hz and the heap
+//! allocations made by the reading thread are counted, which must be none for the
+//! allocation free paths.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, Criterion, Throughput};
+use universal_robot::data::{JointHealth, Value, JOINT_HEALTH_OUTPUTS};
+use universal_robot::Rtde;
+
+/// Counts the allocations made by each thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const JOINT_HEALTH_TYPES: &str =
+    "DOUBLE,VECTOR6D,VECTOR6D,VECTOR6D,VECTOR6D,VECTOR6INT32,DOUBLE,DOUBLE,DOUBLE";
+
+fn package(package_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (3 + payload.len() as u16).to_be_bytes().to_vec();
+    bytes.push(package_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Accept one connection, answer its setup and stream joint health samples once started,
+/// every 2 ms when `paced` or as fast as the socket takes them, until the client disconnects.
+///
+/// Listens on a free port, returned with the robot's thread.
+fn fake_robot(paced: bool) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    let robot = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        drop(listener);
+        loop {
+            let mut header = [0u8; 3];
+            stream.read_exact(&mut header).unwrap();
+            let size = u16::from_be_bytes([header[0], header[1]]) as usize;
+            let mut request = vec![0; size - 3];
+            stream.read_exact(&mut request).unwrap();
+            let reply = match header[2] {
+                b'O' => [&[1], JOINT_HEALTH_TYPES.as_bytes()].concat(),
+                _ => vec![1],
+            };
+            stream.write_all(&package(header[2], &reply)).unwrap();
+            if header[2] == b'S' {
+                break;
+            }
+        }
+        let mut sample = vec![1u8];
+        sample.extend((0..25).flat_map(|value| (value as f64).to_be_bytes()));
+        sample.extend((0..6).flat_map(|mode: i32| mode.to_be_bytes()));
+        sample.extend((0..3).flat_map(|value| (value as f64).to_be_bytes()));
+        let data = package(b'U', &sample);
+        // unpaced, send samples in blocks so the benchmark isn't bound by the writes
+        let block = if paced { 1 } else { 64 };
+        let mut blocks = data.repeat(block);
+        let start = Instant::now();
+        for cycle in (1..).step_by(block) {
+            for (index, data) in blocks.chunks_mut(data.len()).enumerate() {
+                let timestamp = (cycle + index) as f64 * 0.002;
+                data[4..12].copy_from_slice(&timestamp.to_be_bytes());
+            }
+            if stream.write_all(&blocks).is_err() {
+                break;
+            }
+            if paced {
+                let due = Duration::from_secs_f64(cycle as f64 * 0.002);
+                thread::sleep(due.saturating_sub(start.elapsed()));
+            }
+        }
+    });
+    (address, robot)
+}
+
+fn connect(address: SocketAddr) -> Rtde {
+    let mut rtde = Rtde::connect(address, Some(Duration::from_secs(1))).unwrap();
+    rtde.setup_output(&JOINT_HEALTH_OUTPUTS, 500.0).unwrap();
+    rtde.start().unwrap();
+    rtde
+}
+
+/// Read `samples` at the robot's 500 Hz, returning the rate achieved and the allocations made
+fn sustained(samples: usize, mut read: impl FnMut(&mut Rtde)) -> (f64, usize) {
+    let (address, robot) = fake_robot(true);
+    let mut rtde = connect(address);
+    for _ in 0..50 {
+        read(&mut rtde);
+    }
+    let (start, allocated) = (Instant::now(), allocations());
+    for _ in 0..samples {
+        read(&mut rtde);
+    }
+    let (elapsed, allocated) = (start.elapsed(), allocations() - allocated);
+    drop(rtde);
+    robot.join().unwrap();
+    (samples as f64 / elapsed.as_secs_f64(), allocated)
+}
+
+/// Name, whether it must not allocate, and one read of a sample
+type ReadCase<'a> = (&'static str, bool, Box<dyn FnMut(&mut Rtde) + 'a>);
+
+fn sustained_500hz() {
+    const SAMPLES: usize = 1000;
+    let mut health = JointHealth::default();
+    let mut values = [Value::U8(0); JOINT_HEALTH_OUTPUTS.len()];
+    let cases: [ReadCase; 3] = [
+        (
+            "read + parse",
+            false,
+            Box::new(|rtde| {
+                black_box(rtde.read().unwrap().parse::<JointHealth>().unwrap());
+            }),
+        ),
+        (
+            "read_data_into",
+            true,
+            Box::new(|rtde| assert!(rtde.read_data_into(black_box(&mut health)).unwrap())),
+        ),
+        (
+            "read_values_into",
+            true,
+            Box::new(|rtde| assert!(rtde.read_values_into(black_box(&mut values)).unwrap())),
+        ),
+    ];
+    for (name, allocation_free, read) in cases {
+        let (rate, allocated) = sustained(SAMPLES, read);
+        println!("{name:<18} {rate:6.1} Hz, {allocated} allocations in {SAMPLES} samples");
+        assert!(rate > 490.0, "{name} could not keep up with 500 Hz");
+        if allocation_free {
+            assert_eq!(allocated, 0, "{name} allocated while streaming");
+        }
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    let (address, robot) = fake_robot(false);
+    let mut rtde = connect(address);
+    let mut group = c.benchmark_group("read_joint_health");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("read_parse", |b| {
+        b.iter(|| black_box(rtde.read().unwrap().parse::<JointHealth>().unwrap()))
+    });
+    let mut health = JointHealth::default();
+    group.bench_function("read_data_into", |b| {
+        b.iter(|| rtde.read_data_into(black_box(&mut health)).unwrap())
+    });
+    let mut values = [Value::U8(0); JOINT_HEALTH_OUTPUTS.len()];
+    group.bench_function("read_values_into", |b| {
+        b.iter(|| rtde.read_values_into(black_box(&mut values)).unwrap())
+    });
+    group.finish();
+    drop(rtde);
+    robot.join().unwrap();
+}
+
+fn main() {
+    sustained_500hz();
+    let mut criterion = Criterion::default().configure_from_args();
+    decode(&mut criterion);
+    criterion.final_summary();
+}
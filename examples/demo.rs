@@ -14,31 +14,16 @@ use std::time::Duration;
 use std::{error::Error, net::Ipv4Addr};
 
 use serde::Deserialize;
-use universal_robot::data::Vec6;
+use universal_robot::data::{DefaultOutputs, Vec6, DEFAULT_OUTPUTS};
 use universal_robot::prelude::*;
 
+/// The standard telemetry with the program's handshake register
 #[derive(Deserialize, Debug)]
-#[allow(unused)]
 struct Output {
-    digital_bits: u64,
-    timestamp: f64,
-    tcp_pose: Vec6,
-    tcp_speed: Vec6,
-    joint_poses: Vec6,
-    safety_mode: i32,
-    robot_mode: i32,
+    defaults: DefaultOutputs,
     is_ready: i32,
 }
-const OUTPUTS: &[&str] = &[
-    "actual_digital_output_bits",
-    "timestamp",
-    "actual_TCP_pose",
-    "actual_TCP_speed",
-    "actual_q",
-    "safety_mode",
-    "robot_mode",
-    "output_int_register_0", // is_ready
-];
+const IS_READY: &str = "output_int_register_0";
 
 const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
 const TIMEOUT: Duration = Duration::from_secs(10);
@@ -60,7 +45,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     ur.dashboard.popup_close()?;
 
     // Set up RTDE Recipies for communicating real-time data with the Robot
-    let output_recipe = ur.rtde.setup_output(OUTPUTS, DATA_RATE_HZ)?;
+    let outputs: Vec<&str> = DEFAULT_OUTPUTS.into_iter().chain([IS_READY]).collect();
+    let output_recipe = ur.rtde.setup_output(&outputs, DATA_RATE_HZ)?;
     println!("output types: {:?}", output_recipe.get_types());
     println!("-----");
     ur.rtde.start()?;
@@ -103,7 +89,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             ur.rtde.write(pose, input_recipe_2.id())?;
             ur.rtde.write(1i32, input_recipe_1.id())?; // set initial value of recipe
         } else if !move_completed && state.is_ready == 0 {
-            println!("move to confirmed pose = {:?}", state.tcp_pose);
+            println!("move to confirmed pose = {:?}", state.defaults.tcp_pose);
             move_completed = true;
             ur.rtde.write(0i32, input_recipe_1.id())?;
         }
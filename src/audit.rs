@@ -0,0 +1,476 @@
+//! Audit trail of the commands sent to robots
+//!
+//! An [`AuditLog`] records every dashboard command, RTDE setup, input write, start and pause,
+//! and every script sent to the primary interface, with its time, the robot's response and
+//! whether it succeeded, failed or was denied by a [`CommandPolicy`]. Entries go to one or more
+//! [`AuditSink`]s: [`JsonLinesSink`] appends them to a file, [`RobotLogSink`] mirrors them to
+//! the robot's own log.
+//!
+//! ```ignore
+//! let audit = AuditLog::new()
+//!     .sink(JsonLinesSink::open("audit/cell_3.jsonl")?.rotate_size(10_000_000))
+//!     .sink(RobotLogSink::new(Dashboard::new(address, Some(timeout))?));
+//! ur.set_audit(audit);
+//! ```
+//!
+//! Entries are handed to a writer thread over a bounded channel, so recording never holds up the
+//! thread sending commands, e.g. a control loop writing RTDE inputs. If the sinks fall behind,
+//! entries are dropped and counted, see [`AuditLog::dropped`]. A sink that fails to record an
+//! entry is logged. Call [`AuditLog::flush`] before exiting to wait for the queued entries.
+//!
+//! [`CommandPolicy`]: crate::dashboard::policy::CommandPolicy
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dashboard::policy::CommandCategory;
+use crate::prelude::*;
+use crate::recorder::rotated_path;
+use crate::Interface;
+
+/// How a command went
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Ok,
+    /// Sent, or attempted, and failed with this error
+    Failed(String),
+    /// Not sent, denied by the handle's command policy
+    Denied(String),
+}
+
+impl<T> From<&Result<T>> for Outcome {
+    fn from(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Ok,
+            Err(error @ Error::Policy(_)) => Outcome::Denied(error.to_string()),
+            Err(error) => Outcome::Failed(error.to_string()),
+        }
+    }
+}
+
+/// One command sent to a robot
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    /// The robot's label, its address unless the log was given another
+    pub robot: Option<String>,
+    pub interface: Interface,
+    pub command: String,
+    pub response: Option<String>,
+    pub outcome: Outcome,
+}
+
+impl AuditEntry {
+    /// The entry as a single line of JSON, with the time in seconds since the Unix epoch
+    pub fn to_json(&self) -> String {
+        let time = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |time| time.as_secs_f64());
+        let (outcome, error) = match &self.outcome {
+            Outcome::Ok => ("ok", None),
+            Outcome::Failed(error) => ("failed", Some(error)),
+            Outcome::Denied(error) => ("denied", Some(error)),
+        };
+        serde_json::json!({
+            "time": time,
+            "robot": self.robot,
+            "interface": self.interface.to_string(),
+            "command": self.command,
+            "response": self.response,
+            "outcome": outcome,
+            "error": error,
+        })
+        .to_string()
+    }
+    /// Commands too frequent to be worth mirroring to the robot log: dashboard queries and
+    /// messages, which leave the robot as it is, and RTDE input writes, which a control loop
+    /// sends at up to the stream's frequency
+    pub fn is_routine(&self) -> bool {
+        match self.interface {
+            Interface::Dashboard => CommandCategory::of(&self.command) == CommandCategory::Query,
+            Interface::Rtde => self.command.starts_with("write "),
+            Interface::Primary | Interface::Secondary => false,
+        }
+    }
+}
+
+/// Destination of audit entries
+pub trait AuditSink: Send {
+    fn record(&mut self, entry: &AuditEntry) -> Result<()>;
+}
+
+/// Sent to the writer thread
+enum Message {
+    Entry(AuditEntry),
+    /// Answered once the entries queued before it are recorded
+    Flush(SyncSender<()>),
+}
+
+/// Records commands to its sinks on a writer thread, cloned into each interface of a robot, or
+/// each robot of a fleet, so they all share the sinks
+///
+/// The writer thread stops once every clone is dropped, after recording the queued entries.
+#[derive(Clone)]
+pub struct AuditLog {
+    sinks: Arc<Mutex<Vec<Box<dyn AuditSink>>>>,
+    sender: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+    robot: Option<String>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuditLog {
+    /// Entries queued for the writer thread before new ones are dropped
+    pub const CAPACITY: usize = 4096;
+
+    /// Start the writer thread, without any sinks yet
+    pub fn new() -> Self {
+        let sinks = Arc::new(Mutex::new(Vec::new()));
+        let (sender, receiver) = sync_channel(Self::CAPACITY);
+        let writer = Arc::clone(&sinks);
+        std::thread::Builder::new()
+            .name("audit-writer".to_owned())
+            .spawn(move || Self::run(&writer, receiver))
+            .expect("failed to spawn the audit writer thread");
+        AuditLog {
+            sinks,
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            robot: None,
+        }
+    }
+    /// Also record to this sink, in every clone of the log
+    pub fn sink(self, sink: impl AuditSink + 'static) -> Self {
+        lock(&self.sinks).push(Box::new(sink));
+        self
+    }
+    /// The same sinks, labelling entries with this robot
+    pub fn for_robot(&self, robot: &str) -> Self {
+        AuditLog {
+            robot: Some(robot.to_owned()),
+            ..self.clone()
+        }
+    }
+    pub fn robot(&self) -> Option<&str> {
+        self.robot.as_deref()
+    }
+    /// Entries dropped so far, by any clone, because the sinks fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+    /// Queue an entry for the sinks without blocking
+    pub fn record(&self, entry: &AuditEntry) {
+        match self.sender.try_send(Message::Entry(entry.clone())) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "audit log is behind, dropped {} '{}'",
+                    entry.interface,
+                    entry.command
+                );
+            }
+        }
+    }
+    /// Wait until the entries queued so far are recorded
+    pub fn flush(&self) {
+        let (done, wait) = sync_channel(1);
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            // an error means the writer thread is gone, there is nothing left to wait for
+            let _ = wait.recv();
+        }
+    }
+    /// Record a command with the result it returned
+    pub(crate) fn command<T>(
+        &self,
+        interface: Interface,
+        command: &str,
+        response: Option<&str>,
+        result: &Result<T>,
+    ) {
+        self.record(&AuditEntry {
+            time: SystemTime::now(),
+            robot: self.robot.clone(),
+            interface,
+            command: command.to_owned(),
+            response: response.map(str::to_owned),
+            outcome: result.into(),
+        });
+    }
+    /// Writer thread, until every clone of the log is dropped
+    fn run(sinks: &Mutex<Vec<Box<dyn AuditSink>>>, receiver: Receiver<Message>) {
+        for message in receiver {
+            let entry = match message {
+                Message::Entry(entry) => entry,
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+            for sink in lock(sinks).iter_mut() {
+                if let Err(error) = sink.record(&entry) {
+                    log::error!(
+                        "failed to audit {} '{}': {error}",
+                        entry.interface,
+                        entry.command
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn lock(
+    sinks: &Mutex<Vec<Box<dyn AuditSink>>>,
+) -> std::sync::MutexGuard<'_, Vec<Box<dyn AuditSink>>> {
+    // a sink that panicked has lost at most the entry it was writing
+    sinks.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Appends entries to a file as JSON lines, flushed as they are recorded
+///
+/// With a rotation size, the file is renamed once it reaches the size, `audit.jsonl` to the
+/// first free of `audit.1.jsonl`, `audit.2.jsonl`, ..., and a new one started. Existing files
+/// are appended to, never truncated.
+pub struct JsonLinesSink {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    rotate_size: Option<u64>,
+}
+
+impl JsonLinesSink {
+    /// Append to the file, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::append(&path)?;
+        Ok(JsonLinesSink {
+            written: file.metadata()?.len(),
+            path,
+            file,
+            rotate_size: None,
+        })
+    }
+    /// Start a new file once the current one reaches this many bytes
+    pub fn rotate_size(mut self, bytes: u64) -> Self {
+        self.rotate_size = Some(bytes);
+        self
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    fn append(path: &Path) -> Result<File> {
+        Ok(OpenOptions::new().create(true).append(true).open(path)?)
+    }
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = (1..)
+            .map(|index| rotated_path(&self.path, index))
+            .find(|path| !path.exists())
+            .expect("a free index");
+        std::fs::rename(&self.path, &rotated)?;
+        log::debug!("audit log rotated to {}", rotated.display());
+        self.file = Self::append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl AuditSink for JsonLinesSink {
+    fn record(&mut self, entry: &AuditEntry) -> Result<()> {
+        if self.rotate_size.is_some_and(|size| self.written >= size) {
+            self.rotate()?;
+        }
+        let mut line = entry.to_json();
+        line.push('\n');
+        // one write per entry, so concurrent appenders don't interleave within a line
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Mirrors entries to the robot log with [`Dashboard::log`], on a dashboard connection of its own
+///
+/// By default routine entries are skipped, see [`AuditEntry::is_routine`], as polling the robot
+/// would otherwise flood its log.
+pub struct RobotLogSink {
+    dashboard: Dashboard,
+    filter: fn(&AuditEntry) -> bool,
+}
+
+impl RobotLogSink {
+    pub fn new(dashboard: Dashboard) -> Self {
+        RobotLogSink {
+            dashboard,
+            filter: |entry| !entry.is_routine(),
+        }
+    }
+    /// Mirror only the entries this returns true for
+    pub fn filter(mut self, filter: fn(&AuditEntry) -> bool) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+impl AuditSink for RobotLogSink {
+    fn record(&mut self, entry: &AuditEntry) -> Result<()> {
+        if !(self.filter)(entry) {
+            return Ok(());
+        }
+        // scripts are mirrored by their first line, the robot log shows one line per message
+        let command = entry.command.lines().next().unwrap_or_default();
+        let outcome = match &entry.outcome {
+            Outcome::Ok => "ok".to_owned(),
+            Outcome::Failed(error) => format!("failed: {error}"),
+            Outcome::Denied(error) => format!("denied: {error}"),
+        };
+        let robot = entry
+            .robot
+            .as_deref()
+            .map_or(String::new(), |robot| format!("{robot} "));
+        self.dashboard.log(&format!(
+            "audit: {robot}{} '{command}' {outcome}",
+            entry.interface
+        ))?;
+        Ok(())
+    }
+}
+
+impl UniversalRobot {
+    /// Record every command sent on any interface to the audit log, labelled with the robot's
+    /// address unless the log has a label
+    pub fn set_audit(&mut self, audit: AuditLog) {
+        let audit = match audit.robot() {
+            Some(_) => audit,
+            None => audit.for_robot(&self.config().address.to_string()),
+        };
+        self.dashboard.set_audit(audit.clone());
+        self.rtde.set_audit(audit.clone());
+        self.audit = Some(audit);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::dashboard::policy::CommandPolicy;
+    use crate::physical::tests::closed_ports;
+
+    /// Keeps entries in memory
+    pub(crate) struct Entries(pub Arc<Mutex<Vec<AuditEntry>>>);
+
+    impl AuditSink for Entries {
+        fn record(&mut self, entry: &AuditEntry) -> Result<()> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    fn entry(command: &str, outcome: Outcome) -> AuditEntry {
+        AuditEntry {
+            time: UNIX_EPOCH + Duration::from_millis(1500),
+            robot: Some("left".to_owned()),
+            interface: Interface::Dashboard,
+            command: command.to_owned(),
+            response: Some("Stopped".to_owned()),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let line = entry("stop", Outcome::Ok).to_json();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["time"], 1.5);
+        assert_eq!(json["robot"], "left");
+        assert_eq!(json["interface"], "dashboard");
+        assert_eq!(json["command"], "stop");
+        assert_eq!(json["response"], "Stopped");
+        assert_eq!(json["outcome"], "ok");
+        assert!(json["error"].is_null());
+        let line = entry("shutdown", Outcome::Denied("not allowed".to_owned())).to_json();
+        assert!(line.contains(r#""outcome":"denied""#) && line.contains("not allowed"));
+        assert!(entry("robotmode", Outcome::Ok).is_routine());
+        assert!(!entry("stop", Outcome::Ok).is_routine());
+    }
+    #[test]
+    fn test_rotation() {
+        let directory = std::env::temp_dir().join(format!("ur_audit_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("audit.jsonl");
+        let line = entry("stop", Outcome::Ok).to_json().len() as u64 + 1;
+        let mut sink = JsonLinesSink::open(&path).unwrap().rotate_size(2 * line);
+        for _ in 0..5 {
+            sink.record(&entry("stop", Outcome::Ok)).unwrap();
+        }
+        drop(sink);
+        // reopening appends to the current file, then rotates past the existing ones
+        let mut sink = JsonLinesSink::open(&path).unwrap().rotate_size(2 * line);
+        sink.record(&entry("stop", Outcome::Ok)).unwrap();
+        sink.record(&entry("stop", Outcome::Ok)).unwrap();
+        let lines = |name: &str| {
+            let contents = std::fs::read_to_string(directory.join(name)).unwrap();
+            for line in contents.lines() {
+                serde_json::from_str::<serde_json::Value>(line).unwrap();
+            }
+            contents.lines().count()
+        };
+        assert_eq!(lines("audit.1.jsonl"), 2);
+        assert_eq!(lines("audit.2.jsonl"), 2);
+        assert_eq!(lines("audit.3.jsonl"), 2);
+        assert_eq!(lines("audit.jsonl"), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+    #[test]
+    fn test_robot_audit() {
+        let mut ur = UniversalRobot::builder(IpAddr::from(Ipv4Addr::LOCALHOST))
+            .ports(closed_ports())
+            .connect_timeout(Duration::from_millis(200))
+            .lazy(true)
+            .connect()
+            .unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        ur.set_audit(AuditLog::new().sink(Entries(entries.clone())));
+        ur.dashboard.restrict(CommandPolicy::new()).unwrap();
+
+        assert!(ur.dashboard.power(false).is_err());
+        assert!(ur.dashboard.get_mode().is_err());
+        assert!(ur.rtde.setup_output(&["timestamp"], 125.0).is_err());
+        assert!(ur.send_script("popup(\"hello\")").is_err());
+        ur.audit.as_ref().unwrap().flush();
+
+        let entries = entries.lock().unwrap();
+        let summary: Vec<(Interface, &str, bool)> = entries
+            .iter()
+            .map(|entry| {
+                let denied = matches!(entry.outcome, Outcome::Denied(_));
+                (entry.interface, entry.command.as_str(), denied)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Interface::Dashboard, "power off", true),
+                (Interface::Dashboard, "robotmode", false),
+                (Interface::Rtde, "setup_output timestamp at 125 Hz", false),
+                (Interface::Primary, "popup(\"hello\")", false),
+            ]
+        );
+        assert!(matches!(entries[1].outcome, Outcome::Failed(_)));
+        assert_eq!(entries[0].robot.as_deref(), Some("127.0.0.1"));
+    }
+}
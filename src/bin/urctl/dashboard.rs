@@ -0,0 +1,245 @@
+//! Dashboard server commands and queries
+
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{Subcommand, ValueEnum};
+use universal_robot::dashboard::types::{FlightReport, OpMode, UserRole};
+use universal_robot::prelude::{Dashboard, UniversalRobot};
+
+use universal_robot::config::RobotConfig;
+
+#[derive(Subcommand)]
+pub enum DashboardCommand {
+    /// Power the arm on or off
+    Power { state: PowerState },
+    /// Release the brakes
+    BrakeRelease,
+    /// Load a program, relative to the programs directory or absolute
+    Load { program: String },
+    /// Load an installation, or reload the current one
+    LoadInstallation { installation: Option<String> },
+    /// Start the loaded program
+    Play,
+    /// Stop the running program
+    Stop,
+    /// Pause the running program
+    Pause,
+    /// Shut down the robot and controller
+    Shutdown,
+    /// Show a popup on the teach pendant, or close it with `--close`
+    Popup {
+        message: Option<String>,
+        #[arg(long, conflicts_with = "message")]
+        close: bool,
+    },
+    /// Add a message to the robot log
+    Log { message: String },
+    /// Save the robot log to disk
+    SaveLog,
+    /// Show the operational mode, or set it
+    OpMode { mode: Option<OperationalMode> },
+    /// Show the PolyScope user role, or set it
+    UserRole { role: Option<Role> },
+    /// Generate a flight report and print its id
+    FlightReport {
+        #[arg(default_value = "system")]
+        report: Report,
+        /// How long to wait for the report [s]
+        #[arg(long, default_value_t = 600.0)]
+        wait: f64,
+    },
+    /// Generate a support file in a directory on the robot and print its name
+    SupportFile {
+        directory: String,
+        /// How long to wait for the file [s]
+        #[arg(long, default_value_t = 600.0)]
+        wait: f64,
+    },
+    /// Identity, modes, safety and program of the robot
+    Status,
+    /// Robot mode
+    Mode,
+    /// Program state and loaded program
+    ProgramState,
+    /// Path of the loaded program
+    LoadedProgram,
+    /// Name of the loaded installation
+    Installation,
+    /// Is a program running
+    Running,
+    /// Is the loaded program saved
+    Saved,
+    /// Is the robot in remote control
+    Remote,
+    /// PolyScope version
+    Version,
+    /// Full PolyScope version string, with the build date
+    PolyscopeVersion,
+    /// Serial number
+    Serial,
+    /// Robot model
+    Model,
+}
+
+#[derive(Subcommand)]
+pub enum SafetyCommand {
+    /// Safety status
+    Status,
+    /// Safety mode, for controllers older than 5.4.0 without the safety status
+    Mode,
+    /// Unlock a protective stop
+    Unlock,
+    /// Restart the safety system after a fault or violation
+    Restart,
+    /// Close the safety popup
+    ClosePopup,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PowerState {
+    On,
+    Off,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OperationalMode {
+    Manual,
+    Automatic,
+    /// Stop controlling the operational mode from the dashboard server
+    None,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Role {
+    Programmer,
+    Operator,
+    None,
+    Locked,
+    Restricted,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Report {
+    Controller,
+    Software,
+    System,
+}
+
+pub fn run(command: DashboardCommand, target: &RobotConfig) -> Result<(), Box<dyn Error>> {
+    if let DashboardCommand::Status = command {
+        return status(target);
+    }
+    let mut dashboard =
+        Dashboard::connect(target.socket(target.ports.dashboard), Some(target.timeout))?;
+    let output = match command {
+        DashboardCommand::Power { state } => dashboard.power(matches!(state, PowerState::On))?,
+        DashboardCommand::BrakeRelease => dashboard.brake_release()?,
+        DashboardCommand::Load { program } => dashboard.load_program(&program)?,
+        DashboardCommand::LoadInstallation { installation } => {
+            dashboard.load_installation(installation.as_deref())?
+        }
+        DashboardCommand::Play => dashboard.play()?,
+        DashboardCommand::Stop => dashboard.stop()?,
+        DashboardCommand::Pause => dashboard.pause()?,
+        DashboardCommand::Shutdown => dashboard.shutdown()?,
+        DashboardCommand::Popup { close: true, .. } => dashboard.popup_close()?,
+        DashboardCommand::Popup { message, .. } => {
+            dashboard.popup_open(&message.ok_or("a message or --close is required")?)?
+        }
+        DashboardCommand::Log { message } => dashboard.log(&message)?,
+        DashboardCommand::SaveLog => dashboard.save_log()?,
+        DashboardCommand::OpMode { mode: None } => match dashboard.get_op_mode()? {
+            Some(mode) => format!("{mode:?}"),
+            None => "not controlled".to_owned(),
+        },
+        DashboardCommand::OpMode { mode: Some(mode) } => dashboard.set_op_mode(match mode {
+            OperationalMode::Manual => Some(OpMode::Manual),
+            OperationalMode::Automatic => Some(OpMode::Automatic),
+            OperationalMode::None => None,
+        })?,
+        DashboardCommand::UserRole { role: None } => format!("{:?}", dashboard.get_user_role()?),
+        DashboardCommand::UserRole { role: Some(role) } => dashboard.set_user_role(match role {
+            Role::Programmer => UserRole::Programmer,
+            Role::Operator => UserRole::Operator,
+            Role::None => UserRole::None,
+            Role::Locked => UserRole::Locked,
+            Role::Restricted => UserRole::Restricted,
+        })?,
+        DashboardCommand::FlightReport { report, wait } => dashboard.generate_flight_report(
+            match report {
+                Report::Controller => FlightReport::Controller,
+                Report::Software => FlightReport::Software,
+                Report::System => FlightReport::System,
+            },
+            Duration::from_secs_f64(wait),
+        )?,
+        DashboardCommand::SupportFile { directory, wait } => {
+            dashboard.generate_support_file(&directory, Duration::from_secs_f64(wait))?
+        }
+        DashboardCommand::Mode => format!("{:?}", dashboard.get_mode()?),
+        DashboardCommand::ProgramState => format!("{:?}", dashboard.get_program_state()?),
+        DashboardCommand::LoadedProgram => dashboard
+            .get_loaded_program()?
+            .map_or("none".to_owned(), |path| path.to_string()),
+        DashboardCommand::Installation => dashboard.get_loaded_installation()?,
+        DashboardCommand::Running => dashboard.is_running()?.to_string(),
+        DashboardCommand::Saved => match dashboard.is_saved()? {
+            (saved, Some(program)) => format!("{saved} {program}"),
+            (saved, None) => saved.to_string(),
+        },
+        DashboardCommand::Remote => dashboard.is_remote_mode()?.to_string(),
+        DashboardCommand::Version => dashboard.get_software_version()?.to_string(),
+        DashboardCommand::PolyscopeVersion => dashboard.get_version()?,
+        DashboardCommand::Serial => dashboard.get_serial()?,
+        DashboardCommand::Model => dashboard.get_model()?,
+        DashboardCommand::Status => unreachable!("handled above"),
+    };
+    println!("{}", output.trim_end());
+    dashboard.close()?;
+    Ok(())
+}
+
+pub fn run_safety(command: SafetyCommand, target: &RobotConfig) -> Result<(), Box<dyn Error>> {
+    let mut dashboard =
+        Dashboard::connect(target.socket(target.ports.dashboard), Some(target.timeout))?;
+    let output = match command {
+        SafetyCommand::Status => format!("{:?}", dashboard.safety_status()?),
+        SafetyCommand::Mode => format!("{:?}", dashboard.get_safety_mode()?),
+        SafetyCommand::Unlock => dashboard.safety_unlock_protective_stop()?,
+        SafetyCommand::Restart => dashboard.safety_restart()?,
+        SafetyCommand::ClosePopup => dashboard.safety_popup_close()?,
+    };
+    println!("{}", output.trim_end());
+    dashboard.close()?;
+    Ok(())
+}
+
+/// Print the robot's identity and state, one field per line
+fn status(target: &RobotConfig) -> Result<(), Box<dyn Error>> {
+    let mut ur = UniversalRobot::from_config(target)?;
+    let identity = ur.identity()?.clone();
+    let meta = ur.get_meta_data()?;
+    let state = ur.get_state()?;
+    let rows = [
+        ("model", format!("{:?}", identity.model)),
+        ("serial", identity.serial.unwrap_or_default()),
+        ("software", identity.software.to_string()),
+        ("control", identity.control.to_string()),
+        ("mode", format!("{:?}", meta.mode)),
+        ("safety", format!("{:?}", meta.safety_state)),
+        ("remote", meta.is_remote.to_string()),
+        (
+            "operational mode",
+            meta.operational_mode
+                .map_or("not controlled".to_owned(), |mode| format!("{mode:?}")),
+        ),
+        ("program", format!("{:?}", state.state)),
+        ("saved", meta.is_saved.to_string()),
+    ];
+    for (name, value) in rows {
+        println!("{name:<17} {value}");
+    }
+    ur.close()?;
+    Ok(())
+}
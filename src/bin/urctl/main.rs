@@ -0,0 +1,193 @@
+//! `urctl`: dashboard, RTDE and URScript operations from the command line.
+//!
+//! The robot address comes from `--address`, or from the config file, by default
+//! `~/.config/urctl.toml`. The file is a [`RobotConfig`], so it can also override ports, e.g. for
+//! a port-forwarded robot:
+//!
+//! ```toml
+//! address = "192.168.0.10"
+//! # read and write timeout [s]
+//! timeout = 5.0
+//!
+//! [ports]
+//! rtde = 40004
+//! ```
+//!
+//! Build with `cargo build --features cli --bin urctl`.
+
+mod dashboard;
+mod rtde;
+
+use std::error::Error;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use universal_robot::config::RobotConfig;
+use universal_robot::prelude::UniversalRobot;
+
+use dashboard::{DashboardCommand, SafetyCommand};
+use rtde::RtdeCommand;
+
+#[derive(Parser)]
+#[command(
+    name = "urctl",
+    version,
+    about = "Control Universal Robots from the command line"
+)]
+struct Cli {
+    /// Robot address, overrides the config file
+    #[arg(short, long, global = true)]
+    address: Option<IpAddr>,
+    /// Config file [default: ~/.config/urctl.toml]
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// Connection timeout [s]
+    #[arg(short, long, global = true)]
+    timeout: Option<f64>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(flatten)]
+    Dashboard(DashboardCommand),
+    /// Safety queries and recovery
+    #[command(subcommand)]
+    Safety(SafetyCommand),
+    /// Real-Time Data Exchange streams
+    #[command(subcommand)]
+    Rtde(RtdeCommand),
+    /// URScript programs
+    #[command(subcommand)]
+    Script(ScriptCommand),
+}
+
+#[derive(Subcommand)]
+enum ScriptCommand {
+    /// Send a script to the primary interface, which runs it immediately
+    Send {
+        /// Script file, or `-` for stdin
+        file: PathBuf,
+    },
+}
+
+/// Load the given config file, or the default one if it exists, with the command line overrides
+fn load_config(
+    path: Option<&PathBuf>,
+    address: Option<IpAddr>,
+    timeout: Option<f64>,
+) -> Result<RobotConfig, Box<dyn Error>> {
+    let (path, required) = match (path, std::env::var_os("HOME")) {
+        (Some(path), _) => (Some(path.clone()), true),
+        (None, Some(home)) => (Some(PathBuf::from(home).join(".config/urctl.toml")), false),
+        (None, None) => (None, false),
+    };
+    let contents = match path.as_ref().map(std::fs::read_to_string) {
+        None => String::new(),
+        Some(Err(error)) if error.kind() == std::io::ErrorKind::NotFound && !required => {
+            String::new()
+        }
+        Some(other) => other.map_err(|error| format!("{}: {error}", path_name(&path)))?,
+    };
+    parse_config(&contents, address, timeout)
+        .map_err(|error| format!("{}: {error}", path_name(&path)).into())
+}
+
+fn path_name(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map_or(String::from("config"), |path| path.display().to_string())
+}
+
+/// Parse a TOML config, with the command line overrides taking precedence over its fields
+fn parse_config(
+    contents: &str,
+    address: Option<IpAddr>,
+    timeout: Option<f64>,
+) -> Result<RobotConfig, Box<dyn Error>> {
+    let mut table: toml::Table = toml::from_str(contents)?;
+    if let Some(address) = address {
+        table.insert("address".into(), address.to_string().into());
+    }
+    if let Some(timeout) = timeout {
+        table.insert("timeout".into(), timeout.into());
+    }
+    if !table.contains_key("address") {
+        return Err("no robot address, pass --address or set it in the config file".into());
+    }
+    Ok(RobotConfig::from_toml(&table.to_string())?)
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let target = load_config(cli.config.as_ref(), cli.address, cli.timeout)?;
+    match cli.command {
+        Command::Dashboard(command) => dashboard::run(command, &target),
+        Command::Safety(command) => dashboard::run_safety(command, &target),
+        Command::Rtde(command) => rtde::run(command, &target),
+        Command::Script(ScriptCommand::Send { file }) => {
+            let mut script = String::new();
+            match file.to_str() {
+                Some("-") => {
+                    std::io::stdin().read_to_string(&mut script)?;
+                }
+                _ => script = std::fs::read_to_string(&file)?,
+            }
+            let mut ur = UniversalRobot::from_config(&target)?;
+            ur.send_script(&script)?;
+            ur.close()?;
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::parse_from([
+            "urctl",
+            "rtde",
+            "watch",
+            "timestamp",
+            "--json",
+            "-a",
+            "10.0.0.2",
+        ]);
+        assert_eq!(cli.address, Some("10.0.0.2".parse().unwrap()));
+        assert!(matches!(
+            cli.command,
+            Command::Rtde(RtdeCommand::Watch { json: true, .. })
+        ));
+    }
+    #[test]
+    fn test_config() {
+        let file = "address = \"192.168.0.10\"\ntimeout = 2.5\n[ports]\nrtde = 40004";
+        let config = parse_config(file, None, None).unwrap();
+        assert_eq!(config.address, "192.168.0.10".parse::<IpAddr>().unwrap());
+        assert_eq!(config.timeout.as_secs_f64(), 2.5);
+        assert_eq!(config.ports.rtde, 40004);
+        let config = parse_config(file, Some("10.0.0.2".parse().unwrap()), Some(1.0)).unwrap();
+        assert_eq!(config.address, "10.0.0.2".parse::<IpAddr>().unwrap());
+        assert_eq!(config.timeout.as_secs_f64(), 1.0);
+        assert!(parse_config("", None, None).is_err());
+        assert!(parse_config("host = \"robot\"", Some(config.address), None).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! RTDE streaming and recording
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::{Subcommand, ValueEnum};
+use universal_robot::data::{DataType, Value, Vec3, Vec6, DEFAULT_OUTPUTS};
+use universal_robot::recorder::{CsvFormat, RtdeRecorder};
+use universal_robot::types::Recipe;
+use universal_robot::Rtde;
+
+use universal_robot::config::RobotConfig;
+
+#[derive(Subcommand)]
+pub enum RtdeCommand {
+    /// Stream output variables as a table, or as JSON lines with `--json`
+    Watch {
+        /// Output variables, e.g. `timestamp actual_q`
+        #[arg(required = true)]
+        variables: Vec<String>,
+        /// Output frequency [Hz]
+        #[arg(short, long, default_value_t = 10.0)]
+        frequency: f64,
+        /// One JSON object per sample
+        #[arg(long)]
+        json: bool,
+        /// Stop after this many samples
+        #[arg(short = 'n', long)]
+        count: Option<u64>,
+    },
+    /// Record output variables to CSV
+    Record {
+        /// File to write, rotated files are numbered before the extension
+        file: PathBuf,
+        /// Output variables [default: the standard telemetry recipe]
+        variables: Vec<String>,
+        /// Output frequency [Hz]
+        #[arg(short, long, default_value_t = 125.0)]
+        frequency: f64,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: Format,
+        /// Stop after this long [s], otherwise record until interrupted with Ctrl-C
+        #[arg(short, long)]
+        duration: Option<f64>,
+        /// Start a new file once the current one reaches this many bytes
+        #[arg(long)]
+        rotate_size: Option<u64>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Comma separated
+    Csv,
+    /// Space separated, as written by Universal Robots' `record.py`
+    RecordPy,
+}
+
+pub fn run(command: RtdeCommand, target: &RobotConfig) -> Result<(), Box<dyn Error>> {
+    match command {
+        RtdeCommand::Watch {
+            variables,
+            frequency,
+            json,
+            count,
+        } => watch(target, &variables, frequency, json, count),
+        RtdeCommand::Record {
+            file,
+            variables,
+            frequency,
+            format,
+            duration,
+            rotate_size,
+        } => {
+            let variables = match variables.is_empty() {
+                true => DEFAULT_OUTPUTS
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+                false => variables,
+            };
+            let (mut rtde, recipe) = start(target, &variables, frequency)?;
+            let mut builder =
+                RtdeRecorder::builder(&file, &recipe, rtde.output_names()).format(match format {
+                    Format::Csv => CsvFormat::Csv,
+                    Format::RecordPy => CsvFormat::RecordPy,
+                });
+            if let Some(bytes) = rotate_size {
+                builder = builder.rotate_size(bytes);
+            }
+            if duration.is_none() {
+                stop_on_interrupt()?;
+            }
+            let mut recorder = builder.spawn()?;
+            let end = duration.map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+            while end.is_none_or(|end| Instant::now() < end) && !INTERRUPTED.load(Ordering::Relaxed)
+            {
+                recorder.record(&rtde.read()?);
+            }
+            rtde.pause()?;
+            let summary = recorder.finish()?;
+            eprintln!(
+                "recorded {} samples, dropped {}, to {}",
+                summary.samples,
+                summary.dropped,
+                summary
+                    .files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            rtde.close()?;
+            Ok(())
+        }
+    }
+}
+
+/// Set by Ctrl-C, so a recording stops and finishes its files rather than being cut off
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn stop_on_interrupt() -> Result<(), Box<dyn Error>> {
+    extern "C" fn interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    let previous =
+        unsafe { libc::signal(libc::SIGINT, interrupt as *const () as libc::sighandler_t) };
+    match previous {
+        libc::SIG_ERR => Err(std::io::Error::last_os_error().into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn stop_on_interrupt() -> Result<(), Box<dyn Error>> {
+    Err("--duration is required on this platform".into())
+}
+
+/// Set up the output recipe and start streaming it
+fn start(
+    target: &RobotConfig,
+    variables: &[String],
+    frequency: f64,
+) -> Result<(Rtde, Recipe), Box<dyn Error>> {
+    let mut rtde = Rtde::connect(target.socket(target.ports.rtde), Some(target.timeout))?;
+    let recipe: Vec<&str> = variables.iter().map(String::as_str).collect();
+    let setup = rtde.setup_output(&recipe, frequency)?;
+    let unknown: Vec<&str> = recipe
+        .iter()
+        .zip(setup.get_types())
+        .filter(|(_, var_type)| *var_type == DataType::NotFound)
+        .map(|(name, _)| *name)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!("output variables not found: {}", unknown.join(", ")).into());
+    }
+    rtde.start()?;
+    Ok((rtde, setup))
+}
+
+fn watch(
+    target: &RobotConfig,
+    variables: &[String],
+    frequency: f64,
+    json: bool,
+    count: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    const HEADER_EVERY: u64 = 25;
+    let (mut rtde, _) = start(target, variables, frequency)?;
+    let mut values = vec![Value::U8(0); variables.len()];
+    let widths: Vec<usize> = rtde
+        .output_types()
+        .iter()
+        .zip(variables)
+        .map(|(var_type, name)| name.len().max(cell(&placeholder(*var_type)).len()))
+        .collect();
+    let mut samples = 0;
+    while count.is_none_or(|count| samples < count) {
+        if !rtde.read_values_into(&mut values)? {
+            continue;
+        }
+        if json {
+            let fields: Vec<String> = variables
+                .iter()
+                .zip(&values)
+                .map(|(name, value)| {
+                    Ok(format!(
+                        "{}:{}",
+                        serde_json::to_string(name)?,
+                        serde_json::to_string(value)?
+                    ))
+                })
+                .collect::<Result<_, serde_json::Error>>()?;
+            println!("{{{}}}", fields.join(","));
+        } else {
+            if samples % HEADER_EVERY == 0 {
+                let header: Vec<String> = variables
+                    .iter()
+                    .zip(&widths)
+                    .map(|(name, width)| format!("{name:>width$}"))
+                    .collect();
+                println!("{}", header.join("  "));
+            }
+            let row: Vec<String> = values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{:>width$}", cell(value)))
+                .collect();
+            println!("{}", row.join("  "));
+        }
+        samples += 1;
+    }
+    rtde.pause()?;
+    rtde.close()?;
+    Ok(())
+}
+
+/// Value of the type with the widest formatting, to size the table's columns
+fn placeholder(var_type: DataType) -> Value {
+    match var_type {
+        DataType::Vec6 => Value::Vec6(Vec6::new(-1.0, -1.0, -1.0, -1.0, -1.0, -1.0)),
+        DataType::Vec3 => Value::Vec3(Vec3 {
+            x: -1.0,
+            y: -1.0,
+            z: -1.0,
+        }),
+        DataType::IVec6 | DataType::UVec6 => Value::IVec6([-1000; 6]),
+        _ => Value::F64(-1000.0),
+    }
+}
+
+/// Table cell for a value, floats to 4 decimals
+fn cell(value: &Value) -> String {
+    let floats = |values: &[f64]| {
+        let values: Vec<String> = values.iter().map(|value| format!("{value:.4}")).collect();
+        format!("[{}]", values.join(" "))
+    };
+    match *value {
+        Value::Vec6(v) => floats(&[v.x, v.y, v.z, v.rx, v.ry, v.rz]),
+        Value::Vec3(v) => floats(&[v.x, v.y, v.z]),
+        Value::IVec6(v) => format!("{v:?}"),
+        Value::UVec6(v) => format!("{v:?}"),
+        Value::F64(v) => format!("{v:.4}"),
+        Value::U64(v) => v.to_string(),
+        Value::U32(v) => v.to_string(),
+        Value::I32(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::U8(v) => v.to_string(),
+    }
+}
@@ -0,0 +1,199 @@
+//! `urgateway`: the dashboard server and RTDE outputs of one robot over HTTP and JSON.
+//!
+//! ```text
+//! GET  /health                      no token needed
+//! GET  /status                      identity, modes, safety and program
+//! GET  /interfaces                  connection status of each interface
+//! GET  /dashboard/<query>           e.g. mode, program-state, safety, version
+//! POST /dashboard/<command>         e.g. power-on, play, stop, {"program": ..} to load
+//! GET  /rtde/stream?variables=timestamp,actual_q&frequency=10
+//!                                   Server-Sent Events, one JSON object per sample
+//! ```
+//!
+//! Every other endpoint needs the token, as `Authorization: Bearer <token>` or as a `token` query
+//! parameter for clients such as `EventSource` that can't set headers. The token is given with
+//! `--token` or the `URGATEWAY_TOKEN` environment variable; without one, `--no-auth` has to be
+//! passed explicitly.
+//!
+//! Commands that power off, restart safety or shut the robot down are refused with 403, the
+//! gateway's dashboard handle is restricted to queries, motion and safety recovery, see
+//! [`CommandPolicy`].
+//!
+//! The robot is connected lazily, so the gateway starts before the robot is reachable. A request
+//! that finds an interface's connection dropped, e.g. by a robot restart, fails with 502 or 503
+//! and the next one reconnects it. Each stream opens its own RTDE connection.
+//!
+//! At most [`MAX_REQUESTS`] requests, streams included, are handled at once, each on its own
+//! thread. Requests beyond that are answered with 503.
+//!
+//! Build with `cargo build --features gateway --bin urgateway`.
+
+mod routes;
+mod stream;
+#[cfg(test)]
+mod test;
+
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use clap::Parser;
+use tiny_http::Server;
+use universal_robot::config::RobotConfig;
+use universal_robot::dashboard::policy::{CommandCategory, CommandPolicy};
+use universal_robot::prelude::UniversalRobot;
+use universal_robot::UniversalRobotBuilder;
+
+#[derive(Parser)]
+#[command(
+    name = "urgateway",
+    version,
+    about = "Serve a Universal Robot's dashboard and RTDE over HTTP"
+)]
+struct Cli {
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Robot address, overrides the config file
+    #[arg(short, long)]
+    address: Option<IpAddr>,
+    /// Robot config file, `.toml` or `.json`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Robot connection timeout [s]
+    #[arg(short, long)]
+    timeout: Option<f64>,
+    /// Token clients must present [default: $URGATEWAY_TOKEN]
+    #[arg(long)]
+    token: Option<String>,
+    /// Serve without a token, for a gateway only reachable from trusted hosts
+    #[arg(long, conflicts_with = "token")]
+    no_auth: bool,
+}
+
+/// The robot and settings shared by every request
+pub struct Gateway {
+    robot: Mutex<UniversalRobot>,
+    config: RobotConfig,
+    token: Option<String>,
+}
+
+impl Gateway {
+    /// Connect to the robot lazily, each interface on its first request
+    pub fn new(config: RobotConfig, token: Option<String>) -> Result<Self, universal_robot::Error> {
+        let mut robot = UniversalRobotBuilder::new(config.clone())
+            .primary(false)
+            .secondary(false)
+            .lazy(true)
+            .connect()?;
+        robot.dashboard.restrict(Self::policy())?;
+        Ok(Gateway {
+            robot: Mutex::new(robot),
+            config,
+            token,
+        })
+    }
+    /// Dashboard commands clients may send, everything but the destructive ones
+    fn policy() -> CommandPolicy {
+        CommandPolicy::new()
+            .allow(CommandCategory::Motion)
+            .allow(CommandCategory::Safety)
+    }
+    /// The robot, for one request at a time
+    fn robot(&self) -> MutexGuard<'_, UniversalRobot> {
+        // a request that panicked leaves the robot as usable as any failed command does
+        self.robot.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Requests handled at once
+pub const MAX_REQUESTS: usize = 32;
+
+/// A request being handled, counted until it is dropped
+struct InProgress(Arc<AtomicUsize>);
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handle requests until the server is unblocked, each on its own thread, at most
+/// [`MAX_REQUESTS`] at once
+pub fn serve(server: &Server, gateway: Arc<Gateway>) {
+    let active = Arc::new(AtomicUsize::new(0));
+    for request in server.incoming_requests() {
+        let already = active.fetch_add(1, Ordering::SeqCst);
+        let in_progress = InProgress(Arc::clone(&active));
+        if already >= MAX_REQUESTS {
+            drop(in_progress);
+            let busy = routes::ApiError::new(503, "too many requests in progress");
+            routes::respond(request, Err(busy));
+            continue;
+        }
+        let gateway = Arc::clone(&gateway);
+        let spawned = std::thread::Builder::new()
+            .name("urgateway-request".to_owned())
+            .spawn(move || {
+                routes::handle(request, &gateway);
+                drop(in_progress);
+            });
+        if let Err(error) = spawned {
+            log::error!("failed to spawn a request thread: {error}");
+        }
+    }
+}
+
+fn load_config(cli: &Cli) -> Result<RobotConfig, Box<dyn Error>> {
+    let mut config = match (&cli.config, cli.address) {
+        (Some(path), _) => {
+            RobotConfig::load(path).map_err(|error| format!("{}: {error}", path.display()))?
+        }
+        (None, Some(address)) => RobotConfig::new(address),
+        (None, None) => return Err("no robot address, pass --address or --config".into()),
+    };
+    if let Some(address) = cli.address {
+        config.address = address;
+    }
+    if let Some(timeout) = cli.timeout {
+        config.timeout = Duration::try_from_secs_f64(timeout)?;
+    }
+    Ok(config)
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let config = load_config(&cli)?;
+    let token = match cli.no_auth {
+        true => None,
+        false => cli
+            .token
+            .or_else(|| std::env::var("URGATEWAY_TOKEN").ok())
+            .filter(|token| !token.is_empty()),
+    };
+    if token.is_none() && !cli.no_auth {
+        return Err("no token, pass --token, set URGATEWAY_TOKEN or pass --no-auth".into());
+    }
+    let gateway = Arc::new(Gateway::new(config, token)?);
+    let server = Server::http(cli.listen)
+        .map_err(|error| format!("listening on {}: {error}", cli.listen))?;
+    eprintln!(
+        "serving {} on http://{}",
+        gateway.config.address, cli.listen
+    );
+    serve(&server, gateway);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Request routing, the token check and the REST endpoints
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+use universal_robot::dashboard::response::DashboardError;
+use universal_robot::prelude::{Dashboard, UniversalRobot};
+use universal_robot::{Error, Interface, InterfaceStatus};
+
+use crate::{stream, Gateway};
+
+/// A failed request, answered with its status and `{"error": message}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+    fn not_found(path: &str) -> Self {
+        ApiError::new(404, format!("no endpoint {path}"))
+    }
+}
+
+impl From<Error> for ApiError {
+    /// Robot errors as gateway errors, the robot is the upstream server
+    fn from(error: Error) -> Self {
+        let status = match &error {
+            Error::Dashboard(DashboardError::NotRemote(_) | DashboardError::WrongState(_)) => 409,
+            Error::Dashboard(DashboardError::FileNotFound(_)) => 404,
+            Error::Dashboard(DashboardError::Unsupported(_)) => 501,
+            Error::Policy(_) => 403,
+            Error::Unavailable(..) | Error::ConnectionLost => 503,
+            Error::Timeout(..) => 504,
+            _ => 502,
+        };
+        ApiError::new(status, error.to_string())
+    }
+}
+
+type Reply = Result<Value, ApiError>;
+
+/// Answer one request
+pub fn handle(mut request: Request, gateway: &Gateway) {
+    let (path, query) = split_url(request.url());
+    if path != "/health" && !authorized(&request, &query, gateway.token.as_deref()) {
+        return respond(request, Err(ApiError::new(401, "missing or wrong token")));
+    }
+    let method = request.method().clone();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let reply = match (method, segments.as_slice()) {
+        (Method::Get, ["health"]) => Ok(json!({"status": "ok"})),
+        (Method::Get, ["status"]) => status(&mut gateway.robot()),
+        (Method::Get, ["interfaces"]) => Ok(interfaces(&gateway.robot())),
+        (Method::Get, ["dashboard", name]) => dashboard_query(&mut gateway.robot().dashboard, name),
+        (Method::Post, ["dashboard", name]) => match body(&mut request) {
+            Ok(body) => dashboard_command(&mut gateway.robot().dashboard, name, &body),
+            Err(error) => Err(error),
+        },
+        (Method::Get, ["rtde", "stream"]) => {
+            return match stream::parameters(&query) {
+                Ok(parameters) => stream::serve(request, &gateway.config, parameters),
+                Err(error) => respond(request, Err(error)),
+            }
+        }
+        (Method::Get | Method::Post, _) => Err(ApiError::not_found(&path)),
+        (method, _) => Err(ApiError::new(405, format!("{method} is not supported"))),
+    };
+    respond(request, reply);
+}
+
+/// Send the reply as JSON, logging a client that went away
+pub fn respond(request: Request, reply: Reply) {
+    let (status, body) = match reply {
+        Ok(body) => (200, body),
+        Err(error) => (error.status, json!({"error": error.message})),
+    };
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(json_header());
+    if let Err(error) = request.respond(response) {
+        log::warn!("failed to send a response: {error}");
+    }
+}
+
+fn json_header() -> Header {
+    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("valid header")
+}
+
+/// Path and decoded query parameters of a request URL
+pub fn split_url(url: &str) -> (String, BTreeMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect();
+    (decode(path), query)
+}
+
+/// Undo percent encoding, and `+` for spaces in query strings
+fn decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => match rest
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(decoded) => {
+                    bytes.push(decoded);
+                    rest = &rest[2..];
+                }
+                None => bytes.push(b'%'),
+            },
+            other => bytes.push(other),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Does the request carry the token, as a bearer token or a query parameter
+fn authorized(request: &Request, query: &BTreeMap<String, String>, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let bearer = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "));
+    bearer
+        .or(query.get("token").map(String::as_str))
+        .is_some_and(|given| same(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compare without stopping at the first difference, so the time taken doesn't reveal how much
+/// of a guessed token is right
+fn same(given: &[u8], token: &[u8]) -> bool {
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// The request body as JSON, `{}` when empty
+fn body(request: &mut Request) -> Result<Value, ApiError> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|error| ApiError::new(400, format!("unreadable body: {error}")))?;
+    if body.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_str(&body)
+        .map_err(|error| ApiError::new(400, format!("invalid JSON: {error}")))
+}
+
+/// A string field of the request body
+fn field<'a>(body: &'a Value, name: &str) -> Result<&'a str, ApiError> {
+    body.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ApiError::new(400, format!("the body needs a \"{name}\" string")))
+}
+
+/// Identity, modes, safety and program, as `urctl status` shows them
+fn status(ur: &mut UniversalRobot) -> Reply {
+    let identity = ur.identity()?.clone();
+    let meta = ur.get_meta_data()?;
+    let state = ur.get_state()?;
+    Ok(json!({
+        "model": format!("{:?}", identity.model),
+        "serial": identity.serial,
+        "software": identity.software.to_string(),
+        "control": identity.control.to_string(),
+        "mode": format!("{:?}", meta.mode),
+        "safety": format!("{:?}", meta.safety_state),
+        "remote": meta.is_remote,
+        "operational_mode": meta.operational_mode.map(|mode| format!("{mode:?}")),
+        "program": format!("{:?}", state.state),
+        "saved": meta.is_saved,
+        "interfaces": interfaces(ur),
+    }))
+}
+
+fn interfaces(ur: &UniversalRobot) -> Value {
+    let statuses: serde_json::Map<String, Value> = Interface::ALL
+        .iter()
+        .map(|&interface| {
+            let status = match ur.status(interface) {
+                InterfaceStatus::Connected => "connected".to_owned(),
+                InterfaceStatus::Pending => "pending".to_owned(),
+                InterfaceStatus::Unavailable(reason) => format!("unavailable: {reason}"),
+                InterfaceStatus::Disabled => "disabled".to_owned(),
+            };
+            (interface.to_string(), Value::from(status))
+        })
+        .collect();
+    Value::Object(statuses)
+}
+
+/// `GET /dashboard/<name>`, answered with `{"value": ..}`
+fn dashboard_query(dashboard: &mut Dashboard, name: &str) -> Reply {
+    let value = match name {
+        "mode" => json!(format!("{:?}", dashboard.get_mode()?)),
+        "program-state" => json!(format!("{:?}", dashboard.get_program_state()?)),
+        "loaded-program" => json!(dashboard.get_loaded_program()?.map(|path| path.to_string())),
+        "installation" => json!(dashboard.get_loaded_installation()?),
+        "running" => json!(dashboard.is_running()?),
+        "saved" => {
+            let (saved, program) = dashboard.is_saved()?;
+            json!({"saved": saved, "program": program})
+        }
+        "remote" => json!(dashboard.is_remote_mode()?),
+        "version" => json!(dashboard.get_software_version()?.to_string()),
+        "serial" => json!(dashboard.get_serial()?.trim()),
+        "model" => json!(dashboard.get_model()?.trim()),
+        "safety" => json!(format!("{:?}", dashboard.safety_status()?)),
+        "op-mode" => json!(dashboard.get_op_mode()?.map(|mode| format!("{mode:?}"))),
+        "user-role" => json!(format!("{:?}", dashboard.get_user_role()?)),
+        _ => return Err(ApiError::not_found(&format!("/dashboard/{name}"))),
+    };
+    Ok(json!({ "value": value }))
+}
+
+/// `POST /dashboard/<name>`, answered with the dashboard server's `{"response": ..}`
+fn dashboard_command(dashboard: &mut Dashboard, name: &str, body: &Value) -> Reply {
+    let response = match name {
+        "power-on" => dashboard.power(true)?,
+        "power-off" => dashboard.power(false)?,
+        "brake-release" => dashboard.brake_release()?,
+        "load" => dashboard.load_program(field(body, "program")?)?,
+        "load-installation" => {
+            dashboard.load_installation(body.get("installation").and_then(Value::as_str))?
+        }
+        "play" => dashboard.play()?,
+        "stop" => dashboard.stop()?,
+        "pause" => dashboard.pause()?,
+        "shutdown" => dashboard.shutdown()?,
+        "popup" => dashboard.popup_open(field(body, "message")?)?,
+        "close-popup" => dashboard.popup_close()?,
+        "log" => dashboard.log(field(body, "message")?)?,
+        "save-log" => dashboard.save_log()?,
+        "unlock-protective-stop" => dashboard.safety_unlock_protective_stop()?,
+        "restart-safety" => dashboard.safety_restart()?,
+        "close-safety-popup" => dashboard.safety_popup_close()?,
+        _ => return Err(ApiError::not_found(&format!("/dashboard/{name}"))),
+    };
+    Ok(json!({ "response": response }))
+}
@@ -0,0 +1,129 @@
+//! RTDE outputs as Server-Sent Events
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde_json::Value as Json;
+use tiny_http::Request;
+use universal_robot::config::RobotConfig;
+use universal_robot::data::{DataType, Value};
+use universal_robot::Rtde;
+
+use crate::routes::{respond, ApiError};
+
+/// Query parameters of `GET /rtde/stream`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameters {
+    /// Output variables, from the comma separated `variables`
+    pub variables: Vec<String>,
+    /// Output frequency \[Hz\], 10 if not given
+    pub frequency: f64,
+    /// End the stream after this many samples, otherwise it runs until the client disconnects
+    pub count: Option<u64>,
+}
+
+pub fn parameters(query: &BTreeMap<String, String>) -> Result<Parameters, ApiError> {
+    let variables: Vec<String> = query
+        .get("variables")
+        .map(|variables| {
+            variables
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    if variables.is_empty() {
+        return Err(ApiError::new(
+            400,
+            "variables is required, e.g. ?variables=timestamp",
+        ));
+    }
+    let frequency = match query.get("frequency") {
+        Some(frequency) => frequency
+            .parse()
+            .ok()
+            .filter(|frequency: &f64| *frequency > 0.0 && *frequency <= 500.0)
+            .ok_or_else(|| ApiError::new(400, "frequency must be between 0 and 500 Hz"))?,
+        None => 10.0,
+    };
+    let count = match query.get("count") {
+        Some(count) => Some(
+            count
+                .parse()
+                .map_err(|_| ApiError::new(400, "count must be a whole number"))?,
+        ),
+        None => None,
+    };
+    Ok(Parameters {
+        variables,
+        frequency,
+        count,
+    })
+}
+
+/// Stream samples on a connection of its own until the client goes away or `count` is reached
+pub fn serve(request: Request, config: &RobotConfig, parameters: Parameters) {
+    let mut rtde = match start(config, &parameters) {
+        Ok(rtde) => rtde,
+        Err(error) => return respond(request, Err(error)),
+    };
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/event-stream\r\n\
+                Cache-Control: no-cache\r\n\
+                Connection: close\r\n\r\n";
+    let mut sent = writer
+        .write_all(head.as_bytes())
+        .and_then(|_| writer.flush());
+    let mut values = vec![Value::U8(0); parameters.variables.len()];
+    let mut samples = 0;
+    while sent.is_ok() && parameters.count.is_none_or(|count| samples < count) {
+        match rtde.read_values_into(&mut values) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(error) => {
+                // the headers are out, so the error goes to the client as an event
+                let event = serde_json::json!({"error": error.to_string()});
+                let _ = write!(writer, "event: error\ndata: {event}\n\n");
+                break;
+            }
+        }
+        let sample: serde_json::Map<String, Json> = parameters
+            .variables
+            .iter()
+            .zip(&values)
+            .map(|(name, value)| {
+                let value = serde_json::to_value(value).unwrap_or(Json::Null);
+                (name.clone(), value)
+            })
+            .collect();
+        sent = write!(writer, "data: {}\n\n", Json::Object(sample)).and_then(|_| writer.flush());
+        samples += 1;
+    }
+    if let Err(error) = rtde.pause().and_then(|_| rtde.close().map(|_| ())) {
+        log::debug!("closing a stream's RTDE connection: {error}");
+    }
+}
+
+/// Connect, set up the output recipe and start it
+fn start(config: &RobotConfig, parameters: &Parameters) -> Result<Rtde, ApiError> {
+    let mut rtde = Rtde::connect(config.socket(config.ports.rtde), Some(config.timeout))?;
+    let recipe: Vec<&str> = parameters.variables.iter().map(String::as_str).collect();
+    let setup = rtde.setup_output(&recipe, parameters.frequency)?;
+    let unknown: Vec<&str> = recipe
+        .iter()
+        .zip(setup.get_types())
+        .filter(|(_, var_type)| *var_type == DataType::NotFound)
+        .map(|(name, _)| *name)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(ApiError::new(
+            400,
+            format!("output variables not found: {}", unknown.join(", ")),
+        ));
+    }
+    rtde.start()?;
+    Ok(rtde)
+}
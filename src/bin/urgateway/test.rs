@@ -0,0 +1,347 @@
+//! The gateway against mock dashboard and RTDE servers on local ports
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tiny_http::Server;
+use universal_robot::config::RobotConfig;
+
+use crate::{serve, Gateway};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const TOKEN: &str = "s3cret/token";
+
+/// Reply of the mock dashboard server to a command
+fn dashboard_reply(command: &str) -> String {
+    let reply = match command {
+        "version" => "5.12.2.1101534",
+        "get serial number" => "20235500123",
+        "get robot model" => "UR5",
+        "isProgramSaved" => "true pick.urp",
+        "robotmode" => "Robotmode: IDLE",
+        "is in remote control" => "true",
+        "get operational mode" => "NONE",
+        "safetystatus" => "Safetystatus: NORMAL",
+        "programState" => "STOPPED <unnamed>",
+        "stop" => "Stopped",
+        "power on" => "Powering on",
+        "quit" => "Disconnected",
+        _ if command.starts_with("addToLog ") => "Added log message",
+        _ if command.starts_with("Load ") => {
+            return format!("File not found: {}", &command[5..]);
+        }
+        _ => return format!("could not understand: '{command}'"),
+    };
+    reply.to_owned()
+}
+
+/// Dashboard server answering each connection line by line
+fn mock_dashboard() -> u16 {
+    let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            thread::spawn(move || dashboard_connection(stream, usize::MAX));
+        }
+    });
+    port
+}
+
+/// Greet and answer up to `replies` commands, or until the client disconnects
+fn dashboard_connection(mut stream: TcpStream, replies: usize) {
+    writeln!(stream, "Connected: Universal Robots Dashboard Server").unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    for line in reader.lines().take(replies) {
+        let Ok(command) = line else { break };
+        if writeln!(stream, "{}", dashboard_reply(command.trim())).is_err() {
+            break;
+        }
+    }
+}
+
+fn package(package_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (3 + payload.len() as u16).to_be_bytes().to_vec();
+    bytes.push(package_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// RTDE server knowing `timestamp` and `actual_q`, streaming every 10 ms once started
+fn mock_rtde() -> u16 {
+    let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            thread::spawn(move || rtde_connection(stream));
+        }
+    });
+    port
+}
+
+fn rtde_connection(mut stream: TcpStream) {
+    let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+    let streaming = Arc::new(AtomicBool::new(false));
+    let mut outputs: Vec<String> = Vec::new();
+    loop {
+        let mut header = [0u8; 3];
+        if stream.read_exact(&mut header).is_err() {
+            break;
+        }
+        let size = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let mut request = vec![0; size - 3];
+        stream.read_exact(&mut request).unwrap();
+        let reply = match header[2] {
+            b'v' => [5u32, 12, 2, 1101534]
+                .iter()
+                .flat_map(|part| part.to_be_bytes())
+                .collect(),
+            b'O' => {
+                outputs = String::from_utf8_lossy(&request[8..])
+                    .trim()
+                    .split(',')
+                    .map(str::to_owned)
+                    .collect();
+                let types: Vec<&str> = outputs
+                    .iter()
+                    .map(|name| match name.as_str() {
+                        "timestamp" => "DOUBLE",
+                        "actual_q" => "VECTOR6D",
+                        _ => "NOT_FOUND",
+                    })
+                    .collect();
+                [&[1], types.join(",").as_bytes()].concat()
+            }
+            b'S' => {
+                streaming.store(true, Ordering::SeqCst);
+                let (writer, streaming, outputs) =
+                    (writer.clone(), streaming.clone(), outputs.clone());
+                thread::spawn(move || {
+                    for cycle in 1.. {
+                        if !streaming.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        let mut sample = vec![1u8];
+                        for name in &outputs {
+                            let count = if name == "actual_q" { 6 } else { 1 };
+                            let value = cycle as f64 * 0.01;
+                            sample.extend((0..count).flat_map(|_| value.to_be_bytes()));
+                        }
+                        let data = package(b'U', &sample);
+                        if writer.lock().unwrap().write_all(&data).is_err() {
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                });
+                vec![1]
+            }
+            b'P' => {
+                streaming.store(false, Ordering::SeqCst);
+                vec![1]
+            }
+            _ => vec![1],
+        };
+        let reply = package(header[2], &reply);
+        if writer.lock().unwrap().write_all(&reply).is_err() {
+            break;
+        }
+    }
+    streaming.store(false, Ordering::SeqCst);
+}
+
+/// A gateway for the mock robot, with the address it listens on
+fn gateway(token: Option<&str>) -> SocketAddr {
+    gateway_on(mock_dashboard(), token)
+}
+
+/// A gateway for a robot with its dashboard server on this port
+fn gateway_on(dashboard: u16, token: Option<&str>) -> SocketAddr {
+    let mut config = RobotConfig::new(LOCALHOST).timeout(Duration::from_secs(2));
+    config.ports.dashboard = dashboard;
+    config.ports.rtde = mock_rtde();
+    let gateway = Arc::new(Gateway::new(config, token.map(str::to_owned)).unwrap());
+    let server = Server::http((LOCALHOST, 0)).unwrap();
+    let address = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || serve(&server, gateway));
+    address
+}
+
+/// Send a request and read the whole response
+fn send(address: SocketAddr, method: &str, path: &str, token: Option<&str>, body: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let authorization = token.map_or(String::new(), |token| {
+        format!("Authorization: Bearer {token}\r\n")
+    });
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Status and JSON body of a request
+fn request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, Value) {
+    let response = send(address, method, path, token, body);
+    let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_token() {
+    let gateway = gateway(Some(TOKEN));
+    let (status, body) = request(gateway, "GET", "/health", None, "");
+    assert_eq!((status, body), (200, json!({"status": "ok"})));
+    assert_eq!(request(gateway, "GET", "/dashboard/mode", None, "").0, 401);
+    assert_eq!(
+        request(gateway, "GET", "/dashboard/mode", Some("s3cret"), "").0,
+        401
+    );
+    let (status, body) = request(gateway, "GET", "/dashboard/mode", Some(TOKEN), "");
+    assert_eq!((status, body), (200, json!({"value": "Idle"})));
+    // as a query parameter, percent encoded
+    let path = "/dashboard/mode?token=s3cret%2Ftoken";
+    assert_eq!(request(gateway, "GET", path, None, "").0, 200);
+}
+
+#[test]
+fn test_dashboard() {
+    let gateway = gateway(None);
+    let (status, body) = request(gateway, "POST", "/dashboard/stop", None, "");
+    assert_eq!((status, body), (200, json!({"response": "Stopped"})));
+    let (status, body) = request(
+        gateway,
+        "POST",
+        "/dashboard/log",
+        None,
+        r#"{"message": "from the gateway"}"#,
+    );
+    assert_eq!(
+        (status, body),
+        (200, json!({"response": "Added log message"}))
+    );
+    assert_eq!(request(gateway, "POST", "/dashboard/log", None, "").0, 400);
+    assert_eq!(request(gateway, "POST", "/dashboard/log", None, "{").0, 400);
+    let load = r#"{"program": "missing"}"#;
+    let (status, body) = request(gateway, "POST", "/dashboard/load", None, load);
+    assert_eq!(status, 404);
+    assert!(body["error"].as_str().unwrap().contains("missing.urp"));
+    assert_eq!(
+        request(gateway, "GET", "/dashboard/launch", None, "").0,
+        404
+    );
+    assert_eq!(request(gateway, "DELETE", "/status", None, "").0, 405);
+    let (status, body) = request(gateway, "GET", "/dashboard/running", None, "");
+    assert_eq!(status, 501, "{body}");
+    // destructive commands are refused by the gateway's policy
+    for command in ["shutdown", "power-off", "restart-safety"] {
+        let path = format!("/dashboard/{command}");
+        assert_eq!(
+            request(gateway, "POST", &path, None, "").0,
+            403,
+            "{command}"
+        );
+    }
+}
+
+#[test]
+fn test_dashboard_restart() {
+    let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // the greeting's log message and one query, then the robot restarts
+    let robot = thread::spawn(move || dashboard_connection(listener.accept().unwrap().0, 2));
+    let gateway = gateway_on(port, None);
+    let (status, body) = request(gateway, "GET", "/dashboard/mode", None, "");
+    assert_eq!((status, body), (200, json!({"value": "Idle"})));
+    robot.join().unwrap();
+    let listener = TcpListener::bind((LOCALHOST, port)).unwrap();
+    thread::spawn(move || dashboard_connection(listener.accept().unwrap().0, usize::MAX));
+    // the request on the dropped connection fails, the next one reconnects
+    let (status, body) = request(gateway, "GET", "/dashboard/mode", None, "");
+    assert!(matches!(status, 502 | 503), "{status} {body}");
+    let (status, body) = request(gateway, "GET", "/interfaces", None, "");
+    assert_eq!(status, 200);
+    assert!(body["dashboard"]
+        .as_str()
+        .unwrap()
+        .starts_with("unavailable"));
+    let (status, body) = request(gateway, "GET", "/dashboard/mode", None, "");
+    assert_eq!((status, body), (200, json!({"value": "Idle"})));
+}
+
+#[test]
+fn test_status() {
+    let gateway = gateway(Some(TOKEN));
+    let (status, body) = request(gateway, "GET", "/interfaces", Some(TOKEN), "");
+    assert_eq!(status, 200);
+    assert_eq!(body["dashboard"], "pending");
+    assert_eq!(body["primary"], "disabled");
+    let (status, body) = request(gateway, "GET", "/status", Some(TOKEN), "");
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["serial"], "20235500123");
+    assert_eq!(body["software"], "5.12.2.1101534");
+    assert_eq!(body["mode"], "Idle");
+    assert_eq!(body["safety"], "Normal");
+    assert_eq!(body["remote"], true);
+    assert_eq!(body["program"], "Stopped(None)");
+    assert_eq!(body["interfaces"]["dashboard"], "connected");
+    assert_eq!(body["interfaces"]["RTDE"], "connected");
+}
+
+#[test]
+fn test_stream() {
+    let gateway = gateway(Some(TOKEN));
+    let mut stream = TcpStream::connect(gateway).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET /rtde/stream?variables=timestamp,actual_q&frequency=100&count=3&token=s3cret%2Ftoken \
+         HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    )
+    .unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "HTTP/1.1 200 OK");
+    let events: Vec<Value> = lines
+        .map_while(|line| line.ok())
+        .filter_map(|line| {
+            line.strip_prefix("data: ")
+                .map(|data| serde_json::from_str(data).unwrap())
+        })
+        .take(3)
+        .collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["timestamp"], 0.01);
+    assert_eq!(events[2]["actual_q"]["x"], 0.03);
+
+    let path = "/rtde/stream?variables=timestamp,speed";
+    let (status, body) = request(gateway, "GET", path, Some(TOKEN), "");
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "output variables not found: speed");
+    assert_eq!(
+        request(gateway, "GET", "/rtde/stream", Some(TOKEN), "").0,
+        400
+    );
+}
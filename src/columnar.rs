@@ -0,0 +1,446 @@
+//! Compact binary recording of RTDE samples
+//!
+//! The file starts with a schema block listing each variable's name and type code, followed
+//! by fixed-size records holding the data package payloads as received, big-endian and without
+//! the recipe id. Records are never rewritten, so a run interrupted mid-write loses at most the
+//! last partial record, which the reader ignores.
+//!
+//! ```ignore
+//! let recipe = ur.start_telemetry(&DEFAULT_OUTPUTS, 500.0)?;
+//! let mut log = ColumnarWriter::create("run.urlog", ur.rtde.output_names(), &recipe.get_types())?;
+//! loop {
+//!     log.append(&ur.rtde.read()?)?;
+//! }
+//!
+//! let log = ColumnarLog::open("run.urlog")?;
+//! let range = log.time_range(10.0, 20.0)?;
+//! let q = log.column("actual_q").unwrap().slice(range);
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::data::{DataType, Value};
+use crate::prelude::*;
+use crate::rtde::{as_bytes, from_bytes};
+use crate::types::Payload;
+
+/// File signature and format version
+const MAGIC: &[u8; 8] = b"URLOG\0\0\x01";
+
+/// Type code of each [`DataType`] in the schema block.
+///
+/// Part of the file format, so codes are never reused or renumbered when types are added.
+const TYPE_CODES: [(DataType, u16); 10] = [
+    (DataType::Vec6, 0),
+    (DataType::Vec3, 1),
+    (DataType::IVec6, 2),
+    (DataType::UVec6, 3),
+    (DataType::F64, 4),
+    (DataType::U64, 5),
+    (DataType::U32, 6),
+    (DataType::I32, 7),
+    (DataType::Bool, 8),
+    (DataType::U8, 9),
+];
+
+fn type_code(var_type: DataType) -> Result<u16> {
+    TYPE_CODES
+        .iter()
+        .find(|(known, _)| *known == var_type)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| Error::Serialization(format!("{var_type:?} has no type code")))
+}
+
+fn from_type_code(code: u16) -> Result<DataType> {
+    TYPE_CODES
+        .iter()
+        .find(|(_, known)| *known == code)
+        .map(|(var_type, _)| *var_type)
+        .ok_or_else(|| Error::Deserialization(format!("unknown type code {code}")))
+}
+
+/// Name and type of each variable in a record, in recipe order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub names: Vec<String>,
+    pub types: Vec<DataType>,
+}
+
+impl Schema {
+    pub fn new<S: AsRef<str>>(names: &[S], types: &[DataType]) -> Result<Self> {
+        if names.len() != types.len() {
+            return Err(Error::Static("recipe and variable names differ in length"));
+        }
+        if types.contains(&DataType::NotFound) {
+            return Err(Error::Static("cannot record a variable that was not found"));
+        }
+        Ok(Schema {
+            names: names.iter().map(|name| name.as_ref().to_owned()).collect(),
+            types: types.to_vec(),
+        })
+    }
+    /// Bytes in each record
+    pub fn record_size(&self) -> usize {
+        self.types.iter().map(DataType::size).sum()
+    }
+    /// Byte offset of each variable within a record
+    fn offsets(&self) -> Vec<usize> {
+        self.types
+            .iter()
+            .scan(0, |offset, var_type| {
+                let current = *offset;
+                *offset += var_type.size();
+                Some(current)
+            })
+            .collect()
+    }
+    /// Schema block: column count, then each column's type and name
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = as_bytes(self.types.len() as u16)?;
+        for (name, var_type) in self.names.iter().zip(&self.types) {
+            bytes.extend(as_bytes(type_code(*var_type)?)?);
+            bytes.extend(as_bytes(name.len() as u16)?);
+            bytes.extend(name.as_bytes());
+        }
+        Ok(bytes)
+    }
+    /// Parse a schema block, returning it with its length in bytes
+    fn from_bytes(buf: &[u8]) -> Result<(Self, usize)> {
+        let truncated = || Error::Deserialization("truncated schema".to_owned());
+        let count: u16 = from_bytes(buf.get(..2).ok_or_else(truncated)?)?;
+        let mut offset = 2;
+        let mut schema = Schema {
+            names: Vec::new(),
+            types: Vec::new(),
+        };
+        for _ in 0..count {
+            let prefix = buf.get(offset..offset + 4).ok_or_else(truncated)?;
+            let (code, len): (u16, u16) = from_bytes(prefix)?;
+            let var_type = from_type_code(code)?;
+            offset += 4;
+            let name = buf
+                .get(offset..offset + len as usize)
+                .ok_or_else(truncated)?;
+            schema
+                .names
+                .push(String::from_utf8_lossy(name).into_owned());
+            schema.types.push(var_type);
+            offset += len as usize;
+        }
+        Ok((schema, offset))
+    }
+}
+
+/// Appends RTDE samples to a columnar log file
+pub struct ColumnarWriter {
+    file: BufWriter<File>,
+    record_size: usize,
+    records: u64,
+}
+
+impl ColumnarWriter {
+    /// Create the file and write the schema for the output recipe.
+    ///
+    /// Fails if the file exists, so a log that is open for reading is never truncated.
+    pub fn create<P: AsRef<Path>, S: AsRef<str>>(
+        path: P,
+        names: &[S],
+        types: &[DataType],
+    ) -> Result<Self> {
+        let schema = Schema::new(names, types)?;
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(MAGIC)?;
+        file.write_all(&schema.to_bytes()?)?;
+        Ok(ColumnarWriter {
+            file,
+            record_size: schema.record_size(),
+            records: 0,
+        })
+    }
+    /// Append a data package, other package types are ignored
+    pub fn append(&mut self, package: &Payload<Vec<u8>>) -> Result<()> {
+        if !package.is_data() {
+            return Ok(());
+        }
+        // skip the recipe id
+        self.append_raw(package.payload.get(1..).unwrap_or_default())
+    }
+    /// Append a record that is already in the wire format
+    pub fn append_raw(&mut self, record: &[u8]) -> Result<()> {
+        if record.len() != self.record_size {
+            return Err(Error::Serialization(format!(
+                "record of {} bytes does not match the schema's {}",
+                record.len(),
+                self.record_size
+            )));
+        }
+        self.file.write_all(record)?;
+        self.records += 1;
+        Ok(())
+    }
+    /// Records appended so far
+    pub fn len(&self) -> u64 {
+        self.records
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Memory-mapped reader for a columnar log file
+///
+/// The file is mapped rather than read, so it must not be truncated or rewritten while the log
+/// is open. [`ColumnarWriter`] only ever appends to a file it created, appended records are
+/// past the mapped length and not seen until the log is opened again.
+pub struct ColumnarLog {
+    map: Mmap,
+    schema: Schema,
+    offsets: Vec<usize>,
+    data_start: usize,
+    record_size: usize,
+    len: usize,
+}
+
+impl ColumnarLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: mapped bytes must not change while mapped. The writer never truncates or
+        // rewrites a file, only appends past the mapped length; other writers are the caller's
+        // responsibility, see the type's docs.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.get(..MAGIC.len()) != Some(MAGIC) {
+            return Err(Error::Deserialization("not a columnar log".to_owned()));
+        }
+        let (schema, schema_len) = Schema::from_bytes(&map[MAGIC.len()..])?;
+        let data_start = MAGIC.len() + schema_len;
+        let record_size = schema.record_size();
+        let len = match record_size {
+            0 => 0,
+            size => (map.len() - data_start) / size,
+        };
+        Ok(ColumnarLog {
+            offsets: schema.offsets(),
+            map,
+            schema,
+            data_start,
+            record_size,
+            len,
+        })
+    }
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+    /// Number of complete records
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Raw bytes of a record
+    fn record_bytes(&self, index: usize) -> &[u8] {
+        let start = self.data_start + index * self.record_size;
+        &self.map[start..start + self.record_size]
+    }
+    /// Every value of a record, in schema order
+    pub fn record(&self, index: usize) -> Result<Vec<Value>> {
+        if index >= self.len {
+            return Err(Error::Static("record index out of range"));
+        }
+        let bytes = self.record_bytes(index);
+        self.schema
+            .types
+            .iter()
+            .zip(&self.offsets)
+            .map(|(var_type, offset)| var_type.decode(&bytes[*offset..]))
+            .collect()
+    }
+    /// All values of a variable
+    pub fn column(&self, name: &str) -> Option<Column<'_>> {
+        let index = self.schema.names.iter().position(|column| column == name)?;
+        Some(Column {
+            log: self,
+            offset: self.offsets[index],
+            var_type: self.schema.types[index],
+            range: 0..self.len,
+        })
+    }
+    /// Records with a `timestamp` from `start` up to but excluding `end` \[s\].
+    ///
+    /// Timestamps are controller uptime and increase with every record, so this is a binary search.
+    pub fn time_range(&self, start: f64, end: f64) -> Result<Range<usize>> {
+        let timestamps = self
+            .column("timestamp")
+            .filter(|column| column.var_type == DataType::F64)
+            .ok_or(Error::Static("log has no timestamp column"))?;
+        let first = timestamps.partition_point(|time| time < start);
+        let last = timestamps.partition_point(|time| time < end);
+        Ok(first..last.max(first))
+    }
+}
+
+/// View of one variable over a range of records
+#[derive(Clone)]
+pub struct Column<'a> {
+    log: &'a ColumnarLog,
+    offset: usize,
+    var_type: DataType,
+    range: Range<usize>,
+}
+
+impl<'a> Column<'a> {
+    pub fn data_type(&self) -> DataType {
+        self.var_type
+    }
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+    /// Narrow to a range of indices within this column
+    pub fn slice(&self, range: Range<usize>) -> Column<'a> {
+        let start = (self.range.start + range.start).min(self.range.end);
+        let end = (self.range.start + range.end).clamp(start, self.range.end);
+        Column {
+            range: start..end,
+            ..self.clone()
+        }
+    }
+    fn bytes(&self, index: usize) -> &'a [u8] {
+        &self.log.record_bytes(self.range.start + index)[self.offset..]
+    }
+    pub fn get(&self, index: usize) -> Option<Value> {
+        if index >= self.len() {
+            return None;
+        }
+        self.var_type.decode(self.bytes(index)).ok()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Value> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+    /// Scalar values as floats, empty for vector columns
+    pub fn to_f64(&self) -> Vec<f64> {
+        match self.var_type {
+            DataType::F64 => (0..self.len()).map(|index| self.f64_at(index)).collect(),
+            _ => self.iter().filter_map(|value| value.as_f64()).collect(),
+        }
+    }
+    /// Read a double without going through the decoder
+    fn f64_at(&self, index: usize) -> f64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.bytes(index)[..8]);
+        f64::from_be_bytes(bytes)
+    }
+    /// First index where the predicate is false, for a double column ordered by it
+    fn partition_point<F: Fn(f64) -> bool>(&self, predicate: F) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if predicate(self.f64_at(mid)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Vec6;
+    use crate::test::temp_path;
+    use crate::types::PackageType;
+
+    fn write_log(path: &Path, samples: usize) {
+        let mut writer = ColumnarWriter::create(
+            path,
+            &["timestamp", "actual_q", "robot_mode"],
+            &[DataType::F64, DataType::Vec6, DataType::I32],
+        )
+        .unwrap();
+        for index in 0..samples {
+            let q = Vec6::new(index as f64, 0.0, 0.0, 0.0, 0.0, -1.0);
+            let bytes = as_bytes((1u8, index as f64 * 0.002, q, 7i32)).unwrap();
+            let package = Payload::new(PackageType::Data, bytes, None).unwrap();
+            writer.append(&package).unwrap();
+        }
+        assert_eq!(writer.len(), samples as u64);
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round_trip.urlog");
+        write_log(&path, 100);
+        let log = ColumnarLog::open(&path).unwrap();
+        assert_eq!(log.len(), 100);
+        assert_eq!(log.schema().names, ["timestamp", "actual_q", "robot_mode"]);
+        let record = log.record(42).unwrap();
+        assert_eq!(record[0], Value::F64(42.0 * 0.002));
+        assert_eq!(record[2], Value::I32(7));
+        let q = log.column("actual_q").unwrap();
+        assert_eq!(q.get(99).and_then(|value| value.as_vec6()).unwrap().x, 99.0);
+        assert!(log.column("missing").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_time_range_and_slice() {
+        let path = temp_path("time_range.urlog");
+        write_log(&path, 1000);
+        let log = ColumnarLog::open(&path).unwrap();
+        // 0.002 s per record
+        let range = log.time_range(0.1, 0.2).unwrap();
+        assert_eq!(range, 50..100);
+        let times = log.column("timestamp").unwrap().slice(range).to_f64();
+        assert_eq!(times.len(), 50);
+        assert_eq!(times[0], 0.1);
+        assert_eq!(log.time_range(5.0, 6.0).unwrap(), 1000..1000);
+        let modes = log.column("robot_mode").unwrap().slice(990..2000);
+        assert_eq!(modes.to_f64(), vec![7.0; 10]);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_schema_type_codes() {
+        let schema = Schema::new(&["q", "mode"], &[DataType::Vec6, DataType::I32]).unwrap();
+        let bytes = schema.to_bytes().unwrap();
+        assert_eq!(&bytes[2..4], &[0, 0]);
+        assert_eq!(Schema::from_bytes(&bytes).unwrap(), (schema, bytes.len()));
+        let mut unknown = bytes.clone();
+        unknown[3] = 99;
+        assert!(Schema::from_bytes(&unknown).is_err());
+    }
+    #[test]
+    fn test_existing_file_kept() {
+        let path = temp_path("existing.urlog");
+        write_log(&path, 10);
+        assert!(ColumnarWriter::create(&path, &["timestamp"], &[DataType::F64]).is_err());
+        assert_eq!(ColumnarLog::open(&path).unwrap().len(), 10);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_partial_record_ignored() {
+        let path = temp_path("partial.urlog");
+        write_log(&path, 10);
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[0u8; 11]).unwrap();
+        let log = ColumnarLog::open(&path).unwrap();
+        assert_eq!(log.len(), 10);
+        std::fs::remove_file(path).unwrap();
+    }
+}
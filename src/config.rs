@@ -0,0 +1,335 @@
+//! Robot and cell configuration
+//!
+//! Everything [`UniversalRobot::from_config`] needs to connect to a robot and prepare a session,
+//! loaded from TOML or JSON. Only the address is required, so a config can be as small as
+//! `address = "192.168.0.10"`. Overriding the ports lets several port-forwarded robots or
+//! simulators share one host:
+//!
+//! ```toml
+//! address = "127.0.0.1"
+//! # read and write timeout [s]
+//! timeout = 5.0
+//! # programs directory on the controller, found when the first program is loaded if not set
+//! programs_root = "/programs"
+//!
+//! [ports]
+//! dashboard = 39999
+//! primary = 40001
+//! secondary = 40002
+//! rtde = 40004
+//!
+//! [interfaces]
+//! secondary = false
+//! # connect each interface on first use
+//! lazy = true
+//!
+//! [rtde]
+//! frequency = 125.0
+//! # recipe set up and started on connect
+//! output = "health"
+//! recipes.health = ["timestamp", "actual_current", "joint_temperatures"]
+//!
+//! [tool]
+//! tcp = [0.0, 0.0, 0.15, 0.0, 0.0, 0.0]
+//! payload = { mass = 1.2, center_of_gravity = [0.0, 0.0, 0.05] }
+//! ```
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::Vec6;
+use crate::motion::ToolPayload;
+use crate::physical::Endpoint;
+use crate::prelude::*;
+
+/// Connection and session settings for one robot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RobotConfig {
+    pub address: IpAddr,
+    #[serde(default)]
+    pub ports: Ports,
+    /// Read and write timeout of every interface \[s\]
+    #[serde(default = "default_timeout", with = "seconds")]
+    pub timeout: Duration,
+    /// Time allowed to open each connection \[s\], the operating system's default if not set
+    #[serde(default, with = "optional_seconds")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default)]
+    pub interfaces: Interfaces,
+    #[serde(default)]
+    pub rtde: RtdeConfig,
+    #[serde(default)]
+    pub tool: ToolConfig,
+    /// Programs directory on the controller, found when the first program is loaded if not set,
+    /// see [`Dashboard::set_programs_root`]
+    #[serde(default)]
+    pub programs_root: Option<String>,
+}
+
+/// TCP port of each interface
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ports {
+    pub dashboard: u16,
+    pub primary: u16,
+    pub secondary: u16,
+    pub rtde: u16,
+}
+
+impl Ports {
+    /// Ports the controller listens on
+    pub const DEFAULT: Ports = Ports {
+        dashboard: 29999,
+        primary: 30001,
+        secondary: 30002,
+        rtde: 30004,
+    };
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Ports::DEFAULT
+    }
+}
+
+/// Which interfaces to open, and when
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Interfaces {
+    pub dashboard: bool,
+    pub primary: bool,
+    pub secondary: bool,
+    pub rtde: bool,
+    /// Connect each interface on its first use rather than on connect
+    pub lazy: bool,
+}
+
+impl Default for Interfaces {
+    fn default() -> Self {
+        Interfaces {
+            dashboard: true,
+            primary: true,
+            secondary: true,
+            rtde: true,
+            lazy: false,
+        }
+    }
+}
+
+/// RTDE output settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RtdeConfig {
+    /// Output frequency \[Hz\]
+    pub frequency: f64,
+    /// Output recipes by name
+    pub recipes: BTreeMap<String, Vec<String>>,
+    /// Recipe to set up and start streaming on connect
+    pub output: Option<String>,
+}
+
+impl Default for RtdeConfig {
+    fn default() -> Self {
+        RtdeConfig {
+            frequency: 125.0,
+            recipes: BTreeMap::new(),
+            output: None,
+        }
+    }
+}
+
+/// Tool settings applied to motion programs that don't set their own
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    /// Tool center point offset from the tool flange, x, y, z \[m\] and rotation vector \[rad\]
+    pub tcp: Option<[f64; 6]>,
+    pub payload: Option<ToolPayload>,
+}
+
+impl ToolConfig {
+    pub fn tcp(&self) -> Option<Vec6> {
+        self.tcp
+            .map(|[x, y, z, rx, ry, rz]| Vec6::new(x, y, z, rx, ry, rz))
+    }
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl RobotConfig {
+    /// Default settings for the robot at this address
+    pub fn new(address: IpAddr) -> Self {
+        RobotConfig {
+            address,
+            ports: Ports::DEFAULT,
+            timeout: default_timeout(),
+            connect_timeout: None,
+            interfaces: Interfaces::default(),
+            rtde: RtdeConfig::default(),
+            tool: ToolConfig::default(),
+            programs_root: None,
+        }
+    }
+    /// Read and write timeout of every interface
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Load a `.toml` or `.json` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            Some("toml") => Self::from_toml(&contents),
+            _ => Err(Error::Static("robot config must be a .toml or .json file")),
+        }
+    }
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|error| Error::Deserialization(error.to_string()))
+    }
+    pub fn from_json(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).map_err(|error| Error::Deserialization(error.to_string()))
+    }
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|error| Error::Serialization(error.to_string()))
+    }
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|error| Error::Serialization(error.to_string()))
+    }
+    /// Address of an interface's port
+    pub fn socket(&self, port: u16) -> SocketAddr {
+        SocketAddr::new(self.address, port)
+    }
+    pub(crate) fn endpoint(&self, port: u16) -> Endpoint {
+        Endpoint {
+            address: self.socket(port),
+            connect_timeout: self.connect_timeout,
+            timeout: Some(self.timeout),
+        }
+    }
+    /// Variables of a named output recipe
+    pub fn recipe(&self, name: &str) -> Option<Vec<&str>> {
+        let recipe = self.rtde.recipes.get(name)?;
+        Some(recipe.iter().map(String::as_str).collect())
+    }
+}
+
+/// Durations as fractional seconds
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+    }
+}
+
+mod optional_seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match Option::<f64>::deserialize(deserializer)? {
+            Some(seconds) => Duration::try_from_secs_f64(seconds)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: &str = r#"
+address = "127.0.0.1"
+timeout = 2.5
+
+[ports]
+dashboard = 39999
+rtde = 40004
+
+[interfaces]
+secondary = false
+lazy = true
+
+[rtde]
+output = "health"
+recipes.health = ["timestamp", "actual_current"]
+
+[tool]
+tcp = [0.0, 0.0, 0.15, 0.0, 0.0, 0.0]
+payload = { mass = 1.2, center_of_gravity = [0.0, 0.0, 0.05] }
+"#;
+
+    #[test]
+    fn test_minimal() {
+        let config = RobotConfig::from_toml(r#"address = "192.168.0.10""#).unwrap();
+        assert_eq!(config, RobotConfig::new(IpAddr::from([192, 168, 0, 10])));
+        assert_eq!(config.ports.rtde, 30004);
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert!(RobotConfig::from_toml("timeout = 1.0").is_err());
+    }
+    #[test]
+    fn test_cell() {
+        let config = RobotConfig::from_toml(CELL).unwrap();
+        assert_eq!(config.timeout, Duration::from_millis(2500));
+        assert_eq!(config.ports.dashboard, 39999);
+        // ports not overridden keep their defaults
+        assert_eq!(config.ports.primary, 30001);
+        assert!(!config.interfaces.secondary && config.interfaces.rtde);
+        assert!(config.interfaces.lazy);
+        assert_eq!(config.rtde.frequency, 125.0);
+        assert_eq!(
+            config.recipe("health"),
+            Some(vec!["timestamp", "actual_current"])
+        );
+        assert_eq!(config.tool.tcp().unwrap().z, 0.15);
+        assert_eq!(config.tool.payload.unwrap().mass, 1.2);
+        assert_eq!(
+            config.socket(config.ports.rtde),
+            "127.0.0.1:40004".parse().unwrap()
+        );
+    }
+    #[test]
+    fn test_round_trip() {
+        let config = RobotConfig::from_toml(CELL).unwrap();
+        assert_eq!(
+            RobotConfig::from_json(&config.to_json().unwrap()).unwrap(),
+            config
+        );
+        assert_eq!(
+            RobotConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+    }
+    #[test]
+    fn test_unknown_field() {
+        let error =
+            RobotConfig::from_toml("address = \"127.0.0.1\"\n[ports]\nrtd = 1").unwrap_err();
+        assert!(error.to_string().contains("rtd"));
+    }
+}
@@ -5,14 +5,22 @@ mod test;
 
 pub mod commands;
 pub mod helpers;
+pub mod path;
+pub mod policy;
 pub mod query;
+pub mod response;
 pub mod safety;
 pub mod types;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
-use crate::physical::UrPort;
+use crate::audit::AuditLog;
+use crate::config::Ports;
+use crate::physical::{Connection, UrPort};
 use crate::prelude::*;
+use crate::{Interface, InterfaceStatus};
+use policy::CommandPolicy;
+use response::DashboardError;
 
 /// Dashboard Server
 ///
@@ -23,44 +31,126 @@ use crate::prelude::*;
 /// • set operational mode
 /// <https://s3-eu-west-1.amazonaws.com/ur-support-site/42728/DashboardServer_e-Series_2022.pdf>
 pub struct Dashboard {
-    port: UrPort,
+    port: Connection,
     latest_message: String,
+    programs_root: Option<String>,
+    policy: Option<CommandPolicy>,
+    audit: Option<AuditLog>,
 }
 
 impl Dashboard {
-    const DASHBOARD_PORT: u16 = 29999;
     /// Initialize connection to the dashboard server port
     pub fn new(host: IpAddr, timeout: Option<Duration>) -> Result<Self> {
-        let mut port = UrPort::new(host, timeout, Self::DASHBOARD_PORT)?;
-        println!("{}({})", port.read()?, host);
+        Self::connect(SocketAddr::new(host, Ports::DEFAULT.dashboard), timeout)
+    }
+    /// Initialize connection to a dashboard server on another port, e.g. when port-forwarded
+    pub fn connect(address: SocketAddr, timeout: Option<Duration>) -> Result<Self> {
+        Self::with_connection(Connection::Open(UrPort::connect(address, None, timeout)?))
+    }
+    /// Read the greeting if the connection is open, otherwise that happens on first use
+    pub(crate) fn with_connection(port: Connection) -> Result<Self> {
         let mut dashboard = Dashboard {
             port,
             latest_message: String::new(),
+            programs_root: None,
+            policy: None,
+            audit: None,
         };
-        dashboard.log("connected to Rust")?;
+        if dashboard.port.is_open() {
+            dashboard.greet()?;
+        }
         Ok(dashboard)
     }
+    /// Read the server's greeting and say hello in the robot log
+    fn greet(&mut self) -> Result<()> {
+        let port = self.port.open(Interface::Dashboard)?;
+        let greeting = port.read()?;
+        log::debug!("{greeting}({})", port.peer_addr()?.ip());
+        self.log("connected to Rust")?;
+        Ok(())
+    }
+    /// The open port, connecting first if needed
+    fn port(&mut self) -> Result<&mut UrPort> {
+        if !self.port.is_open() {
+            self.port.open(Interface::Dashboard)?;
+            let greeting = self.greet();
+            self.port.check(Interface::Dashboard, greeting)?;
+        }
+        self.port.open(Interface::Dashboard)
+    }
+    pub(crate) fn status(&self) -> InterfaceStatus {
+        self.port.status()
+    }
     /// Private boilerplate function to send a command or query to the dashboard server with an expected response pattern
     ///
-    /// The response pattern is case insensitive
+    /// The response pattern is case insensitive, the response is returned as received without
+    /// the line ending. Responses that don't match are classified into a [`DashboardError`].
+    /// Commands the handle's [`CommandPolicy`] denies are not sent. Every command is recorded in
+    /// the audit log, if there is one.
     fn send(&mut self, payload: &str, response_contains: &str) -> Result<String> {
-        let response = self.port.write(payload)?.to_lowercase();
+        let result = self.exchange(payload, response_contains);
+        if let Some(audit) = &self.audit {
+            let response = match &result {
+                Ok(response) => Some(response.as_str()),
+                Err(Error::Dashboard(_)) => Some(self.latest_message.as_str()),
+                Err(_) => None,
+            };
+            audit.command(Interface::Dashboard, payload, response, &result);
+        }
+        result
+    }
+    fn exchange(&mut self, payload: &str, response_contains: &str) -> Result<String> {
+        if let Some(policy) = &mut self.policy {
+            policy.authorize(payload)?;
+        }
+        let response = self.port()?.write(payload);
+        let response = self
+            .port
+            .check(Interface::Dashboard, response)?
+            .trim_end()
+            .to_owned();
         self.latest_message = response.clone();
-        if response.contains(&response_contains.to_lowercase()) {
+        if response
+            .to_lowercase()
+            .contains(&response_contains.to_lowercase())
+        {
             Ok(response)
         } else {
-            Err(Error::UnexpectedResponse(response))
+            Err(DashboardError::classify(payload, &self.latest_message).into())
         }
     }
+    /// Error for a response to the latest command that could not be parsed
+    fn malformed(&self) -> Error {
+        DashboardError::Malformed(self.latest_message.clone()).into()
+    }
+    /// Send a command that can take longer than the port timeout to respond, e.g. report generation
+    fn send_with_timeout(
+        &mut self,
+        payload: &str,
+        response_contains: &str,
+        timeout: Duration,
+    ) -> Result<String> {
+        let previous = self.port()?.set_read_timeout(Some(timeout))?;
+        let response = self.send(payload, response_contains);
+        self.port()?.set_read_timeout(previous)?;
+        response
+    }
+    /// Record every command sent on this handle, see [`crate::audit`]
+    pub fn set_audit(&mut self, audit: AuditLog) {
+        self.audit = Some(audit);
+    }
     /// Get the latest message that was received by the Dashboard server.
     ///
     /// This is cached and overwritten every time a new message is read.
     pub fn latest_message(&self) -> String {
         self.latest_message.to_owned()
     }
-    /// End connection to the dashboard server port
+    /// End connection to the dashboard server port, whatever the handle's policy allows
     pub fn close(mut self) -> Result<()> {
-        self.send("quit", "disconnected")?;
+        self.policy = None;
+        if self.port.is_open() {
+            self.send("quit", "disconnected")?;
+        }
         self.port.close()
     }
 }
@@ -1,18 +1,24 @@
-use super::types::OpMode;
+use super::path::ProgramPath;
+use super::response::DashboardError;
+use super::types::{FlightReport, OpMode, UserRole};
 use crate::prelude::*;
 
 /// Commands used to set a state on the robot arm
 impl Dashboard {
     /// Load a known program to the Robot
+    ///
+    /// Relative paths are resolved from the programs directory, absolute paths such as
+    /// `/programs/cell_1/pick.urp` are loaded as given. The `.urp` suffix is optional.
     /// - Remote control only
     /// - supported from 5.0.0
     pub fn load_program(&mut self, program: &str) -> Result<String> {
-        let payload = if program.ends_with(".urp") {
-            format!("Load {}", program)
-        } else {
-            format!("Load {}.urp", program)
-        };
-        self.send(&payload, "loading program")
+        let program = ProgramPath::new(program);
+        let response = self.send(&format!("Load {program}"), "loading program")?;
+        // expected response: "Loading program: <absolute path of the program>"
+        if let Some((_, loaded)) = response.split_once(": ") {
+            self.find_programs_root(&program, loaded);
+        }
+        Ok(response)
     }
     /// Load a known installation to the Robot
     /// - Remote control only
@@ -36,10 +42,11 @@ impl Dashboard {
     /// - supported from 5.0.0
     pub fn play(&mut self) -> Result<String> {
         for _ in 0..5 {
-            if let Ok(resp) = self.send("play", "starting program") {
-                return Ok(resp);
-            } else {
-                sleep(Duration::from_millis(100));
+            match self.send("play", "starting program") {
+                Ok(resp) => return Ok(resp),
+                // retrying won't change the policy's mind
+                Err(error @ Error::Policy(_)) => return Err(error),
+                Err(_) => sleep(Duration::from_millis(100)),
             }
         }
         Err(Error::Static("failed to execute play command"))
@@ -118,4 +125,48 @@ impl Dashboard {
     pub fn brake_release(&mut self) -> Result<String> {
         self.send("brake release", "brake releasing")
     }
+    /// Set the PolyScope user role
+    /// - supported from 5.0.0
+    pub fn set_user_role(&mut self, role: UserRole) -> Result<String> {
+        let payload = format!("setUserRole {}", role.as_str());
+        self.send(&payload, "setting user role")
+    }
+    /// Save the robot log to disk
+    /// - supported from 5.0.0
+    pub fn save_log(&mut self) -> Result<String> {
+        self.send("saveLog", "log saved")
+    }
+    /// Generate a flight report and return its id.
+    ///
+    /// The report can take a few minutes to generate, the port timeout is raised to
+    /// `timeout` until it is done
+    /// - supported from 5.8.0
+    pub fn generate_flight_report(
+        &mut self,
+        report: FlightReport,
+        timeout: Duration,
+    ) -> Result<String> {
+        let payload = format!("generate flight report {}", report.as_str());
+        let response = self.send_with_timeout(&payload, "", timeout)?;
+        // expected response: "<report id>", or an error message
+        let lower = response.to_lowercase();
+        if lower.contains("error") || lower.contains("fail") {
+            return Err(DashboardError::classify(&payload, &self.latest_message()).into());
+        }
+        Ok(response.trim().to_owned())
+    }
+    /// Generate a support file in the directory and return its file name.
+    ///
+    /// The directory must exist on the robot, generation can take a few minutes so the port
+    /// timeout is raised to `timeout` until it is done
+    /// - supported from 5.8.0
+    pub fn generate_support_file(&mut self, directory: &str, timeout: Duration) -> Result<String> {
+        let payload = format!("generate support file {}", directory);
+        let response = self.send_with_timeout(&payload, "completed successfully", timeout)?;
+        // expected response: "Completed successfully: <file name>"
+        match response.split_once(": ") {
+            Some((_, file)) => Ok(file.trim().to_owned()),
+            None => Err(self.malformed()),
+        }
+    }
 }
@@ -1,21 +1,35 @@
 use super::types::{OperationalState, RobotMode, RobotState};
+use crate::identity::Feature;
 use crate::prelude::*;
+use crate::watcher::{RobotStatus, StatusField};
 
 impl UniversalRobot {
     /// Request several status metrics from the robot
+    ///
+    /// Queries missing from the robot's software version are skipped, see [`Feature`]
     pub fn get_meta_data(&mut self) -> Result<RobotState> {
+        let identity = self.identity()?.clone();
         let port = &mut self.dashboard;
         let (is_saved, program) = port.is_saved()?;
         Ok(RobotState {
             program,
             is_saved,
-            version: port.get_version()?,
+            version: identity.software,
             mode: port.get_mode()?,
-            is_remote: port.is_remote_mode()?,
-            serial: port.get_version()?,
-            model: port.get_model()?,
-            operational_mode: port.get_op_mode()?,
-            safety_state: port.safety_status()?,
+            is_remote: match identity.supports(Feature::RemoteControlQuery) {
+                true => port.is_remote_mode()?,
+                false => true,
+            },
+            serial: identity.serial.clone(),
+            model: identity.model.clone(),
+            operational_mode: match identity.supports(Feature::OperationalModeQuery) {
+                true => port.get_op_mode()?,
+                false => None,
+            },
+            safety_state: match identity.supports(Feature::SafetyStatus) {
+                true => port.safety_status()?,
+                false => port.get_safety_mode()?,
+            },
         })
     }
     /// Request operational status from the Robot
@@ -26,22 +40,22 @@ impl UniversalRobot {
             state: port.get_program_state()?,
         })
     }
-    /// Wait on program to be loaded
+    /// Wait on program to be loaded, unless it is already loaded
     pub fn load(&mut self, program: &str, timeout: Duration) -> Result<()> {
         let port = &mut self.dashboard;
-        let loaded = port.get_loaded_program()?;
-        if loaded == program {
+        let program = port.program_path(program);
+        if port.get_loaded_program()?.as_ref() == Some(&program) {
             return Ok(());
         }
-        port.load_program(program)?;
+        port.load_program(program.as_str())?;
         let now = Instant::now();
-        while port.get_loaded_program()? != program {
-            if now.elapsed() > timeout {
-                return Err(Error::Timeout(program.to_owned(), now.elapsed().as_secs()));
+        let loaded = |status: &RobotStatus| status.loaded_program.as_ref() == Some(&program);
+        match self.wait_for(&[StatusField::LoadedProgram], loaded, timeout) {
+            Err(Error::Timeout(..)) => {
+                Err(Error::Timeout(program.to_string(), now.elapsed().as_secs()))
             }
-            sleep(Duration::from_millis(1));
+            other => other.map(|_| ()),
         }
-        Ok(())
     }
     /// Wait on power up or timeout
     pub fn power_on(&mut self, timeout: Duration) -> Result<()> {
@@ -52,15 +66,11 @@ impl UniversalRobot {
             return Ok(());
         }
         port.power(true)?;
-        let now = Instant::now();
-        loop {
-            let mode = port.get_mode()?;
-            if on_states.contains(&port.get_mode()?) {
-                break Ok(());
-            } else if now.elapsed() > timeout {
-                break Err(Error::Timeout(format!("{mode:?}"), now.elapsed().as_secs()));
-            }
-            sleep(Duration::from_millis(1));
-        }
+        self.wait_for(
+            &[StatusField::Mode],
+            |status| on_states.contains(&status.mode),
+            timeout,
+        )?;
+        Ok(())
     }
 }
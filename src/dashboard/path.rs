@@ -0,0 +1,164 @@
+//! Program paths on the controller
+//!
+//! The dashboard server reports the loaded program as an absolute path, while programs are
+//! usually loaded by a path relative to the programs directory, e.g. `/programs` on a robot
+//! and `/ursim/programs` on URSim. The directory is configured, or found from where the
+//! controller loads the first program given by a relative path.
+
+use std::fmt::Display;
+
+use crate::prelude::*;
+
+/// Normalised path to a `.urp` program, relative to the programs root where possible
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProgramPath {
+    path: String,
+}
+
+impl ProgramPath {
+    /// Normalise a relative or absolute program path.
+    ///
+    /// Empty and `.` segments are dropped and the `.urp` suffix is added if missing
+    pub fn new(path: &str) -> Self {
+        let path = path.trim();
+        let mut normalised = path
+            .split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .collect::<Vec<_>>()
+            .join("/");
+        if path.starts_with('/') {
+            normalised.insert(0, '/');
+        }
+        if !normalised.ends_with(".urp") {
+            normalised.push_str(".urp");
+        }
+        ProgramPath { path: normalised }
+    }
+    /// Normalise a path reported by the controller, making it relative to `root` if inside it
+    pub fn from_absolute(path: &str, root: &str) -> Self {
+        let program = ProgramPath::new(path);
+        let root = format!("{}/", root.trim().trim_end_matches('/'));
+        match program.path.strip_prefix(&root) {
+            Some(relative) => ProgramPath::new(relative),
+            None => program,
+        }
+    }
+    pub fn is_absolute(&self) -> bool {
+        self.path.starts_with('/')
+    }
+    /// Absolute path on the controller
+    pub fn absolute(&self, root: &str) -> String {
+        match self.is_absolute() {
+            true => self.path.clone(),
+            false => format!("{}/{}", root.trim().trim_end_matches('/'), self.path),
+        }
+    }
+    /// Program file name without directories
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Display for ProgramPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl From<&str> for ProgramPath {
+    fn from(path: &str) -> Self {
+        ProgramPath::new(path)
+    }
+}
+
+impl PartialEq<str> for ProgramPath {
+    /// Compares after normalising `other`, so `"demo"` equals `demo.urp`
+    fn eq(&self, other: &str) -> bool {
+        *self == ProgramPath::new(other)
+    }
+}
+
+impl PartialEq<&str> for ProgramPath {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl Dashboard {
+    /// Set the programs directory used to make loaded program paths relative,
+    /// instead of finding it when a program is loaded
+    pub fn set_programs_root(&mut self, root: &str) {
+        self.programs_root = Some(root.trim().trim_end_matches('/').to_owned());
+    }
+    /// The configured programs directory, or the one found when a program was loaded
+    pub fn programs_root(&self) -> Option<&str> {
+        self.programs_root.as_deref()
+    }
+    /// Normalise a program path, making absolute paths relative to the programs root if known
+    pub(crate) fn program_path(&self, path: &str) -> ProgramPath {
+        match &self.programs_root {
+            Some(root) => ProgramPath::from_absolute(path, root),
+            None => ProgramPath::new(path),
+        }
+    }
+    /// Remember the directory the controller resolved a relative program path in, as the
+    /// programs root if none is set
+    pub(crate) fn find_programs_root(&mut self, requested: &ProgramPath, loaded: &str) {
+        let loaded = ProgramPath::new(loaded);
+        if self.programs_root.is_some() || requested.is_absolute() || !loaded.is_absolute() {
+            return;
+        }
+        if let Some(root) = loaded.as_str().strip_suffix(&format!("/{requested}")) {
+            log::debug!("programs root found at '{root}'");
+            self.set_programs_root(root);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physical::Connection;
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(ProgramPath::new("demo").as_str(), "demo.urp");
+        assert_eq!(
+            ProgramPath::new(" ./cell 1//pick up.urp ").as_str(),
+            "cell 1/pick up.urp"
+        );
+        assert_eq!(
+            ProgramPath::new("/programs/demo").as_str(),
+            "/programs/demo.urp"
+        );
+        assert_eq!(ProgramPath::new("cell_1/Pick.urp").file_name(), "Pick.urp");
+    }
+    #[test]
+    fn test_relative_to_root() {
+        let path = ProgramPath::from_absolute("/programs/cell_1/pick.urp", "/programs/");
+        assert_eq!(path, "cell_1/pick");
+        assert_eq!(path.absolute("/programs"), "/programs/cell_1/pick.urp");
+        let outside = ProgramPath::from_absolute("/data/pick.urp", "/programs");
+        assert!(outside.is_absolute());
+        assert_eq!(outside.absolute("/programs"), "/data/pick.urp");
+        // a sibling directory sharing the prefix is not inside the root
+        assert!(ProgramPath::from_absolute("/programs2/pick.urp", "/programs").is_absolute());
+    }
+    #[test]
+    fn test_find_programs_root() {
+        let mut dashboard = Dashboard::with_connection(Connection::Disabled).unwrap();
+        let requested = ProgramPath::new("cell_1/pick");
+        // a program loaded by absolute path says nothing about the root
+        dashboard.find_programs_root(&ProgramPath::new("/data/pick"), "/data/pick.urp");
+        assert_eq!(dashboard.programs_root(), None);
+        dashboard.find_programs_root(&requested, "/ursim/programs/cell_1/pick.urp");
+        assert_eq!(dashboard.programs_root(), Some("/ursim/programs"));
+        assert_eq!(
+            dashboard.program_path("/ursim/programs/cell_1/pick.urp"),
+            requested
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Which dashboard commands a handle may send
+//!
+//! Every command a [`Dashboard`] sends falls in a [`CommandCategory`]. A handle without a policy
+//! sends anything, as before. A handle restricted with a [`CommandPolicy`] only sends the allowed
+//! categories for the rest of its life, the policy can't be removed or replaced, so code handed
+//! a restricted handle can't widen it. Destructive commands additionally need the policy's
+//! confirmation token, given with [`Dashboard::confirm`] right before the command and used up
+//! by it:
+//!
+//! ```ignore
+//! dashboard.restrict(
+//!     CommandPolicy::new()
+//!         .allow(CommandCategory::Motion)
+//!         .allow(CommandCategory::Destructive)
+//!         .confirmation("end-of-shift"),
+//! )?;
+//! dashboard.play()?;
+//! dashboard.confirm("end-of-shift");
+//! dashboard.power(false)?;
+//! ```
+//!
+//! Denied commands are recorded as denied in the handle's audit log, see [`crate::audit`].
+//! Ending the session with [`Dashboard::close`] is always allowed.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::prelude::*;
+
+/// What a dashboard command can do to the robot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CommandCategory {
+    /// Reads state, or only shows or logs a message
+    Query,
+    /// Loads, starts, pauses or stops programs, powers on or releases the brakes
+    Motion,
+    /// Changes the safety configuration or recovers from a safety stop
+    Safety,
+    /// Powers off, restarts safety or shuts the robot down. Commands the policy doesn't know
+    /// are treated as destructive too.
+    Destructive,
+}
+
+impl CommandCategory {
+    /// The category of a dashboard command, from its payload
+    pub fn of(command: &str) -> Self {
+        let command = command.trim().to_lowercase();
+        COMMANDS
+            .iter()
+            .find(|(name, _)| {
+                command
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+            })
+            .map_or(CommandCategory::Destructive, |(_, category)| *category)
+    }
+}
+
+impl fmt::Display for CommandCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CommandCategory::Query => "query",
+            CommandCategory::Motion => "motion",
+            CommandCategory::Safety => "safety",
+            CommandCategory::Destructive => "destructive",
+        })
+    }
+}
+
+/// Dashboard commands by their lowercase first words, longer commands before their prefixes
+const COMMANDS: [(&str, CommandCategory); 37] = {
+    use CommandCategory::*;
+    [
+        ("robotmode", Query),
+        ("running", Query),
+        ("isprogramsaved", Query),
+        ("is in remote control", Query),
+        ("programstate", Query),
+        ("polyscopeversion", Query),
+        ("version", Query),
+        ("get loaded program", Query),
+        ("get loaded installation", Query),
+        ("get user role", Query),
+        ("get serial number", Query),
+        ("get robot model", Query),
+        ("get operational mode", Query),
+        ("safetystatus", Query),
+        ("safetymode", Query),
+        ("addtolog", Query),
+        ("popup", Query),
+        ("close popup", Query),
+        ("savelog", Query),
+        ("generate flight report", Query),
+        ("generate support file", Query),
+        ("quit", Query),
+        ("load installation", Safety),
+        ("load", Motion),
+        ("play", Motion),
+        ("stop", Motion),
+        ("pause", Motion),
+        ("power on", Motion),
+        ("brake release", Motion),
+        ("unlock protective stop", Safety),
+        ("close safety popup", Safety),
+        ("set operational mode", Safety),
+        ("clear operational mode", Safety),
+        ("setuserrole", Safety),
+        ("power off", Destructive),
+        ("restart safety", Destructive),
+        ("shutdown", Destructive),
+    ]
+};
+
+/// Why a command was not sent
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum PolicyError {
+    #[error("{category} command '{command}' is not allowed on this dashboard handle")]
+    NotAllowed {
+        command: String,
+        category: CommandCategory,
+    },
+    #[error("destructive command '{0}' was not confirmed with the policy's token")]
+    Unconfirmed(String),
+    #[error("the dashboard handle is already restricted, its policy can't be replaced")]
+    Restricted,
+}
+
+/// Command categories a dashboard handle may send, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct CommandPolicy {
+    allowed: BTreeSet<CommandCategory>,
+    confirmation: Option<String>,
+    /// Token given for the next destructive command
+    confirmed: Option<String>,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandPolicy {
+    /// Queries only
+    pub fn new() -> Self {
+        CommandPolicy {
+            allowed: BTreeSet::from([CommandCategory::Query]),
+            confirmation: None,
+            confirmed: None,
+        }
+    }
+    pub fn allow(mut self, category: CommandCategory) -> Self {
+        self.allowed.insert(category);
+        self
+    }
+    pub fn deny(mut self, category: CommandCategory) -> Self {
+        self.allowed.remove(&category);
+        self
+    }
+    /// Token that must be given with [`Dashboard::confirm`] before each destructive command.
+    ///
+    /// Without one, destructive commands are denied even when allowed.
+    pub fn confirmation(mut self, token: &str) -> Self {
+        self.confirmation = Some(token.to_owned());
+        self
+    }
+    pub fn allows(&self, category: CommandCategory) -> bool {
+        self.allowed.contains(&category)
+    }
+    /// Would the command be sent now, without using up a confirmation
+    pub fn permits(&self, command: &str) -> bool {
+        let category = CommandCategory::of(command);
+        self.allows(category)
+            && (category != CommandCategory::Destructive
+                || self.confirmation.is_some() && self.confirmed == self.confirmation)
+    }
+    /// Check a command before it is sent.
+    ///
+    /// A confirmation is used up by the next destructive command, whether or not it matched.
+    pub(crate) fn authorize(&mut self, command: &str) -> Result<()> {
+        let category = CommandCategory::of(command);
+        let denied = if !self.allows(category) {
+            Some(PolicyError::NotAllowed {
+                command: command.to_owned(),
+                category,
+            })
+        } else if category == CommandCategory::Destructive {
+            let confirmed = self.confirmed.take();
+            match (&self.confirmation, confirmed) {
+                (Some(token), Some(given)) if *token == given => None,
+                _ => Some(PolicyError::Unconfirmed(command.to_owned())),
+            }
+        } else {
+            None
+        };
+        match &denied {
+            Some(error) => log::warn!("dashboard policy denied: {error}"),
+            None => log::debug!("dashboard policy permitted {category} command '{command}'"),
+        }
+        match denied {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Dashboard {
+    /// Limit the commands this handle sends for the rest of its life, see [`CommandPolicy`].
+    ///
+    /// Fails with [`PolicyError::Restricted`] if the handle already has a policy.
+    pub fn restrict(&mut self, policy: CommandPolicy) -> Result<()> {
+        if self.policy.is_some() {
+            return Err(PolicyError::Restricted.into());
+        }
+        self.policy = Some(policy);
+        Ok(())
+    }
+    pub fn policy(&self) -> Option<&CommandPolicy> {
+        self.policy.as_ref()
+    }
+    /// Confirm the next destructive command with the policy's token
+    pub fn confirm(&mut self, token: &str) {
+        if let Some(policy) = &mut self.policy {
+            policy.confirmed = Some(token.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::audit::tests::Entries;
+    use crate::audit::{AuditLog, Outcome};
+    use crate::physical::{Connection, Endpoint};
+
+    /// A handle to a dashboard server that isn't running, so every permitted command fails to
+    /// connect and every denied one fails on the policy
+    fn offline() -> Dashboard {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        drop(listener);
+        Dashboard::with_connection(Connection::Pending(Endpoint {
+            address,
+            connect_timeout: Some(Duration::from_millis(200)),
+            timeout: Some(Duration::from_millis(200)),
+        }))
+        .unwrap()
+    }
+
+    fn is_policy_error<T: fmt::Debug>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Policy(_)))
+    }
+
+    #[test]
+    fn test_categories() {
+        use CommandCategory::*;
+        let cases = [
+            ("robotmode", Query),
+            ("get loaded program", Query),
+            ("close popup", Query),
+            ("addToLog connected to Rust", Query),
+            ("Load /programs/pick.urp", Motion),
+            ("load installation default.installation", Safety),
+            ("power on", Motion),
+            ("close safety popup", Safety),
+            ("setUserRole operator", Safety),
+            ("set operational mode manual", Safety),
+            ("power off", Destructive),
+            ("restart safety", Destructive),
+            ("shutdown", Destructive),
+            ("stopped", Destructive),
+            ("something new", Destructive),
+        ];
+        for (command, category) in cases {
+            assert_eq!(CommandCategory::of(command), category, "{command}");
+        }
+    }
+    #[test]
+    fn test_policy() {
+        let mut dashboard = offline();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let audit = AuditLog::new().sink(Entries(entries.clone()));
+        dashboard.set_audit(audit.clone());
+        // without a policy everything goes out, and fails on the missing server
+        assert!(!is_policy_error(dashboard.shutdown()));
+        dashboard.restrict(CommandPolicy::new()).unwrap();
+        assert!(is_policy_error(dashboard.play()));
+        assert!(is_policy_error(dashboard.safety_unlock_protective_stop()));
+        assert!(!is_policy_error(dashboard.get_mode()));
+        audit.flush();
+        let denied: Vec<bool> = entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| matches!(entry.outcome, Outcome::Denied(_)))
+            .collect();
+        assert_eq!(denied, [false, true, true, false]);
+        // the policy can't be widened
+        let wider = CommandPolicy::new().allow(CommandCategory::Motion);
+        assert!(is_policy_error(dashboard.restrict(wider)));
+        assert!(is_policy_error(dashboard.play()));
+    }
+    #[test]
+    fn test_confirmation() {
+        let mut dashboard = offline();
+        dashboard
+            .restrict(
+                CommandPolicy::new()
+                    .allow(CommandCategory::Destructive)
+                    .confirmation("end-of-shift"),
+            )
+            .unwrap();
+        assert!(is_policy_error(dashboard.power(false)));
+        dashboard.confirm("end of shift");
+        assert!(is_policy_error(dashboard.power(false)));
+        dashboard.confirm("end-of-shift");
+        assert!(dashboard.policy().unwrap().permits("power off"));
+        assert!(!is_policy_error(dashboard.power(false)));
+        // used up by the command above
+        assert!(is_policy_error(dashboard.shutdown()));
+
+        // allowed, but there is no token to confirm with
+        let mut dashboard = offline();
+        let policy = CommandPolicy::new().allow(CommandCategory::Destructive);
+        dashboard.restrict(policy).unwrap();
+        dashboard.confirm("");
+        assert!(is_policy_error(dashboard.shutdown()));
+    }
+    #[test]
+    fn test_close_ignores_policy() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            writeln!(writer, "Connected: Universal Robots Dashboard Server").unwrap();
+            reader.read_line(&mut line).unwrap();
+            writeln!(writer, "Added log message").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            writeln!(writer, "Disconnected").unwrap();
+            line
+        });
+        let mut dashboard = Dashboard::connect(address, Some(Duration::from_secs(1))).unwrap();
+        let policy = CommandPolicy::new().deny(CommandCategory::Query);
+        dashboard.restrict(policy).unwrap();
+        assert!(is_policy_error(dashboard.get_mode()));
+        dashboard.close().unwrap();
+        assert_eq!(server.join().unwrap(), "quit\n");
+    }
+}
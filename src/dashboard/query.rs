@@ -1,5 +1,7 @@
-use super::types::{OpMode, ProgramState, RobotMode};
+use super::path::ProgramPath;
+use super::types::{OpMode, ProgramState, RobotMode, UserRole};
 use crate::prelude::*;
+use crate::types::Version;
 
 /// Queries to ask the robot arm for state information
 impl Dashboard {
@@ -12,7 +14,7 @@ impl Dashboard {
     ///
     /// - supported from 5.0.0
     pub fn get_mode(&mut self) -> Result<RobotMode> {
-        let response = self.send("robotmode", "robotmode")?;
+        let response = self.send("robotmode", "robotmode")?.to_lowercase();
         let mode: Vec<&str> = response.split_whitespace().collect();
         // expected response: "Robotmode: <mode>"
         if let Some(status) = mode.get(1) {
@@ -26,25 +28,22 @@ impl Dashboard {
                 "idle" => Ok(RobotMode::Idle),
                 "backdrive" => Ok(RobotMode::Backdrive),
                 "running" => Ok(RobotMode::Running),
-                val => Err(Error::UnexpectedResponse(format!(
-                    "Unknown Robot Mode: {}",
-                    val
-                ))),
+                _ => Err(self.malformed()),
             }
         } else {
-            Err(Error::UnexpectedResponse(response))
+            Err(self.malformed())
         }
     }
     /// Execution state enquiry
     /// - supported from 5.0.0
     pub fn is_running(&mut self) -> Result<bool> {
-        let response = self.send("running", "program running")?;
+        let response = self.send("running", "program running")?.to_lowercase();
         let state: Vec<&str> = response.split_whitespace().collect();
         // expected response: "Program running: <bool>"
         match *state.last().unwrap_or(&"") {
             "true" => Ok(true),
             "false" => Ok(false),
-            _ => Err(Error::UnexpectedResponse(response)),
+            _ => Err(self.malformed()),
         }
     }
     /// Enquire about the save state of the active program and path to loaded program file
@@ -52,21 +51,17 @@ impl Dashboard {
     pub fn is_saved(&mut self) -> Result<(bool, Option<String>)> {
         let response = self.send("isProgramSaved", "")?;
         // anticipated response "program running: false"
-        if response.contains("program running: false") {
+        if response.to_lowercase().contains("program running: false") {
             return Ok((false, None));
         }
-        let mut state = response.split_whitespace();
-        let Some(status) = state.next() else {
-            return Err(Error::UnexpectedResponse(response));
-        };
-        let Some(program_name) = state.next() else {
-            return Err(Error::UnexpectedResponse(response));
+        let Some((status, program_name)) = response.trim().split_once(' ') else {
+            return Err(self.malformed());
         };
         // expected response: "true <program.name>" or "false <program.name>"
-        match status {
+        match status.to_lowercase().as_str() {
             "true" => Ok((true, Some(program_name.to_owned()))),
             "false" => Ok((false, Some(program_name.to_owned()))),
-            _ => Err(Error::UnexpectedResponse(response)),
+            _ => Err(self.malformed()),
         }
     }
     /// Returns the remote control status of the robot.
@@ -74,12 +69,12 @@ impl Dashboard {
     /// If the robot is in local mode or disabled it returns false.
     /// - supported from 5.6.0
     pub fn is_remote_mode(&mut self) -> Result<bool> {
-        let response = self.send("is in remote control", "")?;
-        // expected response: "true", or "false"
-        match response.as_str().trim() {
+        let response = self.send("is in remote control", "")?.to_lowercase();
+        // expected response: "true", or "false", some versions prefix it with "remote control: "
+        match response.split_whitespace().last().unwrap_or("") {
             "true" => Ok(true),
             "false" => Ok(false),
-            _ => Err(Error::UnexpectedResponse(response)),
+            _ => Err(self.malformed()),
         }
     }
     /// Returns the state of the loaded program
@@ -88,15 +83,12 @@ impl Dashboard {
     /// - supported from 5.0.0
     pub fn get_program_state(&mut self) -> Result<ProgramState> {
         let response = self.send("programState", "")?;
-        // expected response: i.e "playing 'program.urp'"
-        let mut state = response.split_whitespace();
-        let Some(status) = state.next() else {
-            return Err(Error::UnexpectedResponse(response));
-        };
-        let Some(program_name) = state.next() else {
-            return Err(Error::UnexpectedResponse(response));
+        // expected response: i.e "PLAYING program.urp", the name may contain spaces
+        let Some((status, program_name)) = response.trim().split_once(' ') else {
+            return Err(self.malformed());
         };
-        match status {
+        let program_name = program_name.trim();
+        match status.to_lowercase().as_str() {
             "playing" => Ok(ProgramState::Playing(program_name.to_owned())),
             "paused" => Ok(ProgramState::Paused(program_name.to_owned())),
             "stopped" => {
@@ -106,25 +98,66 @@ impl Dashboard {
                     Ok(ProgramState::Stopped(Some(program_name.to_owned())))
                 }
             }
-            _ => Err(Error::UnexpectedResponse(response)),
+            _ => Err(self.malformed()),
         }
     }
-    /// Which program is loaded?
+    /// Which program is loaded? `None` if none is, e.g. on a freshly booted robot
+    ///
+    /// The path is made relative to the programs root, see [`Dashboard::set_programs_root`]
     /// - supported from 5.0.0
-    pub fn get_loaded_program(&mut self) -> Result<String> {
-        let response = self.send("get loaded program", "loaded program")?;
-        // expected response: "Loaded program: <path to loaded program>
-        let prog: Vec<&str> = response.split_whitespace().collect();
-        let Some(path) = prog.get(2) else {
-            return Err(Error::UnexpectedResponse(response));
-        };
-        Ok(path.trim_start_matches("/ursim/programs/").to_string())
+    pub fn get_loaded_program(&mut self) -> Result<Option<ProgramPath>> {
+        let response = self.send("get loaded program", "program")?;
+        // expected response: "Loaded program: <path to loaded program>" or "No program loaded"
+        if response.to_lowercase().starts_with("no program loaded") {
+            return Ok(None);
+        }
+        match response.split_once(": ") {
+            Some((_, path)) => Ok(Some(self.program_path(path))),
+            None => Err(self.malformed()),
+        }
     }
     /// Version information for the UR Software installed on the Robot
     /// - supported from 5.0.0
     pub fn get_version(&mut self) -> Result<String> {
         self.send("PolyscopeVersion", "URSoftware")
     }
+    /// Version number of the UR Software installed on the Robot
+    ///
+    /// Falls back to parsing the `PolyscopeVersion` response where `version` is not available
+    /// - supported from 5.0.0
+    pub fn get_software_version(&mut self) -> Result<Version> {
+        let parse = |response: &str| {
+            response
+                .split_whitespace()
+                .find_map(|word| word.parse::<Version>().ok())
+        };
+        // expected response: "5.12.2.1101534" or "URSoftware 5.12.2.1101534 (Jun 27 2022)"
+        if let Some(version) = self.send("version", "").ok().as_deref().and_then(parse) {
+            return Ok(version);
+        }
+        let response = self.get_version()?;
+        parse(&response).ok_or_else(|| self.malformed())
+    }
+    /// Which installation is loaded?
+    /// - supported from 5.0.0
+    pub fn get_loaded_installation(&mut self) -> Result<String> {
+        let response = self.send("get loaded installation", "installation")?;
+        // expected response: "Loaded installation: <path to loaded installation>"
+        match response.split_once(": ") {
+            Some((_, path)) => Ok(path.trim().to_owned()),
+            None => Err(self.malformed()),
+        }
+    }
+    /// Current PolyScope user role
+    /// - supported from 5.0.0
+    pub fn get_user_role(&mut self) -> Result<UserRole> {
+        let response = self.send("get user role", "")?;
+        // expected response: "<role>"
+        match response.split_whitespace().last() {
+            Some(role) => role.parse().map_err(|_| self.malformed()),
+            None => Err(self.malformed()),
+        }
+    }
     /// Serial number of Robot
     /// - supported from 5.6.0
     pub fn get_serial(&mut self) -> Result<String> {
@@ -143,11 +176,11 @@ impl Dashboard {
     /// - supported from 5.6.0
     pub fn get_op_mode(&mut self) -> Result<Option<OpMode>> {
         let response = self.send("get operational mode", "")?;
-        match response.as_str().trim() {
+        match response.to_lowercase().trim() {
             "manual" => Ok(Some(OpMode::Manual)),
             "automatic" => Ok(Some(OpMode::Automatic)),
             "none" => Ok(None),
-            _ => Err(Error::UnexpectedResponse(response)),
+            _ => Err(self.malformed()),
         }
     }
 }
@@ -0,0 +1,120 @@
+//! Classification of dashboard server failure responses
+
+/// Reason the dashboard server refused or failed a command, holding the original response text
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DashboardError {
+    /// The robot is in local control, the command is only allowed in remote control
+    #[error("Robot is not in remote control: '{0}'")]
+    NotRemote(String),
+    /// The program, installation or directory does not exist on the controller
+    #[error("File not found: '{0}'")]
+    FileNotFound(String),
+    /// The command is not allowed in the current robot or program state
+    #[error("Not allowed in the current state: '{0}'")]
+    WrongState(String),
+    /// The command is not known to this software version
+    #[error("Command not supported: '{0}'")]
+    Unsupported(String),
+    /// The response could not be understood
+    #[error("Malformed response: '{0}'")]
+    Malformed(String),
+}
+
+/// Commands that name a file on the controller, by their lowercase first words
+const FILE_COMMANDS: [&str; 3] = ["load", "load installation", "generate support file"];
+
+/// Commands that only read state, by their lowercase first words
+const QUERIES: [&str; 10] = [
+    "robotmode",
+    "running",
+    "isprogramsaved",
+    "is in remote control",
+    "programstate",
+    "polyscopeversion",
+    "version",
+    "get ",
+    "safetystatus",
+    "safetymode",
+];
+
+impl DashboardError {
+    /// Classify a response that did not match the pattern expected for its command.
+    ///
+    /// Only commands naming a file can fail with [`FileNotFound`](Self::FileNotFound), and
+    /// queries don't depend on the robot state, so their unexpected responses are
+    /// [`Malformed`](Self::Malformed) unless no program is loaded.
+    /// Matching is case insensitive, the original response text is kept in the error.
+    pub fn classify(command: &str, response: &str) -> Self {
+        let command = command.trim().to_lowercase();
+        let is_any = |names: &[&str]| names.iter().any(|name| command.starts_with(name));
+        let text = response.trim().to_owned();
+        let lower = text.to_lowercase();
+        let contains = |patterns: &[&str]| patterns.iter().any(|pattern| lower.contains(pattern));
+        if contains(&["could not understand", "unknown command", "not supported"]) {
+            DashboardError::Unsupported(text)
+        } else if contains(&["remote control", "local control", "remote mode"]) {
+            DashboardError::NotRemote(text)
+        } else if is_any(&FILE_COMMANDS)
+            && contains(&["file not found", "no such file", "does not exist"])
+        {
+            DashboardError::FileNotFound(text)
+        } else if contains(&["no program loaded"])
+            || !is_any(&QUERIES)
+                && contains(&[
+                    "failed",
+                    "cannot",
+                    "can not",
+                    "not allowed",
+                    "error",
+                    "is running",
+                ])
+        {
+            DashboardError::WrongState(text)
+        } else {
+            DashboardError::Malformed(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            ("power on", "Failed to execute: power on", "WrongState"),
+            ("load Foo.urp", "File not found: /programs/Foo.urp", "FileNotFound"),
+            (
+                "play",
+                "Command is not allowed due to robot is in local control",
+                "NotRemote",
+            ),
+            ("version", "could not understand: 'version'", "Unsupported"),
+            (
+                "unlock protective stop",
+                "Cannot unlock protective stop until 5s after occurrence. Always inspect cause of protective stop before unlocking",
+                "WrongState",
+            ),
+            ("robotmode", "Robotmode:", "Malformed"),
+            ("play", "No program loaded", "WrongState"),
+            ("get loaded program", "No program loaded", "WrongState"),
+            ("programState", "Error reading program state", "Malformed"),
+            ("popup Part does not exist", "Failed to show popup", "WrongState"),
+        ];
+        for (command, response, kind) in cases {
+            let error = DashboardError::classify(command, response);
+            assert!(
+                format!("{error:?}").starts_with(kind),
+                "{command}: {error:?}"
+            );
+        }
+    }
+    #[test]
+    fn test_preserves_case() {
+        assert_eq!(
+            DashboardError::classify("load Foo.urp", "File not found: /programs/Foo.urp"),
+            DashboardError::FileNotFound("File not found: /programs/Foo.urp".to_owned())
+        );
+    }
+}
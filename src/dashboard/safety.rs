@@ -7,26 +7,23 @@ impl Dashboard {
     /// - supported from 5.4.0
     pub fn safety_status(&mut self) -> Result<SafetyStatus> {
         let response = self.send("safetystatus", "safetystatus")?;
-        let status: Vec<&str> = response.split_whitespace().collect();
         // expected response: "safetystatus: <status>"
-        match status[1] {
-            "normal" => Ok(SafetyStatus::Normal),
-            "reduced" => Ok(SafetyStatus::Reduced),
-            "protective_stop" => Ok(SafetyStatus::ProtectiveStop),
-            "recovery" => Ok(SafetyStatus::Recovery),
-            "safeguard_stop" => Ok(SafetyStatus::SafeguardStop),
-            "system_emergency_stop" => Ok(SafetyStatus::SystemEmergencyStop),
-            "robot_emergency_stop" => Ok(SafetyStatus::RobotEmergencyStop),
-            "violation" => Ok(SafetyStatus::Violation),
-            "fault" => Ok(SafetyStatus::Fault),
-            "automatic_mode_safeguard_stop" => Ok(SafetyStatus::AutomaticModeSafeguardStop),
-            "system_three_position_enabling_stop" => {
-                Ok(SafetyStatus::SystemThreePositionEnablingStop)
-            }
-            val => Err(Error::UnexpectedResponse(format!(
-                "Unknown Safety Status: {}",
-                val
-            ))),
+        match response.split_whitespace().nth(1) {
+            Some(status) => status.parse().map_err(|_| self.malformed()),
+            None => Err(self.malformed()),
+        }
+    }
+    /// Safety Mode Inquiry, superseded by [`Dashboard::safety_status`] which also reports
+    /// the three position enabling and automatic mode stops.
+    ///
+    /// Use this on controllers older than 5.4.0
+    /// - supported from 5.0.0
+    pub fn get_safety_mode(&mut self) -> Result<SafetyStatus> {
+        let response = self.send("safetymode", "safetymode")?;
+        // expected response: "Safetymode: <mode>"
+        match response.split_whitespace().nth(1) {
+            Some(mode) => mode.parse().map_err(|_| self.malformed()),
+            None => Err(self.malformed()),
         }
     }
     /// Closes an open Safety Popup
@@ -51,7 +48,13 @@ impl Dashboard {
     /// - Remote control only
     /// - supported from 5.1.0
     pub fn safety_restart(&mut self) -> Result<String> {
-        self.log("restarted safety remotely")?;
+        // no breadcrumb for a restart the policy won't send
+        if self
+            .policy()
+            .is_none_or(|policy| policy.permits("restart safety"))
+        {
+            self.log("restarted safety remotely")?;
+        }
         self.send("restart safety", "restarting safety")
     }
 }
@@ -1,4 +1,4 @@
-use crate::dashboard::types::OpMode;
+use crate::dashboard::types::{OpMode, UserRole};
 use crate::prelude::*;
 use std::net::Ipv4Addr;
 
@@ -164,3 +164,60 @@ fn test_restart_safety() {
     assert!(dashboard.safety_restart().is_ok());
     assert!(dashboard.close().is_ok());
 }
+#[test]
+fn test_get_safety_mode() {
+    let mut dashboard = init_dash().unwrap();
+    assert_eq!(
+        dashboard.get_safety_mode().unwrap(),
+        dashboard.safety_status().unwrap()
+    );
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_get_software_version() {
+    let mut dashboard = init_dash().unwrap();
+    let version = dashboard.get_software_version().unwrap();
+    assert_eq!(version.major, 5);
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_get_loaded_installation() {
+    let mut dashboard = init_dash().unwrap();
+    let resp = dashboard.get_loaded_installation().unwrap();
+    assert!(resp.ends_with(".installation"));
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_user_role() {
+    let mut dashboard = init_dash().unwrap();
+    let role = dashboard.get_user_role().unwrap();
+    assert!(dashboard.set_user_role(UserRole::Programmer).is_ok());
+    assert!(dashboard.set_user_role(role).is_ok());
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_save_log() {
+    let mut dashboard = init_dash().unwrap();
+    assert!(dashboard.save_log().is_ok());
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_load_absolute_path() {
+    let mut dashboard = init_dash().unwrap();
+    let path = format!("/ursim/programs/{TEST_PROGRAM}");
+    assert!(dashboard.load_program(&path).is_ok());
+    assert_eq!(
+        dashboard.get_loaded_program().unwrap().unwrap(),
+        TEST_PROGRAM
+    );
+    assert!(dashboard.close().is_ok());
+}
+#[test]
+fn test_programs_root() {
+    let mut dashboard = init_dash().unwrap();
+    assert!(dashboard.load_program(TEST_PROGRAM).is_ok());
+    let loaded = dashboard.get_loaded_program().unwrap().unwrap();
+    assert_eq!(loaded, TEST_PROGRAM);
+    assert_eq!(dashboard.programs_root(), Some("/ursim/programs"));
+    assert!(dashboard.close().is_ok());
+}
@@ -1,5 +1,9 @@
+use crate::identity::RobotModel;
+use crate::prelude::*;
+use crate::types::Version;
+
 /// Robot status mode
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum RobotMode {
     NoController,
     Disconnected,
@@ -12,18 +16,42 @@ pub enum RobotMode {
     Running,
 }
 
+impl TryFrom<i32> for RobotMode {
+    type Error = Error;
+    /// Convert from the `robot_mode` RTDE output
+    fn try_from(value: i32) -> Result<Self> {
+        match value {
+            -1 => Ok(RobotMode::NoController),
+            0 => Ok(RobotMode::Disconnected),
+            1 => Ok(RobotMode::ConfirmSafety),
+            2 => Ok(RobotMode::Booting),
+            3 => Ok(RobotMode::PowerOff),
+            4 => Ok(RobotMode::PowerOn),
+            5 => Ok(RobotMode::Idle),
+            6 => Ok(RobotMode::Backdrive),
+            7 => Ok(RobotMode::Running),
+            num => Err(Error::UnexpectedResponse(format!(
+                "Unknown Robot Mode: {}",
+                num
+            ))),
+        }
+    }
+}
+
 /// Robot State slowly changing metadata
 #[derive(Debug)]
 pub struct RobotState {
     pub program: Option<String>,
     pub is_saved: bool,
-    pub version: String,
+    pub version: Version,
     pub mode: RobotMode,
+    /// None if not set, or the software is too old to report it
     pub operational_mode: Option<OpMode>,
     pub safety_state: SafetyStatus,
+    /// Always true before 5.6.0, which has no local control mode
     pub is_remote: bool,
-    pub serial: String,
-    pub model: String,
+    pub serial: Option<String>,
+    pub model: RobotModel,
 }
 
 /// Program operational state data
@@ -34,7 +62,7 @@ pub struct OperationalState {
 }
 
 /// State of the active program and path to loaded program file, or STOPPED if no program is loaded
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ProgramState {
     Stopped(Option<String>),
     Playing(String),
@@ -49,7 +77,7 @@ pub enum OpMode {
 }
 
 /// Robot safety status
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum SafetyStatus {
     Normal,
     Reduced,
@@ -60,6 +88,123 @@ pub enum SafetyStatus {
     RobotEmergencyStop,
     Violation,
     Fault,
+    /// Reported while the controller boots, before the safety system is running
+    Undefined,
+    /// The safety system is validating the joint ids after a joint was replaced
+    ValidateJointId,
     AutomaticModeSafeguardStop,
     SystemThreePositionEnablingStop,
 }
+
+impl TryFrom<i32> for SafetyStatus {
+    type Error = Error;
+    /// Convert from the `safety_mode` or `safety_status` RTDE outputs
+    fn try_from(value: i32) -> Result<Self> {
+        match value {
+            1 => Ok(SafetyStatus::Normal),
+            2 => Ok(SafetyStatus::Reduced),
+            3 => Ok(SafetyStatus::ProtectiveStop),
+            4 => Ok(SafetyStatus::Recovery),
+            5 => Ok(SafetyStatus::SafeguardStop),
+            6 => Ok(SafetyStatus::SystemEmergencyStop),
+            7 => Ok(SafetyStatus::RobotEmergencyStop),
+            8 => Ok(SafetyStatus::Violation),
+            9 => Ok(SafetyStatus::Fault),
+            10 => Ok(SafetyStatus::Undefined),
+            11 => Ok(SafetyStatus::ValidateJointId),
+            12 => Ok(SafetyStatus::AutomaticModeSafeguardStop),
+            13 => Ok(SafetyStatus::SystemThreePositionEnablingStop),
+            num => Err(Error::UnexpectedResponse(format!(
+                "Unknown Safety Status: {}",
+                num
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for SafetyStatus {
+    type Err = Error;
+    /// Convert from the status names used by the `safetystatus` and `safetymode` queries
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(SafetyStatus::Normal),
+            "reduced" => Ok(SafetyStatus::Reduced),
+            "protective_stop" => Ok(SafetyStatus::ProtectiveStop),
+            "recovery" => Ok(SafetyStatus::Recovery),
+            "safeguard_stop" => Ok(SafetyStatus::SafeguardStop),
+            "system_emergency_stop" => Ok(SafetyStatus::SystemEmergencyStop),
+            "robot_emergency_stop" => Ok(SafetyStatus::RobotEmergencyStop),
+            "violation" => Ok(SafetyStatus::Violation),
+            "fault" => Ok(SafetyStatus::Fault),
+            "undefined_safety_mode" => Ok(SafetyStatus::Undefined),
+            "validate_joint_id" => Ok(SafetyStatus::ValidateJointId),
+            "automatic_mode_safeguard_stop" => Ok(SafetyStatus::AutomaticModeSafeguardStop),
+            "system_three_position_enabling_stop" => {
+                Ok(SafetyStatus::SystemThreePositionEnablingStop)
+            }
+            val => Err(Error::UnexpectedResponse(format!(
+                "Unknown Safety Status: {}",
+                val
+            ))),
+        }
+    }
+}
+
+/// PolyScope user role, restricts what can be done from the teach pendant
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UserRole {
+    Programmer,
+    Operator,
+    None,
+    Locked,
+    Restricted,
+}
+
+impl UserRole {
+    /// Name used by the dashboard server
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Programmer => "programmer",
+            UserRole::Operator => "operator",
+            UserRole::None => "none",
+            UserRole::Locked => "locked",
+            UserRole::Restricted => "restricted",
+        }
+    }
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "programmer" => Ok(UserRole::Programmer),
+            "operator" => Ok(UserRole::Operator),
+            "none" => Ok(UserRole::None),
+            "locked" => Ok(UserRole::Locked),
+            "restricted" => Ok(UserRole::Restricted),
+            val => Err(Error::UnexpectedResponse(format!(
+                "Unknown User Role: {}",
+                val
+            ))),
+        }
+    }
+}
+
+/// Contents of a flight report
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FlightReport {
+    Controller,
+    Software,
+    System,
+}
+
+impl FlightReport {
+    /// Name used by the dashboard server
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlightReport::Controller => "controller",
+            FlightReport::Software => "software",
+            FlightReport::System => "system",
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Many robots managed as one fleet
+//!
+//! Commands run on every robot at once, each on its own thread, and return a result per robot so
+//! one robot failing doesn't stop the others.
+//!
+//! ```ignore
+//! let (mut fleet, failed) = Fleet::connect(configs);
+//! fleet.power_on(Duration::from_secs(30));
+//! let status = fleet.status();
+//!
+//! let stream = fleet.stream(&["timestamp", "actual_q"], 125.0)?;
+//! while let Ok(event) = stream.recv() {
+//!     if let FleetEvent::Sample(sample) = event {
+//!         println!("{} {:?}", sample.robot, sample.values);
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use crate::config::RobotConfig;
+use crate::dashboard::types::{OperationalState, RobotState};
+use crate::data::{DataType, Value};
+use crate::motion::Motion;
+use crate::prelude::*;
+use crate::sync_start::{StartReport, SyncStart};
+use crate::Rtde;
+
+/// Connections to many robots by name
+#[derive(Default)]
+pub struct Fleet {
+    robots: BTreeMap<String, UniversalRobot>,
+}
+
+/// Dashboard state of one robot
+#[derive(Debug)]
+pub struct RobotStatus {
+    pub meta: RobotState,
+    pub state: OperationalState,
+}
+
+/// Status of every robot in the fleet, queried at the same time
+#[derive(Debug)]
+pub struct FleetStatus {
+    /// When the queries were sent
+    pub taken: SystemTime,
+    pub robots: BTreeMap<String, Result<RobotStatus>>,
+}
+
+impl FleetStatus {
+    /// Names of the robots that couldn't be queried
+    pub fn failed(&self) -> Vec<&str> {
+        self.robots
+            .iter()
+            .filter(|(_, status)| status.is_err())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// Output values of one robot's RTDE package
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub robot: String,
+    /// When the package was read, on this computer's clock so samples from all robots compare
+    pub received: SystemTime,
+    /// In the order of the streamed variables
+    pub values: Vec<Value>,
+}
+
+/// Sent on the fleet's telemetry channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum FleetEvent {
+    Sample(Telemetry),
+    /// A robot's stream ended, the other robots keep streaming
+    Failed {
+        robot: String,
+        error: String,
+    },
+}
+
+impl Fleet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Connect to every robot at once.
+    ///
+    /// Returns the fleet of robots that connected, and why the others didn't.
+    pub fn connect<I>(configs: I) -> (Fleet, BTreeMap<String, Error>)
+    where
+        I: IntoIterator<Item = (String, RobotConfig)>,
+    {
+        let results: Vec<(String, Result<UniversalRobot>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = configs
+                .into_iter()
+                .map(|(name, config)| {
+                    (
+                        name,
+                        scope.spawn(move || UniversalRobot::from_config(&config)),
+                    )
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|(name, worker)| (name, join(worker)))
+                .collect()
+        });
+        let mut fleet = Fleet::new();
+        let mut failed = BTreeMap::new();
+        for (name, result) in results {
+            match result {
+                Ok(ur) => {
+                    fleet.add(name, ur);
+                }
+                Err(error) => {
+                    log::warn!("{name} not connected: {error}");
+                    failed.insert(name, error);
+                }
+            }
+        }
+        (fleet, failed)
+    }
+    /// Add a robot, returning the one it replaces
+    pub fn add(&mut self, name: impl Into<String>, ur: UniversalRobot) -> Option<UniversalRobot> {
+        self.robots.insert(name.into(), ur)
+    }
+    pub fn remove(&mut self, name: &str) -> Option<UniversalRobot> {
+        self.robots.remove(name)
+    }
+    pub fn get(&mut self, name: &str) -> Option<&mut UniversalRobot> {
+        self.robots.get_mut(name)
+    }
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.robots.keys().map(String::as_str)
+    }
+    pub fn len(&self) -> usize {
+        self.robots.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.robots.is_empty()
+    }
+    /// Run a command on every robot at once, each on its own thread
+    pub fn run<T, F>(&mut self, command: F) -> BTreeMap<String, Result<T>>
+    where
+        T: Send,
+        F: Fn(&mut UniversalRobot) -> Result<T> + Sync,
+    {
+        let command = &command;
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .robots
+                .iter_mut()
+                .map(|(name, ur)| (name.clone(), scope.spawn(move || command(ur))))
+                .collect();
+            workers
+                .into_iter()
+                .map(|(name, worker)| (name, join(worker)))
+                .collect()
+        })
+    }
+    /// Power on and release the brakes of every robot, waiting until each is running
+    pub fn power_on(&mut self, timeout: Duration) -> BTreeMap<String, Result<()>> {
+        self.run(|ur| ur.power_on(timeout))
+    }
+    pub fn power_off(&mut self) -> BTreeMap<String, Result<String>> {
+        self.run(|ur| ur.dashboard.power(false))
+    }
+    /// Stop the running program of every robot
+    pub fn stop(&mut self) -> BTreeMap<String, Result<String>> {
+        self.run(|ur| ur.dashboard.stop())
+    }
+    /// Start the loaded program of every robot
+    pub fn play(&mut self) -> BTreeMap<String, Result<String>> {
+        self.run(|ur| ur.dashboard.play())
+    }
+    /// Start a motion on each named robot together, see [`SyncStart::start`]
+    pub fn start_together(
+        &mut self,
+        sync: &SyncStart,
+        motions: &[(&str, Motion)],
+    ) -> Result<StartReport> {
+        let mut robots = Vec::with_capacity(motions.len());
+        for (name, ur) in self.robots.iter_mut() {
+            if let Some((_, motion)) = motions.iter().find(|(robot, _)| robot == name) {
+                robots.push((name.as_str(), ur, motion));
+            }
+        }
+        if robots.len() != motions.len() {
+            return Err(Error::Static("every motion must name a robot in the fleet"));
+        }
+        sync.start(&mut robots)
+    }
+    /// Query the dashboard state of every robot
+    pub fn status(&mut self) -> FleetStatus {
+        let taken = SystemTime::now();
+        let robots = self.run(|ur| {
+            Ok(RobotStatus {
+                meta: ur.get_meta_data()?,
+                state: ur.get_state()?,
+            })
+        });
+        FleetStatus { taken, robots }
+    }
+    /// Stream output variables from every robot into one channel.
+    ///
+    /// Each robot streams on a new RTDE connection and thread, so the robots' own RTDE
+    /// connections stay free for other recipes. A robot whose stream fails sends
+    /// [`FleetEvent::Failed`] and stops, while the others carry on.
+    pub fn stream(&self, variables: &[&str], frequency: f64) -> Result<TelemetryStream> {
+        let variables: Vec<String> = variables.iter().map(|name| name.to_string()).collect();
+        let (sender, receiver) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let mut workers = Vec::with_capacity(self.robots.len());
+        for (name, ur) in &self.robots {
+            let worker = StreamWorker {
+                robot: name.clone(),
+                config: ur.config().clone(),
+                variables: variables.clone(),
+                frequency,
+                sender: sender.clone(),
+                running: running.clone(),
+            };
+            workers.push(
+                std::thread::Builder::new()
+                    .name(format!("fleet-{name}"))
+                    .spawn(move || worker.run())?,
+            );
+        }
+        Ok(TelemetryStream {
+            receiver,
+            running,
+            workers,
+        })
+    }
+    /// Close every robot's connections
+    pub fn close(self) -> BTreeMap<String, Result<()>> {
+        self.robots
+            .into_iter()
+            .map(|(name, ur)| (name, ur.close()))
+            .collect()
+    }
+}
+
+/// Result of a worker thread, or an error if it panicked
+fn join<T>(worker: std::thread::ScopedJoinHandle<'_, Result<T>>) -> Result<T> {
+    worker
+        .join()
+        .unwrap_or(Err(Error::Static("fleet command panicked")))
+}
+
+/// Telemetry from every robot of a fleet, streamed until stopped or dropped
+pub struct TelemetryStream {
+    receiver: Receiver<FleetEvent>,
+    running: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl TelemetryStream {
+    /// Wait for the next event, failing once every robot's stream has ended
+    pub fn recv(&self) -> std::result::Result<FleetEvent, RecvError> {
+        self.receiver.recv()
+    }
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> std::result::Result<FleetEvent, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+    /// Events received so far, without waiting
+    pub fn try_iter(&self) -> impl Iterator<Item = FleetEvent> + '_ {
+        self.receiver.try_iter()
+    }
+    /// Stop streaming and close the RTDE connections
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+    fn shutdown(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("fleet stream thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for TelemetryStream {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Streams one robot's telemetry
+struct StreamWorker {
+    robot: String,
+    config: RobotConfig,
+    variables: Vec<String>,
+    frequency: f64,
+    sender: Sender<FleetEvent>,
+    running: Arc<AtomicBool>,
+}
+
+impl StreamWorker {
+    fn run(self) {
+        if let Err(error) = self.stream() {
+            log::warn!("{} stopped streaming: {error}", self.robot);
+            let _ = self.sender.send(FleetEvent::Failed {
+                robot: self.robot.clone(),
+                error: error.to_string(),
+            });
+        }
+    }
+    fn stream(&self) -> Result<()> {
+        let mut rtde = Rtde::connect(
+            self.config.socket(self.config.ports.rtde),
+            Some(self.config.timeout),
+        )?;
+        let variables: Vec<&str> = self.variables.iter().map(String::as_str).collect();
+        let recipe = rtde.setup_output(&variables, self.frequency)?;
+        if let Some(index) = recipe
+            .get_types()
+            .iter()
+            .position(|var_type| *var_type == DataType::NotFound)
+        {
+            return Err(Error::UnexpectedResponse(format!(
+                "output variable not found: {}",
+                variables[index]
+            )));
+        }
+        rtde.start()?;
+        let mut values = vec![Value::U8(0); variables.len()];
+        while self.running.load(Ordering::Relaxed) {
+            if !rtde.read_values_into(&mut values)? {
+                continue;
+            }
+            let sample = Telemetry {
+                robot: self.robot.clone(),
+                received: SystemTime::now(),
+                values: values.clone(),
+            };
+            if self.sender.send(FleetEvent::Sample(sample)).is_err() {
+                break;
+            }
+        }
+        rtde.pause()?;
+        rtde.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physical::tests::closed_ports;
+    use std::net::Ipv4Addr;
+
+    /// Robot connecting lazily to ports nothing listens on, so every command fails
+    fn unreachable_robot() -> UniversalRobot {
+        UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(closed_ports())
+            .lazy(true)
+            .connect()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_run_isolates_failures() {
+        let mut fleet = Fleet::new();
+        fleet.add("left", unreachable_robot());
+        fleet.add("right", unreachable_robot());
+        assert_eq!(fleet.names().collect::<Vec<_>>(), ["left", "right"]);
+        let results = fleet.stop();
+        assert_eq!(results.len(), 2);
+        assert!(results.values().all(|result| matches!(
+            result,
+            Err(Error::Unavailable(crate::Interface::Dashboard, _))
+        )));
+        let status = fleet.status();
+        assert_eq!(status.failed(), ["left", "right"]);
+        assert!(fleet.close().values().all(Result::is_ok));
+    }
+    #[test]
+    fn test_stream_failures() {
+        let mut fleet = Fleet::new();
+        fleet.add("left", unreachable_robot());
+        fleet.add("right", unreachable_robot());
+        let stream = fleet.stream(&["timestamp"], 125.0).unwrap();
+        let mut failed: Vec<String> = (0..2)
+            .map(
+                |_| match stream.recv_timeout(Duration::from_secs(5)).unwrap() {
+                    FleetEvent::Failed { robot, .. } => robot,
+                    other => panic!("unexpected {other:?}"),
+                },
+            )
+            .collect();
+        failed.sort();
+        assert_eq!(failed, ["left", "right"]);
+        // every robot's stream has ended
+        assert!(stream.recv().is_err());
+        stream.stop();
+    }
+}
@@ -0,0 +1,257 @@
+//! Robot identity
+//!
+//! Serial number, model and software versions of the connected robot, queried once on connect
+//! and used to pick the kinematics and to gate dashboard commands that older software lacks.
+
+use std::f64::consts::FRAC_PI_2;
+
+use crate::prelude::*;
+use crate::types::Version;
+use crate::Rtde;
+
+/// Robot arm model
+#[derive(Debug, Clone, PartialEq)]
+pub enum RobotModel {
+    UR3,
+    UR5,
+    UR10,
+    UR3e,
+    UR5e,
+    UR7e,
+    UR10e,
+    UR12e,
+    UR16e,
+    UR15,
+    UR20,
+    UR30,
+    /// Model name reported by the robot that isn't known to this crate
+    Unknown(String),
+}
+
+impl RobotModel {
+    /// Parse the `get robot model` response, which leaves out the e-Series suffix
+    pub fn from_dashboard(model: &str, generation: ControllerGeneration) -> Self {
+        let model = model.trim().to_uppercase();
+        let size = model
+            .trim_start_matches("UR")
+            .trim_end_matches('E')
+            .parse::<u32>();
+        match (size, generation) {
+            (Ok(3), ControllerGeneration::CB3) => RobotModel::UR3,
+            (Ok(5), ControllerGeneration::CB3) => RobotModel::UR5,
+            (Ok(10), ControllerGeneration::CB3) => RobotModel::UR10,
+            (Ok(3), ControllerGeneration::ESeries) => RobotModel::UR3e,
+            (Ok(5), ControllerGeneration::ESeries) => RobotModel::UR5e,
+            (Ok(7), ControllerGeneration::ESeries) => RobotModel::UR7e,
+            (Ok(10), ControllerGeneration::ESeries) => RobotModel::UR10e,
+            (Ok(12), ControllerGeneration::ESeries) => RobotModel::UR12e,
+            (Ok(16), ControllerGeneration::ESeries) => RobotModel::UR16e,
+            (Ok(15), ControllerGeneration::ESeries) => RobotModel::UR15,
+            (Ok(20), ControllerGeneration::ESeries) => RobotModel::UR20,
+            (Ok(30), ControllerGeneration::ESeries) => RobotModel::UR30,
+            _ => RobotModel::Unknown(model),
+        }
+    }
+    /// Nominal Denavit-Hartenberg parameters, without the per-robot calibration offsets
+    pub fn dh_parameters(&self) -> Option<DhParameters> {
+        // (d1, a2, a3, d4, d5, d6) \[m\]
+        let (d1, a2, a3, d4, d5, d6) = match self {
+            RobotModel::UR3 => (0.1519, -0.24365, -0.21325, 0.11235, 0.08535, 0.0819),
+            RobotModel::UR5 => (0.089159, -0.425, -0.39225, 0.10915, 0.09465, 0.0823),
+            RobotModel::UR10 => (0.1273, -0.612, -0.5723, 0.163941, 0.1157, 0.0922),
+            RobotModel::UR3e => (0.15185, -0.24355, -0.2132, 0.13105, 0.08535, 0.0921),
+            RobotModel::UR5e | RobotModel::UR7e => {
+                (0.1625, -0.425, -0.3922, 0.1333, 0.0997, 0.0996)
+            }
+            RobotModel::UR10e | RobotModel::UR12e => {
+                (0.1807, -0.6127, -0.57155, 0.17415, 0.11985, 0.11655)
+            }
+            RobotModel::UR16e => (0.1807, -0.4784, -0.36, 0.17415, 0.11985, 0.11655),
+            RobotModel::UR20 => (0.2363, -0.862, -0.7287, 0.201, 0.1593, 0.1543),
+            RobotModel::UR30 => (0.2363, -0.637, -0.5037, 0.201, 0.1593, 0.1543),
+            RobotModel::UR15 | RobotModel::Unknown(_) => return None,
+        };
+        Some(DhParameters {
+            a: [0.0, a2, a3, 0.0, 0.0, 0.0],
+            d: [d1, 0.0, 0.0, d4, d5, d6],
+            alpha: [FRAC_PI_2, 0.0, 0.0, FRAC_PI_2, -FRAC_PI_2, 0.0],
+        })
+    }
+}
+
+/// Denavit-Hartenberg parameters of the six joints, lengths \[m\] and angles \[rad\]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DhParameters {
+    pub a: [f64; 6],
+    pub d: [f64; 6],
+    pub alpha: [f64; 6],
+}
+
+/// Control box hardware generation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControllerGeneration {
+    CB3,
+    ESeries,
+}
+
+/// Teach pendant software family
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolyScope {
+    /// PolyScope 3.x on CB3 and 5.x on e-Series
+    Classic,
+    /// PolyScope X, software 10.x
+    X,
+}
+
+/// Dashboard features that depend on the software version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    SafetyStatus,
+    RemoteControlQuery,
+    OperationalModeQuery,
+    SerialAndModel,
+    FlightReport,
+}
+
+impl Feature {
+    /// First e-Series software version with the feature
+    pub fn since(&self) -> Version {
+        let minor = match self {
+            Feature::SafetyStatus => 4,
+            Feature::RemoteControlQuery
+            | Feature::OperationalModeQuery
+            | Feature::SerialAndModel => 6,
+            Feature::FlightReport => 8,
+        };
+        Version {
+            major: 5,
+            minor,
+            ..Version::default()
+        }
+    }
+}
+
+/// Identity of the connected robot
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotIdentity {
+    /// Only reported from 5.6.0
+    pub serial: Option<String>,
+    pub model: RobotModel,
+    /// PolyScope software version from the dashboard server
+    pub software: Version,
+    /// URControl version from RTDE
+    pub control: Version,
+    pub generation: ControllerGeneration,
+    pub polyscope: PolyScope,
+}
+
+impl RobotIdentity {
+    /// Query the dashboard server and RTDE for the robot identity
+    ///
+    /// Either interface may be unavailable, its versions are then left at zero, along with the
+    /// serial and model when the dashboard server is unavailable.
+    pub fn query(dashboard: &mut Dashboard, rtde: &mut Rtde) -> Result<Self> {
+        let control = rtde.get_ur_version();
+        let software = dashboard.get_software_version();
+        let (control, software) = match (control, software) {
+            (Err(error @ Error::Unavailable(..)), Err(Error::Unavailable(..))) => {
+                return Err(error)
+            }
+            (Err(Error::Unavailable(..)), software) => (Version::default(), software?),
+            (control, Err(Error::Unavailable(..))) => (control?, Version::default()),
+            (control, software) => (control?, software?),
+        };
+        // CB3 runs 3.x of both, e-Series 5.x of URControl under PolyScope 5.x or 10.x
+        let generation = match control.major.max(software.major) {
+            ..=3 => ControllerGeneration::CB3,
+            _ => ControllerGeneration::ESeries,
+        };
+        let mut identity = RobotIdentity {
+            serial: None,
+            model: RobotModel::Unknown(String::new()),
+            software,
+            control,
+            generation,
+            polyscope: match software.major {
+                10.. => PolyScope::X,
+                _ => PolyScope::Classic,
+            },
+        };
+        if identity.supports(Feature::SerialAndModel) {
+            identity.serial = Some(dashboard.get_serial()?.trim().to_owned());
+            identity.model = RobotModel::from_dashboard(&dashboard.get_model()?, generation);
+        }
+        Ok(identity)
+    }
+    /// Is the feature available on this robot's software
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.generation == ControllerGeneration::ESeries && self.software >= feature.since()
+    }
+    /// Nominal kinematics of this robot model
+    pub fn dh_parameters(&self) -> Option<DhParameters> {
+        self.model.dh_parameters()
+    }
+}
+
+impl UniversalRobot {
+    /// Identity of the robot, queried when connecting, or on first use when connecting lazily
+    pub fn identity(&mut self) -> Result<&RobotIdentity> {
+        if self.identity.is_none() {
+            self.identity = Some(RobotIdentity::query(&mut self.dashboard, &mut self.rtde)?);
+        }
+        Ok(self.identity.as_ref().expect("queried above"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(software: &str) -> RobotIdentity {
+        RobotIdentity {
+            serial: None,
+            model: RobotModel::UR5e,
+            software: software.parse().unwrap(),
+            control: software.parse().unwrap(),
+            generation: ControllerGeneration::ESeries,
+            polyscope: PolyScope::Classic,
+        }
+    }
+
+    #[test]
+    fn test_model_from_dashboard() {
+        let cases = [
+            ("UR5", ControllerGeneration::CB3, RobotModel::UR5),
+            ("ur5", ControllerGeneration::ESeries, RobotModel::UR5e),
+            ("UR10e", ControllerGeneration::ESeries, RobotModel::UR10e),
+            ("UR20", ControllerGeneration::ESeries, RobotModel::UR20),
+            (
+                "UR7",
+                ControllerGeneration::CB3,
+                RobotModel::Unknown("UR7".into()),
+            ),
+        ];
+        for (model, generation, expected) in cases {
+            assert_eq!(RobotModel::from_dashboard(model, generation), expected);
+        }
+    }
+    #[test]
+    fn test_feature_gates() {
+        assert!(!identity("5.3.1").supports(Feature::SafetyStatus));
+        assert!(identity("5.4.0").supports(Feature::SafetyStatus));
+        assert!(!identity("5.5.1.1234").supports(Feature::RemoteControlQuery));
+        assert!(identity("5.12.2.1101534").supports(Feature::FlightReport));
+        let cb3 = RobotIdentity {
+            generation: ControllerGeneration::CB3,
+            ..identity("3.15.7")
+        };
+        assert!(!cb3.supports(Feature::SafetyStatus));
+    }
+    #[test]
+    fn test_dh_parameters() {
+        let dh = RobotModel::UR5e.dh_parameters().unwrap();
+        assert_eq!(dh.a[1], -0.425);
+        assert_eq!(dh.d[0], 0.1625);
+        assert!(RobotModel::Unknown("UR99".into()).dh_parameters().is_none());
+    }
+}
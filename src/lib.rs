@@ -4,12 +4,28 @@
 #[cfg(test)]
 mod test;
 
-mod dashboard;
+pub mod audit;
+pub mod columnar;
+pub mod config;
+pub mod dashboard;
+pub mod fleet;
+pub mod identity;
+pub mod motion;
 mod physical;
-mod rolling_buffer;
+pub mod program_file;
+pub mod recorder;
+pub mod rolling_buffer;
 mod rtde;
+pub mod sequencer;
+pub mod shared_buffer;
+pub mod sync_start;
+pub mod urscript;
+pub mod watcher;
 
+pub use physical::{Interface, InterfaceStatus, UniversalRobotBuilder};
+pub use rtde::capture;
 pub use rtde::data;
+pub use rtde::health;
 pub use rtde::types;
 use rtde::types::PackageType;
 pub use rtde::Rtde;
@@ -32,6 +48,8 @@ pub enum Error {
     UnexpectedResponse(String),
     #[error("Connection closed unexpectedly")]
     ConnectionLost,
+    #[error("{0} interface unavailable: {1}")]
+    Unavailable(Interface, String),
     #[error("Exceeded maximum read attempts for package, expected {0:?}")]
     MaxReads(PackageType),
     #[error("timeout powering on. {0:?} after {1:?}")]
@@ -40,6 +58,14 @@ pub enum Error {
     Serialization(String),
     #[error("Deserialization error: {0}")]
     Deserialization(String),
+    #[error("Invalid URScript: {0}")]
+    Script(String),
+    #[error(transparent)]
+    Dashboard(#[from] dashboard::response::DashboardError),
+    #[error(transparent)]
+    Motion(#[from] motion::MotionError),
+    #[error(transparent)]
+    Policy(#[from] dashboard::policy::PolicyError),
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
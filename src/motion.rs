@@ -0,0 +1,538 @@
+//! High level motion commands for the Universal Robot
+//!
+//! Moves are rendered into a URScript program, sent to the primary interface, and followed
+//! to completion on the RTDE stream by watching `runtime_state`, `safety_mode` and `actual_q`.
+//!
+//! ```ignore
+//! ur.move_joints(Vec6::new(0.0, -1.57, 1.57, -1.57, -1.57, 0.0), 1.05, 1.4)?;
+//!
+//! let motion = Motion::new()
+//!     .tcp(Vec6::new(0.0, 0.0, 0.15, 0.0, 0.0, 0.0))
+//!     .then(Move::linear(Target::Pose(pose_1)).blend(0.05))
+//!     .then(Move::linear(Target::Pose(pose_2)));
+//! ur.execute(&motion, Duration::from_secs(30))?;
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ToolConfig;
+use crate::dashboard::types::SafetyStatus;
+use crate::data::{Value, Vec6};
+use crate::prelude::*;
+use crate::types::RuntimeState;
+use crate::urscript::{Block, Call, Expr, Program};
+use crate::Rtde;
+
+/// RTDE outputs set up by the motion commands when no output recipe has been configured yet.
+pub const MOTION_OUTPUTS: [&str; 4] = ["timestamp", "actual_q", "runtime_state", "safety_mode"];
+
+/// Why a move didn't complete
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum MotionError {
+    #[error("protective stop during move")]
+    ProtectiveStop,
+    #[error("safety stop during move: {0:?}")]
+    SafetyStop(SafetyStatus),
+    #[error("program did not start within {0:?}")]
+    NotStarted(Duration),
+    #[error("move did not finish within {0:?}")]
+    Timeout(Duration),
+    #[error("move stopped {0:.4} rad from its target")]
+    TargetNotReached(f64),
+    #[error("output recipe is missing '{0}', which is needed to follow the move")]
+    MissingOutput(&'static str),
+    #[error("{0} was not ready to start within {1:?}")]
+    NotReady(String, Duration),
+}
+
+/// Where to move to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Joint positions \[rad\]
+    Joints(Vec6),
+    /// Tool pose in base coordinates \[m, rad\]
+    Pose(Vec6),
+}
+
+impl From<Target> for Expr {
+    fn from(target: Target) -> Self {
+        match target {
+            Target::Joints(q) => Expr::joints(q),
+            Target::Pose(pose) => Expr::pose(pose),
+        }
+    }
+}
+
+/// Path followed by a move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveKind {
+    /// `movej`: linear in joint-space
+    Joint(Target),
+    /// `movel`: linear in tool-space
+    Linear(Target),
+    /// `movep`: constant tool speed with circular blends
+    Process(Target),
+    /// `movec`: circular arc through `via`, ending at `to`
+    Circular {
+        via: Target,
+        to: Target,
+        mode: CircleMode,
+    },
+}
+
+/// Orientation handling of a circular move
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CircleMode {
+    /// Orientation interpolated from the start to the end pose, `via` orientation ignored
+    #[default]
+    Unconstrained = 0,
+    /// Orientation kept constant relative to the circle tangent
+    Fixed = 1,
+}
+
+/// A single motion command with its speed, acceleration, time and blend options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub kind: MoveKind,
+    /// rad/s for joint moves, m/s otherwise
+    pub speed: f64,
+    /// rad/s² for joint moves, m/s² otherwise
+    pub accel: f64,
+    /// Time to take over the move \[s\], overrides speed and acceleration
+    pub time: Option<f64>,
+    /// Blend radius into the next move \[m\]
+    pub blend: Option<f64>,
+}
+
+impl Move {
+    /// Controller defaults for joint moves
+    const JOINT_SPEED: f64 = 1.05;
+    const JOINT_ACCEL: f64 = 1.4;
+    /// Controller defaults for tool moves
+    const TOOL_SPEED: f64 = 0.25;
+    const TOOL_ACCEL: f64 = 1.2;
+
+    fn new(kind: MoveKind, speed: f64, accel: f64) -> Self {
+        Move {
+            kind,
+            speed,
+            accel,
+            time: None,
+            blend: None,
+        }
+    }
+    /// `movej` to the target
+    pub fn joint(target: Target) -> Self {
+        Self::new(
+            MoveKind::Joint(target),
+            Self::JOINT_SPEED,
+            Self::JOINT_ACCEL,
+        )
+    }
+    /// `movel` to the target
+    pub fn linear(target: Target) -> Self {
+        Self::new(MoveKind::Linear(target), Self::TOOL_SPEED, Self::TOOL_ACCEL)
+    }
+    /// `movep` to the target
+    pub fn process(target: Target) -> Self {
+        Self::new(
+            MoveKind::Process(target),
+            Self::TOOL_SPEED,
+            Self::TOOL_ACCEL,
+        )
+    }
+    /// `movec` through `via` to `to`
+    pub fn circular(via: Target, to: Target, mode: CircleMode) -> Self {
+        Self::new(
+            MoveKind::Circular { via, to, mode },
+            Self::TOOL_SPEED,
+            Self::TOOL_ACCEL,
+        )
+    }
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+    pub fn accel(mut self, accel: f64) -> Self {
+        self.accel = accel;
+        self
+    }
+    /// Ignored by `movep` and `movec`
+    pub fn time(mut self, seconds: f64) -> Self {
+        self.time = Some(seconds);
+        self
+    }
+    pub fn blend(mut self, radius: f64) -> Self {
+        self.blend = Some(radius);
+        self
+    }
+    /// Final target of this move
+    pub fn target(&self) -> Target {
+        match self.kind {
+            MoveKind::Joint(target) | MoveKind::Linear(target) | MoveKind::Process(target) => {
+                target
+            }
+            MoveKind::Circular { to, .. } => to,
+        }
+    }
+    /// URScript call for this move
+    pub fn to_call(&self) -> Call {
+        let call = match self.kind {
+            MoveKind::Joint(target) => Call::new("movej").arg(target),
+            MoveKind::Linear(target) => Call::new("movel").arg(target),
+            MoveKind::Process(target) => Call::new("movep").arg(target),
+            MoveKind::Circular { via, to, .. } => Call::new("movec").arg(via).arg(to),
+        };
+        let mut call = call.kwarg("a", self.accel).kwarg("v", self.speed);
+        if let (Some(time), MoveKind::Joint(_) | MoveKind::Linear(_)) = (self.time, self.kind) {
+            call = call.kwarg("t", time);
+        }
+        call = call.kwarg("r", self.blend.unwrap_or(0.0));
+        if let MoveKind::Circular { mode, .. } = self.kind {
+            call = call.kwarg("mode", mode as i32);
+        }
+        call
+    }
+}
+
+/// Mass carried by the tool, set with URScript's `set_payload`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolPayload {
+    /// \[kg\]
+    pub mass: f64,
+    /// Center of gravity from the tool flange \[m\], the TCP if not set
+    #[serde(default)]
+    pub center_of_gravity: Option<[f64; 3]>,
+}
+
+/// A sequence of moves run as one URScript program, so blends carry from one move into the next
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Motion {
+    pub moves: Vec<Move>,
+    /// Tool center point offset from the tool flange, set before moving
+    pub tcp: Option<Vec6>,
+    /// Payload, set before moving
+    pub payload: Option<ToolPayload>,
+}
+
+impl Motion {
+    /// Time allowed for the single-move helpers to complete
+    pub const TIMEOUT: Duration = Duration::from_secs(60);
+    /// Name of the URScript function the motion is sent as
+    const PROGRAM_NAME: &'static str = "rust_motion";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Append a move to the sequence
+    pub fn then(mut self, next: Move) -> Self {
+        self.moves.push(next);
+        self
+    }
+    /// Set the tool center point for the whole sequence
+    pub fn tcp(mut self, tcp: Vec6) -> Self {
+        self.tcp = Some(tcp);
+        self
+    }
+    /// Set the payload for the whole sequence
+    pub fn payload(mut self, payload: ToolPayload) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+    /// Build the URScript program for this motion
+    pub fn to_program(&self) -> Program {
+        Program::new(Self::PROGRAM_NAME).body(self.to_block())
+    }
+    /// The program's statements, to run after others of the caller's
+    pub(crate) fn to_block(&self) -> Block {
+        let mut body = Block::new();
+        if let Some(tcp) = self.tcp {
+            body = body.call(Call::new("set_tcp").arg(Expr::pose(tcp)));
+        }
+        if let Some(payload) = self.payload {
+            let mut call = Call::new("set_payload").arg(payload.mass);
+            if let Some(cog) = payload.center_of_gravity {
+                call = call.arg(Expr::list(cog));
+            }
+            body = body.call(call);
+        }
+        for step in &self.moves {
+            body = body.call(step.to_call());
+        }
+        body
+    }
+    /// This motion, with the tool's TCP and payload where the motion doesn't set them
+    pub(crate) fn with_tool(&self, tool: &ToolConfig) -> Motion {
+        Motion {
+            tcp: self.tcp.or(tool.tcp()),
+            payload: self.payload.or(tool.payload),
+            ..self.clone()
+        }
+    }
+    /// Joint positions the motion should finish at, if the last move is given in joint-space
+    fn final_joints(&self) -> Option<Vec6> {
+        match self.moves.last()?.target() {
+            Target::Joints(q) => Some(q),
+            Target::Pose(_) => None,
+        }
+    }
+}
+
+/// Follows the progress of a motion program on the RTDE stream
+struct MotionMonitor {
+    actual_q: usize,
+    runtime_state: usize,
+    safety_mode: usize,
+}
+
+impl MotionMonitor {
+    /// Time allowed between sending a program and the controller reporting it as playing
+    const START_TIMEOUT: Duration = Duration::from_secs(2);
+    /// Largest joint error accepted at the end of a joint-space move \[rad\]
+    const JOINT_TOLERANCE: f64 = 1e-3;
+
+    /// Locate the variables needed in the output recipe, setting up and starting one if there is none.
+    fn new(rtde: &mut Rtde) -> Result<Self> {
+        if rtde.output_types().is_empty() {
+            rtde.setup_output(&MOTION_OUTPUTS, 125.0)?;
+        }
+        if !rtde.is_streaming() {
+            rtde.start()?;
+        }
+        let index = |name: &'static str| {
+            rtde.output_index(name)
+                .ok_or(Error::Motion(MotionError::MissingOutput(name)))
+        };
+        Ok(MotionMonitor {
+            actual_q: index("actual_q")?,
+            runtime_state: index("runtime_state")?,
+            safety_mode: index("safety_mode")?,
+        })
+    }
+    /// Block until the program has played and stopped, or fail on a safety stop or timeout.
+    ///
+    /// Samples buffered before the program was sent, or a program that was still running,
+    /// can report playing before this program does, so playing only counts once the stream
+    /// has reported stopped since the send.
+    fn wait(&self, rtde: &mut Rtde, target: Option<Vec6>, timeout: Duration) -> Result<()> {
+        let types = rtde.output_types().to_vec();
+        let now = Instant::now();
+        let mut stopped = false;
+        let mut started = false;
+        loop {
+            let package = rtde.read()?;
+            if !package.is_data() {
+                continue;
+            }
+            let values = package.values(&types)?;
+            let safety_mode = match values[self.safety_mode] {
+                Value::I32(mode) => SafetyStatus::try_from(mode)?,
+                other => return Err(Error::UnexpectedResponse(format!("safety_mode {other:?}"))),
+            };
+            match safety_mode {
+                SafetyStatus::Normal | SafetyStatus::Reduced => {}
+                SafetyStatus::ProtectiveStop => return Err(MotionError::ProtectiveStop.into()),
+                other => return Err(MotionError::SafetyStop(other).into()),
+            }
+            let runtime_state = match values[self.runtime_state] {
+                Value::U32(state) => RuntimeState::try_from(state)?,
+                other => {
+                    return Err(Error::UnexpectedResponse(format!(
+                        "runtime_state {other:?}"
+                    )))
+                }
+            };
+            match runtime_state {
+                RuntimeState::Stopped if started => break,
+                RuntimeState::Stopped => stopped = true,
+                RuntimeState::Playing if stopped => started = true,
+                _ => {}
+            }
+            if !started && now.elapsed() > Self::START_TIMEOUT {
+                return Err(MotionError::NotStarted(Self::START_TIMEOUT).into());
+            }
+            if now.elapsed() > timeout {
+                return Err(MotionError::Timeout(timeout).into());
+            }
+        }
+        let Some(target) = target else {
+            return Ok(());
+        };
+        // the program has stopped, so the latest sample holds the resting position
+        let package = loop {
+            let package = rtde.read()?;
+            if package.is_data() {
+                break package;
+            }
+        };
+        let Some(actual) = package.values(&types)?[self.actual_q].as_vec6() else {
+            return Err(Error::UnexpectedResponse(
+                "actual_q is not a vector".to_owned(),
+            ));
+        };
+        let error = max_joint_error(&actual, &target);
+        if error > Self::JOINT_TOLERANCE {
+            return Err(MotionError::TargetNotReached(error).into());
+        }
+        Ok(())
+    }
+}
+
+fn max_joint_error(actual: &Vec6, target: &Vec6) -> f64 {
+    [
+        actual.x - target.x,
+        actual.y - target.y,
+        actual.z - target.z,
+        actual.rx - target.rx,
+        actual.ry - target.ry,
+        actual.rz - target.rz,
+    ]
+    .iter()
+    .fold(0.0, |max, diff| diff.abs().max(max))
+}
+
+impl UniversalRobot {
+    /// Move to joint positions, linear in joint-space, and wait for the move to complete.
+    ///
+    /// Speed in rad/s and acceleration in rad/s².
+    pub fn move_joints(&mut self, q: Vec6, speed: f64, accel: f64) -> Result<()> {
+        let motion = Motion::new().then(Move::joint(Target::Joints(q)).speed(speed).accel(accel));
+        self.execute(&motion, Motion::TIMEOUT)
+    }
+    /// Move the tool linearly to a pose, and wait for the move to complete.
+    ///
+    /// Speed in m/s and acceleration in m/s².
+    pub fn move_linear(&mut self, pose: Vec6, speed: f64, accel: f64) -> Result<()> {
+        let motion = Motion::new().then(Move::linear(Target::Pose(pose)).speed(speed).accel(accel));
+        self.execute(&motion, Motion::TIMEOUT)
+    }
+    /// Run a motion program on the robot and wait for it to complete.
+    ///
+    /// Uses the active RTDE output recipe to follow the motion, which must include
+    /// `actual_q`, `runtime_state` and `safety_mode`. If no output recipe has been set up,
+    /// [`MOTION_OUTPUTS`] is set up and started.
+    ///
+    /// The TCP and payload of the robot's [`RobotConfig`](crate::config::RobotConfig) are used
+    /// when the motion doesn't set its own.
+    ///
+    /// Any program already running on the robot is stopped.
+    pub fn execute(&mut self, motion: &Motion, timeout: Duration) -> Result<()> {
+        let motion = motion.with_tool(&self.config().tool);
+        let script = motion.to_program().render()?;
+        let monitor = MotionMonitor::new(&mut self.rtde)?;
+        self.send_script(&script)?;
+        monitor.wait(&mut self.rtde, motion.final_joints(), timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::DataType;
+    use crate::rtde::as_bytes;
+
+    /// Stream of [`MOTION_OUTPUTS`] samples of the joint positions, runtime state and safety mode
+    fn stream(samples: &[(f64, RuntimeState, i32)]) -> Rtde {
+        let recipe = [
+            ("timestamp", DataType::F64),
+            ("actual_q", DataType::Vec6),
+            ("runtime_state", DataType::U32),
+            ("safety_mode", DataType::I32),
+        ];
+        let payloads = samples
+            .iter()
+            .enumerate()
+            .map(|(index, (q, state, safety))| {
+                let q = Vec6::new(*q, 0.0, 0.0, 0.0, 0.0, 0.0);
+                as_bytes((index as f64 * 0.008, q, *state as u32, *safety)).unwrap()
+            })
+            .collect();
+        Rtde::streaming(&recipe, payloads)
+    }
+
+    fn render(step: Move) -> String {
+        Program::new("test")
+            .body(Block::new().call(step.to_call()))
+            .render()
+            .unwrap()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .trim()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_render_joint_move() {
+        let step = Move::joint(Target::Joints(Vec6::new(
+            0.0, -1.57, 1.57, -1.57, -1.57, 0.5,
+        )))
+        .speed(0.5)
+        .accel(1.0);
+        assert_eq!(
+            render(step),
+            "movej([0.0, -1.57, 1.57, -1.57, -1.57, 0.5], a=1.0, v=0.5, r=0.0)"
+        );
+    }
+    #[test]
+    fn test_render_options() {
+        let pose = Vec6::new(-0.12, -0.43, 0.14, 0.0, 3.11, 0.04);
+        assert_eq!(
+            render(Move::linear(Target::Pose(pose)).time(2.0).blend(0.01)),
+            "movel(p[-0.12, -0.43, 0.14, 0.0, 3.11, 0.04], a=1.2, v=0.25, t=2.0, r=0.01)"
+        );
+        // movep has no time argument
+        assert_eq!(
+            render(Move::process(Target::Pose(pose)).time(2.0)),
+            "movep(p[-0.12, -0.43, 0.14, 0.0, 3.11, 0.04], a=1.2, v=0.25, r=0.0)"
+        );
+        assert_eq!(
+            render(Move::circular(Target::Pose(pose), Target::Pose(pose), CircleMode::Fixed)),
+            "movec(p[-0.12, -0.43, 0.14, 0.0, 3.11, 0.04], p[-0.12, -0.43, 0.14, 0.0, 3.11, 0.04], a=1.2, v=0.25, r=0.0, mode=1)"
+        );
+    }
+    #[test]
+    fn test_render_program() {
+        let motion = Motion::new()
+            .tcp(Vec6::new(0.0, 0.0, 0.1, 0.0, 0.0, 0.0))
+            .payload(ToolPayload {
+                mass: 1.5,
+                center_of_gravity: Some([0.0, 0.0, 0.05]),
+            })
+            .then(Move::joint(Target::Joints(Vec6::default())));
+        assert_eq!(
+            motion.to_program().render().unwrap(),
+            "def rust_motion():\n  set_tcp(p[0.0, 0.0, 0.1, 0.0, 0.0, 0.0])\n  set_payload(1.5, [0.0, 0.0, 0.05])\n  movej([0.0, 0.0, 0.0, 0.0, 0.0, 0.0], a=1.4, v=1.05, r=0.0)\nend\n"
+        );
+        assert_eq!(motion.final_joints(), Some(Vec6::default()));
+    }
+    #[test]
+    fn test_wait_ignores_stale_playing() {
+        use RuntimeState::*;
+        // a program still playing from before, then this one moving from 0 to 1
+        let mut rtde = stream(&[
+            (0.0, Playing, 1),
+            (0.0, Stopped, 1),
+            (0.5, Playing, 1),
+            (1.0, Stopped, 1),
+            (1.0, Stopped, 1),
+        ]);
+        let monitor = MotionMonitor::new(&mut rtde).unwrap();
+        let target = Vec6::new(1.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        assert!(monitor
+            .wait(&mut rtde, Some(target), Motion::TIMEOUT)
+            .is_ok());
+    }
+    #[test]
+    fn test_wait_safety_stop() {
+        let mut rtde = stream(&[(0.0, RuntimeState::Stopped, 11)]);
+        let monitor = MotionMonitor::new(&mut rtde).unwrap();
+        assert!(matches!(
+            monitor.wait(&mut rtde, None, Motion::TIMEOUT),
+            Err(Error::Motion(MotionError::SafetyStop(
+                SafetyStatus::ValidateJointId
+            )))
+        ));
+    }
+}
@@ -1,11 +1,17 @@
 //! Connection layer between the robot and this code API
 
+use std::fmt;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::net::SocketAddr;
 use std::net::{IpAddr, TcpStream};
 
+use crate::audit::AuditLog;
+use crate::config::{Ports, RobotConfig};
+use crate::identity::RobotIdentity;
 use crate::prelude::*;
+use crate::types::Recipe;
+use crate::watcher::StateWatcher;
 use crate::Rtde;
 
 /// Universal Robot Remote Control Interface
@@ -15,24 +21,257 @@ use crate::Rtde;
 ///
 /// - Dashboard - basic commands
 /// - RTDE (Real-time data exchange) - high speed custom data
+///
+/// Interfaces that are disabled, or that couldn't be connected, return
+/// [`Error::Unavailable`](crate::Error::Unavailable) when used while the others keep working,
+/// see [`UniversalRobot::status`].
 pub struct UniversalRobot {
     pub dashboard: Dashboard,
-    primary: UrPort,
-    secondary: UrPort,
+    primary: Connection,
+    secondary: Connection,
     pub rtde: Rtde,
+    pub watcher: StateWatcher,
+    pub(crate) identity: Option<RobotIdentity>,
+    config: RobotConfig,
+    pub(crate) audit: Option<AuditLog>,
+}
+
+/// A robot's network interfaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    Dashboard,
+    Primary,
+    Secondary,
+    Rtde,
+}
+
+impl Interface {
+    pub const ALL: [Interface; 4] = [
+        Interface::Dashboard,
+        Interface::Primary,
+        Interface::Secondary,
+        Interface::Rtde,
+    ];
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Interface::Dashboard => "dashboard",
+            Interface::Primary => "primary",
+            Interface::Secondary => "secondary",
+            Interface::Rtde => "RTDE",
+        })
+    }
+}
+
+/// Connection state of an interface
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterfaceStatus {
+    Connected,
+    /// Connected on first use
+    Pending,
+    /// The last attempt to connect failed, or the connection was lost, it is retried on next use
+    Unavailable(String),
+    Disabled,
+}
+
+/// Chooses which interfaces of a robot to open and when
+///
+/// ```ignore
+/// // RTDE is disabled in this installation's security settings
+/// let mut ur = UniversalRobot::builder(address).rtde(false).lazy(true).connect()?;
+/// ur.dashboard.power(true)?; // the dashboard server is connected here
+/// ```
+#[derive(Debug, Clone)]
+pub struct UniversalRobotBuilder {
+    config: RobotConfig,
+}
+
+impl UniversalRobotBuilder {
+    pub fn new(config: RobotConfig) -> Self {
+        UniversalRobotBuilder { config }
+    }
+    /// Read and write timeout of every interface
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+    /// Time allowed to open each connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+    pub fn ports(mut self, ports: Ports) -> Self {
+        self.config.ports = ports;
+        self
+    }
+    pub fn dashboard(mut self, enabled: bool) -> Self {
+        self.config.interfaces.dashboard = enabled;
+        self
+    }
+    pub fn primary(mut self, enabled: bool) -> Self {
+        self.config.interfaces.primary = enabled;
+        self
+    }
+    pub fn secondary(mut self, enabled: bool) -> Self {
+        self.config.interfaces.secondary = enabled;
+        self
+    }
+    pub fn rtde(mut self, enabled: bool) -> Self {
+        self.config.interfaces.rtde = enabled;
+        self
+    }
+    /// Connect each interface on its first use rather than in [`connect`](Self::connect)
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.config.interfaces.lazy = lazy;
+        self
+    }
+    /// Open the enabled interfaces and query the robot identity, unless connecting lazily.
+    ///
+    /// An interface that can't be connected is reported by [`UniversalRobot::status`] instead of
+    /// failing the connection, which only fails if none of the enabled interfaces could be
+    /// connected. The config's RTDE output recipe is started if RTDE is available. Failing to
+    /// query the identity or start the recipe is logged, they can be retried once connected.
+    pub fn connect(self) -> Result<UniversalRobot> {
+        let config = self.config;
+        let ports = config.ports;
+        let interfaces = config.interfaces;
+        let connection = |enabled: bool, interface: Interface, port: u16| {
+            let endpoint = config.endpoint(port);
+            match (enabled, interfaces.lazy) {
+                (false, _) => Connection::Disabled,
+                (true, true) => Connection::Pending(endpoint),
+                (true, false) => match endpoint.open() {
+                    Ok(port) => Connection::Open(port),
+                    Err(error) => {
+                        log::warn!("{interface} interface unavailable: {error}");
+                        Connection::Unavailable(endpoint, error.to_string())
+                    }
+                },
+            }
+        };
+        // a server that accepts the connection but fails the greeting is unavailable too
+        let unavailable = |interface: Interface, port: u16, error: Error| {
+            log::warn!("{interface} interface unavailable: {error}");
+            Connection::Unavailable(config.endpoint(port), error.to_string())
+        };
+        let dashboard = connection(interfaces.dashboard, Interface::Dashboard, ports.dashboard);
+        let mut dashboard = Dashboard::with_connection(dashboard).or_else(|error| {
+            Dashboard::with_connection(unavailable(Interface::Dashboard, ports.dashboard, error))
+        })?;
+        if let Some(root) = &config.programs_root {
+            dashboard.set_programs_root(root);
+        }
+        let rtde = connection(interfaces.rtde, Interface::Rtde, ports.rtde);
+        let mut ur = UniversalRobot {
+            dashboard,
+            primary: connection(interfaces.primary, Interface::Primary, ports.primary),
+            secondary: connection(interfaces.secondary, Interface::Secondary, ports.secondary),
+            rtde: Rtde::with_connection(rtde).or_else(|error| {
+                Rtde::with_connection(unavailable(Interface::Rtde, ports.rtde, error))
+            })?,
+            watcher: StateWatcher::new(),
+            identity: None,
+            config: config.clone(),
+            audit: None,
+        };
+        if !interfaces.lazy {
+            let statuses = Interface::ALL.map(|interface| ur.status(interface));
+            if !statuses.contains(&InterfaceStatus::Connected) {
+                let reasons: Vec<String> = Interface::ALL
+                    .iter()
+                    .zip(statuses)
+                    .filter_map(|(interface, status)| match status {
+                        InterfaceStatus::Unavailable(reason) => {
+                            Some(format!("{interface}: {reason}"))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                return Err(Error::UnexpectedResponse(format!(
+                    "no interface could be connected ({})",
+                    reasons.join(", ")
+                )));
+            }
+            let connected = |interface| ur.status(interface) == InterfaceStatus::Connected;
+            if connected(Interface::Dashboard) || connected(Interface::Rtde) {
+                match ur.identity() {
+                    Ok(identity) => log::info!("connected to {identity:?}"),
+                    Err(error) => log::warn!("could not identify the robot: {error}"),
+                }
+            }
+        }
+        if let Some(name) = &config.rtde.output {
+            match ur.status(Interface::Rtde) {
+                InterfaceStatus::Connected | InterfaceStatus::Pending => {
+                    if let Err(error) = ur.start_recipe(name) {
+                        log::warn!("could not start RTDE recipe {name}: {error}");
+                    }
+                }
+                _ => log::warn!("not starting RTDE recipe {name}, RTDE is unavailable"),
+            }
+        }
+        Ok(ur)
+    }
 }
 
 impl UniversalRobot {
-    const PRIMARY: u16 = 30001;
-    const SECONDARY: u16 = 30002;
-    /// Connect to universal robot tcp ports
+    /// Connect to universal robot tcp ports and query the robot identity
     pub fn connect(address: IpAddr, timeout: Duration) -> Result<Self> {
-        Ok(UniversalRobot {
-            dashboard: Dashboard::new(address, Some(timeout))?,
-            primary: UrPort::new(address, Some(timeout), Self::PRIMARY)?,
-            secondary: UrPort::new(address, Some(timeout), Self::SECONDARY)?,
-            rtde: Rtde::new(address, Some(timeout))?,
-        })
+        Self::builder(address).timeout(timeout).connect()
+    }
+    /// Choose which interfaces to open and whether to connect them on first use
+    pub fn builder(address: IpAddr) -> UniversalRobotBuilder {
+        UniversalRobotBuilder::new(RobotConfig::new(address))
+    }
+    /// Connect to the interfaces enabled in the config on their configured ports, query the robot
+    /// identity, and start streaming the config's RTDE output recipe if it names one.
+    ///
+    /// See [`UniversalRobotBuilder::connect`].
+    pub fn from_config(config: &RobotConfig) -> Result<Self> {
+        UniversalRobotBuilder::new(config.clone()).connect()
+    }
+    /// Settings the robot was connected with
+    pub fn config(&self) -> &RobotConfig {
+        &self.config
+    }
+    /// Connection state of an interface
+    pub fn status(&self, interface: Interface) -> InterfaceStatus {
+        match interface {
+            Interface::Dashboard => self.dashboard.status(),
+            Interface::Primary => self.primary.status(),
+            Interface::Secondary => self.secondary.status(),
+            Interface::Rtde => self.rtde.status(),
+        }
+    }
+    /// Set up a named output recipe from the config at the configured frequency and start streaming it
+    pub fn start_recipe(&mut self, name: &str) -> Result<Recipe> {
+        let variables = self
+            .config
+            .recipe(name)
+            .ok_or_else(|| Error::UnexpectedResponse(format!("no RTDE recipe named {name}")))?;
+        let recipe = self
+            .rtde
+            .setup_output(&variables, self.config.rtde.frequency)?;
+        self.rtde.start()?;
+        Ok(recipe)
+    }
+    /// Send a URScript program to the primary interface.
+    ///
+    /// The controller compiles and runs it immediately, stopping any program that is already running.
+    /// A script that isn't wrapped in `def ...: end` is run as a single statement.
+    pub fn send_script(&mut self, script: &str) -> Result<()> {
+        log::debug!("sending script:\n{script}");
+        let result = self
+            .primary
+            .open(Interface::Primary)
+            .and_then(|port| port.send(script));
+        let result = self.primary.check(Interface::Primary, result);
+        if let Some(audit) = &self.audit {
+            audit.command(Interface::Primary, script, None, &result);
+        }
+        result
     }
     /// Close connection to universal robot tcp ports
     pub fn close(self) -> Result<()> {
@@ -49,24 +288,41 @@ pub struct UrPort {
     pub reader: BufReader<TcpStream>,
     pub writer: BufWriter<TcpStream>,
     socket: TcpStream,
+    /// Where the port was opened, to reconnect once the connection is lost
+    endpoint: Endpoint,
 }
 
 impl UrPort {
-    /// Create a new TCP connection to the selected port
-    pub fn new(host: IpAddr, timeout: Option<Duration>, port: u16) -> Result<Self> {
-        let stream = TcpStream::connect(SocketAddr::new(host, port))?;
+    /// Create a new TCP connection, waiting at most `connect_timeout` for it to open
+    pub fn connect(
+        address: SocketAddr,
+        connect_timeout: Option<Duration>,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let stream = match connect_timeout {
+            Some(connect_timeout) => TcpStream::connect_timeout(&address, connect_timeout)?,
+            None => TcpStream::connect(address)?,
+        };
         stream.set_read_timeout(timeout)?;
         stream.set_write_timeout(timeout)?;
         Ok(Self {
             reader: BufReader::new(stream.try_clone()?),
             writer: BufWriter::new(stream.try_clone()?),
             socket: stream,
+            endpoint: Endpoint {
+                address,
+                connect_timeout,
+                timeout,
+            },
         })
     }
-    /// Simple TCP Read of the port
+    /// Simple TCP Read of the port, failing with [`Error::ConnectionLost`] once the robot has
+    /// closed it
     pub fn read(&mut self) -> Result<String> {
         let mut buf = String::new();
-        self.reader.read_line(&mut buf)?;
+        if self.reader.read_line(&mut buf)? == 0 {
+            return Err(Error::ConnectionLost);
+        }
         Ok(buf)
     }
     /// Simple TCP Write of command to the port, all writes also read response
@@ -76,6 +332,36 @@ impl UrPort {
         self.writer.flush()?;
         self.read()
     }
+    /// Simple TCP Write of command to the port, for interfaces that don't respond line by line
+    pub fn send(&mut self, command: &str) -> Result<()> {
+        let payload = format!("{}\n", command.trim_end());
+        self.writer.write_all(payload.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+    /// Has data arrived that hasn't been read yet? Doesn't block.
+    pub fn has_pending(&mut self) -> Result<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(true);
+        }
+        self.socket.set_nonblocking(true)?;
+        let peeked = self.socket.peek(&mut [0u8; 1]);
+        self.socket.set_nonblocking(false)?;
+        match peeked {
+            Ok(size) => Ok(size > 0),
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.peer_addr()?)
+    }
+    /// Change the read timeout, returning the previous one
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<Option<Duration>> {
+        let previous = self.socket.read_timeout()?;
+        self.socket.set_read_timeout(timeout)?;
+        Ok(previous)
+    }
     /// Close this socket
     pub fn close(self) -> Result<()> {
         match self.socket.shutdown(std::net::Shutdown::Both) {
@@ -85,10 +371,90 @@ impl UrPort {
     }
 }
 
+/// Where and how to open a connection, kept to connect on first use
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Endpoint {
+    pub address: SocketAddr,
+    pub connect_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+}
+
+impl Endpoint {
+    pub fn open(&self) -> Result<UrPort> {
+        UrPort::connect(self.address, self.connect_timeout, self.timeout)
+    }
+}
+
+/// An interface's port, which may not be open yet
+#[derive(Debug)]
+pub(crate) enum Connection {
+    Open(UrPort),
+    Pending(Endpoint),
+    /// The last attempt to connect failed
+    Unavailable(Endpoint, String),
+    Disabled,
+}
+
+impl Connection {
+    pub fn is_open(&self) -> bool {
+        matches!(self, Connection::Open(_))
+    }
+    /// The open port, connecting first if needed
+    pub fn open(&mut self, interface: Interface) -> Result<&mut UrPort> {
+        let endpoint = match self {
+            Connection::Open(port) => return Ok(port),
+            Connection::Disabled => {
+                return Err(Error::Unavailable(interface, "disabled".to_owned()))
+            }
+            Connection::Pending(endpoint) | Connection::Unavailable(endpoint, _) => *endpoint,
+        };
+        match endpoint.open() {
+            Ok(port) => {
+                *self = Connection::Open(port);
+                self.open(interface)
+            }
+            Err(error) => {
+                *self = Connection::Unavailable(endpoint, error.to_string());
+                Err(Error::Unavailable(interface, error.to_string()))
+            }
+        }
+    }
+    pub fn status(&self) -> InterfaceStatus {
+        match self {
+            Connection::Open(_) => InterfaceStatus::Connected,
+            Connection::Pending(_) => InterfaceStatus::Pending,
+            Connection::Unavailable(_, reason) => InterfaceStatus::Unavailable(reason.clone()),
+            Connection::Disabled => InterfaceStatus::Disabled,
+        }
+    }
+    /// Drop the port if `result` shows the connection was lost, or left in the middle of an
+    /// exchange by an IO error, so it is reconnected on next use
+    pub fn check<T>(&mut self, interface: Interface, result: Result<T>) -> Result<T> {
+        if let (Err(error @ (Error::Io(_) | Error::ConnectionLost)), Connection::Open(port)) =
+            (&result, &*self)
+        {
+            let endpoint = port.endpoint;
+            log::warn!("{interface} connection lost: {error}");
+            *self = Connection::Unavailable(endpoint, error.to_string());
+        }
+        result
+    }
+    /// Close the port if it is open
+    pub fn close(self) -> Result<()> {
+        match self {
+            Connection::Open(port) => port.close(),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use crate::config::Ports;
     use crate::prelude::*;
-    use std::net::Ipv4Addr;
+    use crate::{Interface, InterfaceStatus};
+    use std::io::{BufRead, Write};
+    use std::net::{Ipv4Addr, TcpListener};
 
     const TEST_PROGRAM: &str = "rtde_control_loop.urp";
     const TEST_PROGRAM_2: &str = "rtde_control_loop_copy.urp";
@@ -126,7 +492,7 @@ mod tests {
     #[test]
     fn test_awaited_load() {
         let mut ur = init_ur().unwrap();
-        println!("{}", ur.dashboard.get_loaded_program().unwrap());
+        println!("{:?}", ur.dashboard.get_loaded_program().unwrap());
         println!("{:?}", ur.dashboard.get_program_state().unwrap());
         if let Err(e) = ur.load(TEST_PROGRAM_2, TIMEOUT) {
             panic!("load 1 {e:?}");
@@ -147,4 +513,134 @@ mod tests {
         println!("Power On");
         assert!(ur.close().is_ok());
     }
+
+    /// A port on localhost that nothing listens on
+    pub(crate) fn closed_port() -> u16 {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+    /// Ports on localhost that nothing listens on, so every interface fails to connect
+    pub(crate) fn closed_ports() -> Ports {
+        Ports {
+            dashboard: closed_port(),
+            primary: closed_port(),
+            secondary: closed_port(),
+            rtde: closed_port(),
+        }
+    }
+
+    #[test]
+    fn test_lazy_connect() {
+        let mut ur = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(closed_ports())
+            .secondary(false)
+            .lazy(true)
+            .connect()
+            .unwrap();
+        assert_eq!(ur.status(Interface::Primary), InterfaceStatus::Pending);
+        assert_eq!(ur.status(Interface::Secondary), InterfaceStatus::Disabled);
+        assert!(matches!(
+            ur.send_script("popup(\"hello\")"),
+            Err(Error::Unavailable(Interface::Primary, _))
+        ));
+        assert!(matches!(
+            ur.status(Interface::Primary),
+            InterfaceStatus::Unavailable(_)
+        ));
+        assert!(matches!(ur.identity(), Err(Error::Unavailable(..))));
+        assert!(ur.close().is_ok());
+    }
+    #[test]
+    fn test_degraded_connect() {
+        let primary = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let ports = Ports {
+            primary: primary.local_addr().unwrap().port(),
+            ..closed_ports()
+        };
+        let mut ur = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(ports)
+            .connect()
+            .unwrap();
+        assert_eq!(ur.status(Interface::Primary), InterfaceStatus::Connected);
+        assert!(matches!(
+            ur.status(Interface::Rtde),
+            InterfaceStatus::Unavailable(_)
+        ));
+        assert!(matches!(
+            ur.rtde.get_ur_version(),
+            Err(Error::Unavailable(Interface::Rtde, _))
+        ));
+        ur.send_script("popup(\"hello\")").unwrap();
+        let (stream, _) = primary.accept().unwrap();
+        let mut script = String::new();
+        std::io::BufReader::new(stream)
+            .read_line(&mut script)
+            .unwrap();
+        assert_eq!(script, "popup(\"hello\")\n");
+        assert!(ur.close().is_ok());
+
+        let unreachable = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(closed_ports())
+            .connect();
+        assert!(unreachable.is_err());
+    }
+    /// Dashboard server accepting a connection for each entry of `sessions`, answering that many
+    /// queries with the robot mode before closing it
+    fn dropping_dashboard(listener: TcpListener, sessions: Vec<usize>) {
+        std::thread::spawn(move || {
+            for replies in sessions {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                writeln!(writer, "Connected: Universal Robots Dashboard Server").unwrap();
+                let mut line = String::new();
+                // the greeting's log message, then the queries
+                for reply in 0..=replies {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match reply {
+                        0 => writeln!(writer, "Added log message").unwrap(),
+                        _ => writeln!(writer, "Robotmode: IDLE").unwrap(),
+                    }
+                }
+            }
+        });
+    }
+    #[test]
+    fn test_reconnect_after_drop() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let ports = Ports {
+            dashboard: listener.local_addr().unwrap().port(),
+            ..closed_ports()
+        };
+        dropping_dashboard(listener, vec![1, 1, 1]);
+        // querying the identity loses the first connection, which doesn't fail connecting
+        let mut ur = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(ports)
+            .primary(false)
+            .secondary(false)
+            .rtde(false)
+            .connect()
+            .unwrap();
+        assert!(matches!(
+            ur.status(Interface::Dashboard),
+            InterfaceStatus::Unavailable(_)
+        ));
+        assert!(ur.dashboard.get_mode().is_ok());
+        assert_eq!(ur.status(Interface::Dashboard), InterfaceStatus::Connected);
+        // the server closed the connection after one reply
+        assert!(matches!(
+            ur.dashboard.get_mode(),
+            Err(Error::ConnectionLost | Error::Io(_))
+        ));
+        assert!(matches!(
+            ur.status(Interface::Dashboard),
+            InterfaceStatus::Unavailable(_)
+        ));
+        assert!(ur.dashboard.get_mode().is_ok());
+        assert_eq!(ur.status(Interface::Dashboard), InterfaceStatus::Connected);
+    }
 }
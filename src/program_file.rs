@@ -0,0 +1,556 @@
+//! Read-only parsers for PolyScope `.urp` program and `.installation` files
+//!
+//! Both are XML documents, usually gzip compressed. These are the files named by
+//! [`Dashboard::load_program`] and [`Dashboard::load_installation`], so they can be audited
+//! before loading, e.g. checking the program uses the RTDE registers a recipe writes to:
+//!
+//! ```ignore
+//! let program = UrProgram::open("programs/rtde_control_loop.urp")?;
+//! let missing = program.missing_registers(&["input_int_register_0", "output_int_register_0"]);
+//! assert!(missing.is_empty(), "program never uses {missing:?}");
+//! ur.load("rtde_control_loop.urp", TIMEOUT)?;
+//! ```
+
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use roxmltree::{Document, Node as XmlNode};
+
+use crate::data::{Vec3, Vec6};
+use crate::prelude::*;
+
+/// Motion type of a Move node
+#[derive(Debug, Clone, PartialEq)]
+pub enum MotionType {
+    MoveJ,
+    MoveL,
+    MoveP,
+    Other(String),
+}
+
+/// Program node and the data relevant to auditing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// Robot Program
+    MainProgram,
+    /// BeforeStart or other special sequences
+    Sequence(String),
+    Thread(String),
+    Move {
+        motion_type: MotionType,
+        speed: Option<f64>,
+        accel: Option<f64>,
+        /// Name of the TCP used, None when using the active TCP
+        tcp: Option<String>,
+    },
+    Waypoint {
+        name: String,
+        /// Fixed, Relative or Variable
+        waypoint_type: String,
+        joints: Option<Vec6>,
+        tcp_offset: Option<Vec6>,
+        /// Variable holding the pose of a variable waypoint
+        variable: Option<String>,
+    },
+    Assignment {
+        variable: String,
+        expression: String,
+    },
+    Script(String),
+    If(String),
+    Loop(Option<String>),
+    Comment(String),
+    SetPayload(Option<f64>),
+    /// Any node this parser doesn't interpret, by its tag name
+    Other(String),
+}
+
+/// Node of a program tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// This node and all of its descendants, depth first
+    pub fn walk(&self) -> Vec<&Node> {
+        let mut nodes = vec![self];
+        for child in &self.children {
+            nodes.extend(child.walk());
+        }
+        nodes
+    }
+    /// Expression text of this node, if it has one
+    pub fn expression(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Assignment { expression, .. } => Some(expression),
+            NodeKind::Script(expression) | NodeKind::If(expression) => Some(expression),
+            NodeKind::Loop(expression) => expression.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Parsed `.urp` program file
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrProgram {
+    pub name: String,
+    /// Installation the program was saved with
+    pub installation: Option<String>,
+    pub directory: Option<String>,
+    /// PolyScope version that last saved the program
+    pub last_saved_in: Option<String>,
+    /// Top level program nodes: sequences, main program and threads
+    pub nodes: Vec<Node>,
+}
+
+impl UrProgram {
+    /// Read and parse a program file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+    /// Parse the contents of a program file, compressed or not
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = decompress(bytes)?;
+        let document = Document::parse(&text).map_err(xml_error)?;
+        let root = document.root_element();
+        if !root.has_tag_name("URProgram") {
+            return Err(Error::Deserialization(format!(
+                "expected a URProgram, found {}",
+                root.tag_name().name()
+            )));
+        }
+        Ok(UrProgram {
+            name: root.attribute("name").unwrap_or_default().to_owned(),
+            installation: root.attribute("installation").map(str::to_owned),
+            directory: root.attribute("directory").map(str::to_owned),
+            last_saved_in: root.attribute("lastSavedIn").map(str::to_owned),
+            nodes: parse_children(root),
+        })
+    }
+    /// Every node in the program, depth first
+    pub fn walk(&self) -> Vec<&Node> {
+        self.nodes.iter().flat_map(Node::walk).collect()
+    }
+    /// Move nodes, each with its waypoints as children
+    pub fn moves(&self) -> Vec<&Node> {
+        self.walk()
+            .into_iter()
+            .filter(|node| matches!(node.kind, NodeKind::Move { .. }))
+            .collect()
+    }
+    /// Names of all variables assigned in the program
+    pub fn variables(&self) -> BTreeSet<String> {
+        self.walk()
+            .into_iter()
+            .filter_map(|node| match &node.kind {
+                NodeKind::Assignment { variable, .. } => Some(variable.to_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+    /// RTDE register variables read or written by the program, e.g. `input_int_register_0`
+    pub fn rtde_registers(&self) -> BTreeSet<String> {
+        self.walk()
+            .into_iter()
+            .filter_map(Node::expression)
+            .flat_map(registers_in)
+            .collect()
+    }
+    /// Registers of an RTDE recipe that the program never uses.
+    ///
+    /// Variables in the recipe that aren't general purpose registers are ignored.
+    pub fn missing_registers(&self, recipe: &[&str]) -> Vec<String> {
+        let used = self.rtde_registers();
+        recipe
+            .iter()
+            .filter(|name| name.contains("_register_") && !used.contains(**name))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// Tool center point defined in an installation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tcp {
+    pub name: String,
+    pub offset: Vec6,
+}
+
+/// Payload defined in an installation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub name: String,
+    /// kg
+    pub mass: f64,
+    /// m
+    pub center_of_gravity: Vec3,
+}
+
+/// Parsed `.installation` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Installation {
+    pub name: String,
+    pub directory: Option<String>,
+    pub tcps: Vec<Tcp>,
+    pub active_tcp: Option<String>,
+    pub payloads: Vec<Payload>,
+    /// Names given to the general purpose registers in PolyScope, keyed by RTDE variable name
+    pub register_names: Vec<(String, String)>,
+}
+
+impl Installation {
+    /// Read and parse an installation file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+    /// Parse the contents of an installation file, compressed or not
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = decompress(bytes)?;
+        let document = Document::parse(&text).map_err(xml_error)?;
+        let root = document.root_element();
+        if !root.has_tag_name("Installation") {
+            return Err(Error::Deserialization(format!(
+                "expected an Installation, found {}",
+                root.tag_name().name()
+            )));
+        }
+        let tcp_settings = root
+            .descendants()
+            .find(|node| node.has_tag_name("TCPSettings"));
+        let tcps = root
+            .descendants()
+            .filter(|node| node.has_tag_name("tcp"))
+            .map(|node| Tcp {
+                name: node.attribute("name").unwrap_or_default().to_owned(),
+                offset: node.attribute("offset").and_then(vec6).unwrap_or_default(),
+            })
+            .collect();
+        let payloads = root
+            .descendants()
+            .filter(|node| node.has_tag_name("Payload"))
+            .map(|node| Payload {
+                name: node.attribute("name").unwrap_or_default().to_owned(),
+                mass: node
+                    .attribute("mass")
+                    .and_then(|mass| mass.parse().ok())
+                    .unwrap_or_default(),
+                center_of_gravity: node
+                    .attribute("centerOfGravity")
+                    .and_then(vec3)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        const REGISTERS: [(&str, &str); 6] = [
+            (
+                "GeneralPurposeBooleanRegisterInputNames",
+                "input_bit_register",
+            ),
+            (
+                "GeneralPurposeBooleanRegisterOutputNames",
+                "output_bit_register",
+            ),
+            ("GeneralPurposeIntRegisterInputNames", "input_int_register"),
+            (
+                "GeneralPurposeIntRegisterOutputNames",
+                "output_int_register",
+            ),
+            (
+                "GeneralPurposeFloatRegisterInputNames",
+                "input_double_register",
+            ),
+            (
+                "GeneralPurposeFloatRegisterOutputNames",
+                "output_double_register",
+            ),
+        ];
+        let mut register_names = Vec::new();
+        for (tag, prefix) in REGISTERS {
+            let Some(names) = root
+                .descendants()
+                .find(|node| node.has_tag_name(tag))
+                .and_then(|node| node.attribute("value"))
+            else {
+                continue;
+            };
+            // boolean registers available to RTDE clients start at 64
+            let offset = if prefix.ends_with("bit_register") {
+                64
+            } else {
+                0
+            };
+            for (index, name) in names.split(',').map(str::trim).enumerate() {
+                if !name.is_empty() {
+                    register_names.push((format!("{prefix}_{}", index + offset), name.to_owned()));
+                }
+            }
+        }
+        Ok(Installation {
+            name: root.attribute("fileName").unwrap_or_default().to_owned(),
+            directory: root.attribute("directory").map(str::to_owned),
+            tcps,
+            active_tcp: tcp_settings
+                .and_then(|node| node.attribute("activePose"))
+                .map(str::to_owned),
+            payloads,
+            register_names,
+        })
+    }
+}
+
+/// Files may be saved gzip compressed or as plain XML
+fn decompress(bytes: &[u8]) -> Result<String> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut text = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        String::from_utf8(bytes.to_vec()).map_err(|error| Error::Deserialization(error.to_string()))
+    }
+}
+
+fn xml_error(error: roxmltree::Error) -> Error {
+    Error::Deserialization(error.to_string())
+}
+
+/// Program nodes held in the `children` element of a node
+fn parse_children(node: XmlNode) -> Vec<Node> {
+    node.children()
+        .find(|child| child.has_tag_name("children"))
+        .map(|children| children.children().filter_map(parse_node).collect())
+        .unwrap_or_default()
+}
+
+fn parse_node(node: XmlNode) -> Option<Node> {
+    if !node.is_element() {
+        return None;
+    }
+    let attribute = |name| node.attribute(name).map(str::to_owned);
+    let number = |name| node.attribute(name).and_then(|val| val.parse().ok());
+    let kind = match node.tag_name().name() {
+        // suppressed nodes are never run
+        "SuppressedNode" | "InitVariablesNode" => return None,
+        "MainProgram" => NodeKind::MainProgram,
+        "SpecialSequence" => NodeKind::Sequence(attribute("type").unwrap_or_default()),
+        "Thread" => NodeKind::Thread(attribute("name").unwrap_or_default()),
+        "Move" => NodeKind::Move {
+            motion_type: match node.attribute("motionType").unwrap_or_default() {
+                "MoveJ" => MotionType::MoveJ,
+                "MoveL" => MotionType::MoveL,
+                "MoveP" => MotionType::MoveP,
+                other => MotionType::Other(other.to_owned()),
+            },
+            speed: number("speed"),
+            accel: number("acceleration"),
+            tcp: if node.attribute("useActiveTCP") == Some("true") {
+                None
+            } else {
+                child(node, "tcp")
+                    .and_then(|tcp| tcp.attribute("referencedName").map(str::to_owned))
+            },
+        },
+        "Waypoint" => {
+            let position = child(node, "position");
+            NodeKind::Waypoint {
+                name: attribute("name").unwrap_or_default(),
+                waypoint_type: attribute("type").unwrap_or_default(),
+                joints: position
+                    .and_then(|position| child(position, "JointAngles"))
+                    .and_then(|angles| angles.attribute("angles"))
+                    .and_then(vec6),
+                tcp_offset: position
+                    .and_then(|position| child(position, "TCPOffset"))
+                    .and_then(|offset| offset.attribute("pose"))
+                    .and_then(vec6),
+                variable: child(node, "variable").and_then(variable_name),
+            }
+        }
+        "Assignment" => NodeKind::Assignment {
+            variable: child(node, "variable")
+                .and_then(variable_name)
+                .unwrap_or_default(),
+            expression: expression(node).unwrap_or_default(),
+        },
+        "Script" => NodeKind::Script(expression(node).unwrap_or_default()),
+        "If" | "ElseIf" => NodeKind::If(expression(node).unwrap_or_default()),
+        "Loop" => NodeKind::Loop(expression(node)),
+        "Comment" => NodeKind::Comment(attribute("comment").unwrap_or_default()),
+        "SetPayload" => NodeKind::SetPayload(number("mass")),
+        other => NodeKind::Other(other.to_owned()),
+    };
+    Some(Node {
+        kind,
+        children: parse_children(node),
+    })
+}
+
+fn child<'a, 'input>(node: XmlNode<'a, 'input>, tag: &str) -> Option<XmlNode<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(tag))
+}
+
+/// Text of the `expression` element of a node.
+///
+/// PolyScope stores expressions as a sequence of characters, tokens and references to variables.
+fn expression(node: XmlNode) -> Option<String> {
+    let expression = child(node, "expression")?;
+    let mut text = String::new();
+    for part in expression.children().filter(XmlNode::is_element) {
+        match part.tag_name().name() {
+            "ExpressionChar" => text.push_str(part.attribute("character").unwrap_or_default()),
+            "ExpressionToken" => text.push_str(part.attribute("token").unwrap_or_default()),
+            _ => {
+                if let Some(name) = part.descendants().find_map(variable_name) {
+                    text.push_str(&name);
+                }
+            }
+        }
+    }
+    Some(text)
+}
+
+/// Name of a variable, following its reference to where it is declared if needed
+fn variable_name(node: XmlNode) -> Option<String> {
+    if let Some(name) = node.attribute("name") {
+        return Some(name.to_owned());
+    }
+    resolve(node, node.attribute("reference")?)?
+        .attribute("name")
+        .map(str::to_owned)
+}
+
+/// Follow a relative reference path such as `../../SpecialSequence/children/Assignment[2]/variable`
+fn resolve<'a, 'input>(node: XmlNode<'a, 'input>, reference: &str) -> Option<XmlNode<'a, 'input>> {
+    let mut current = node;
+    for step in reference.split('/') {
+        current = match step {
+            "" | "." => current,
+            ".." => current.parent_element()?,
+            step => {
+                let (tag, nth) = match step.split_once('[') {
+                    Some((tag, nth)) => (tag, nth.trim_end_matches(']').parse().ok()?),
+                    None => (step, 1),
+                };
+                current
+                    .children()
+                    .filter(|child| child.has_tag_name(tag))
+                    .nth(nth - 1)?
+            }
+        }
+    }
+    Some(current)
+}
+
+fn numbers(text: &str) -> Vec<f64> {
+    text.split(',')
+        .filter_map(|num| num.trim().parse().ok())
+        .collect()
+}
+
+fn vec6(text: &str) -> Option<Vec6> {
+    match numbers(text)[..] {
+        [x, y, z, rx, ry, rz] => Some(Vec6::new(x, y, z, rx, ry, rz)),
+        _ => None,
+    }
+}
+
+fn vec3(text: &str) -> Option<Vec3> {
+    match numbers(text)[..] {
+        [x, y, z] => Some(Vec3 { x, y, z }),
+        _ => None,
+    }
+}
+
+/// RTDE variable names of the registers accessed by literal index in an expression
+fn registers_in(expression: &str) -> Vec<String> {
+    const FUNCTIONS: [(&str, &str); 6] = [
+        ("read_input_integer_register(", "input_int_register"),
+        ("read_input_float_register(", "input_double_register"),
+        ("read_input_boolean_register(", "input_bit_register"),
+        ("write_output_integer_register(", "output_int_register"),
+        ("write_output_float_register(", "output_double_register"),
+        ("write_output_boolean_register(", "output_bit_register"),
+    ];
+    let mut registers = Vec::new();
+    for (function, variable) in FUNCTIONS {
+        for (start, _) in expression.match_indices(function) {
+            let args = &expression[start + function.len()..];
+            let index: String = args
+                .trim_start()
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            if !index.is_empty() {
+                registers.push(format!("{variable}_{index}"));
+            }
+        }
+    }
+    registers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &[u8] = include_bytes!("../examples/simulator/programs/rtde_control_loop.urp");
+    const INSTALLATION: &[u8] =
+        include_bytes!("../examples/simulator/programs/default.installation");
+
+    #[test]
+    fn test_parse_program() {
+        let program = UrProgram::parse(PROGRAM).unwrap();
+        assert_eq!(program.name, "rtde_control_loop");
+        assert_eq!(program.installation.as_deref(), Some("default"));
+        let moves = program.moves();
+        assert_eq!(moves.len(), 1);
+        assert!(matches!(
+            moves[0].kind,
+            NodeKind::Move {
+                motion_type: MotionType::MoveL,
+                speed: Some(0.08),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &moves[0].children[0].kind,
+            NodeKind::Waypoint { variable: Some(variable), .. } if variable == "setp"
+        ));
+        assert_eq!(
+            program.variables(),
+            BTreeSet::from(["setp".to_owned(), "tmp".to_owned()])
+        );
+        // the If condition refers to setp by reference
+        assert!(program.walk().iter().any(
+            |node| matches!(&node.kind, NodeKind::If(condition) if condition.contains("setp"))
+        ));
+    }
+    #[test]
+    fn test_program_registers() {
+        let program = UrProgram::parse(PROGRAM).unwrap();
+        let registers = program.rtde_registers();
+        assert!(registers.contains("input_int_register_0"));
+        assert!(registers.contains("input_double_register_5"));
+        assert!(registers.contains("output_int_register_0"));
+        assert_eq!(
+            program.missing_registers(&[
+                "timestamp",
+                "input_double_register_0",
+                "input_double_register_6"
+            ]),
+            vec!["input_double_register_6"]
+        );
+    }
+    #[test]
+    fn test_parse_installation() {
+        let installation = Installation::parse(INSTALLATION).unwrap();
+        assert_eq!(installation.name, "default");
+        assert_eq!(installation.active_tcp.as_deref(), Some("TCP"));
+        assert_eq!(installation.tcps[0].offset, Vec6::default());
+        assert_eq!(installation.payloads[0].mass, 1.0);
+        assert!(installation
+            .register_names
+            .contains(&("output_int_register_1".to_owned(), "count".to_owned())));
+    }
+}
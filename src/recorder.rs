@@ -0,0 +1,397 @@
+//! RTDE stream recording to CSV
+//!
+//! Samples are handed to a writer thread over a bounded channel, so recording never blocks the
+//! loop reading the stream. If the writer falls behind, samples are dropped and counted rather
+//! than queued without limit.
+//!
+//! ```ignore
+//! let recipe = ur.start_telemetry(&JOINT_HEALTH_OUTPUTS, 125.0)?;
+//! let mut recorder = RtdeRecorder::builder("run.csv", &recipe, ur.rtde.output_names())
+//!     .rotate_size(100_000_000)
+//!     .spawn()?;
+//! loop {
+//!     let package = ur.rtde.read()?;
+//!     recorder.record(&package);
+//! }
+//! let summary = recorder.finish()?;
+//! ```
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use crate::data::{DataType, Value};
+use crate::prelude::*;
+use crate::types::{PackageType, Payload, Recipe};
+
+/// Layout of the recorded file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsvFormat {
+    /// Comma separated, booleans as 0 and 1
+    Csv,
+    /// Space separated with Python formatted values, as written by Universal Robots' `record.py`
+    RecordPy,
+}
+
+impl CsvFormat {
+    fn delimiter(&self) -> &'static str {
+        match self {
+            CsvFormat::Csv => ",",
+            CsvFormat::RecordPy => " ",
+        }
+    }
+}
+
+/// Column names for a recipe, vector variables are flattened into `<name>_0..n`
+pub fn csv_header<S: AsRef<str>>(names: &[S], types: &[DataType]) -> Vec<String> {
+    let mut header = Vec::new();
+    for (name, var_type) in names.iter().zip(types) {
+        let name = name.as_ref();
+        match var_type {
+            DataType::Vec6 | DataType::IVec6 | DataType::UVec6 => {
+                header.extend((0..6).map(|index| format!("{name}_{index}")))
+            }
+            DataType::Vec3 => header.extend((0..3).map(|index| format!("{name}_{index}"))),
+            _ => header.push(name.to_owned()),
+        }
+    }
+    header
+}
+
+/// Format a value as one or more CSV fields
+fn push_fields(fields: &mut Vec<String>, value: &Value, format: CsvFormat) {
+    let float = |val: f64| match format {
+        // python prints whole floats with a trailing .0
+        CsvFormat::RecordPy if val.is_finite() && val.fract() == 0.0 => format!("{val:.1}"),
+        _ => val.to_string(),
+    };
+    match value {
+        Value::Vec6(vec) => fields.extend([vec.x, vec.y, vec.z, vec.rx, vec.ry, vec.rz].map(float)),
+        Value::Vec3(vec) => fields.extend([vec.x, vec.y, vec.z].map(float)),
+        Value::IVec6(vec) => fields.extend(vec.map(|val| val.to_string())),
+        Value::UVec6(vec) => fields.extend(vec.map(|val| val.to_string())),
+        Value::F64(val) => fields.push(float(*val)),
+        Value::U64(val) => fields.push(val.to_string()),
+        Value::U32(val) => fields.push(val.to_string()),
+        Value::I32(val) => fields.push(val.to_string()),
+        Value::U8(val) => fields.push(val.to_string()),
+        Value::Bool(val) => fields.push(
+            match (format, val) {
+                (CsvFormat::Csv, true) => "1",
+                (CsvFormat::Csv, false) => "0",
+                (CsvFormat::RecordPy, true) => "True",
+                (CsvFormat::RecordPy, false) => "False",
+            }
+            .to_owned(),
+        ),
+    }
+}
+
+/// Configures and starts an [`RtdeRecorder`]
+pub struct RecorderBuilder {
+    path: PathBuf,
+    recipe_id: u8,
+    names: Vec<String>,
+    types: Vec<DataType>,
+    format: CsvFormat,
+    rotate_size: Option<u64>,
+    rotate_interval: Option<Duration>,
+    capacity: usize,
+}
+
+impl RecorderBuilder {
+    /// Layout of the recorded files, defaults to [`CsvFormat::Csv`]
+    pub fn format(mut self, format: CsvFormat) -> Self {
+        self.format = format;
+        self
+    }
+    /// Start a new file once the current one reaches this many bytes
+    pub fn rotate_size(mut self, bytes: u64) -> Self {
+        self.rotate_size = Some(bytes);
+        self
+    }
+    /// Start a new file once the current one has been open this long
+    pub fn rotate_interval(mut self, interval: Duration) -> Self {
+        self.rotate_interval = Some(interval);
+        self
+    }
+    /// Samples that can be queued for the writer before new ones are dropped
+    pub fn capacity(mut self, samples: usize) -> Self {
+        self.capacity = samples;
+        self
+    }
+    /// Create the first file and start the writer thread
+    pub fn spawn(self) -> Result<RtdeRecorder> {
+        if self.names.len() != self.types.len() {
+            return Err(Error::Static("recipe and variable names differ in length"));
+        }
+        if self.types.contains(&DataType::NotFound) {
+            return Err(Error::Static("cannot record a variable that was not found"));
+        }
+        let (sender, receiver) = sync_channel(self.capacity);
+        let recipe_id = self.recipe_id;
+        let mut writer = CsvWriter::new(self)?;
+        let worker = std::thread::Builder::new()
+            .name("rtde-recorder".to_owned())
+            .spawn(move || writer.run(receiver))?;
+        Ok(RtdeRecorder {
+            sender: Some(sender),
+            worker: Some(worker),
+            recipe_id,
+            dropped: 0,
+        })
+    }
+}
+
+/// Outcome of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingSummary {
+    /// Samples written to file
+    pub samples: u64,
+    /// Samples dropped because the writer fell behind
+    pub dropped: u64,
+    /// Files written, in order
+    pub files: Vec<PathBuf>,
+}
+
+/// Records RTDE data packages to CSV files on a background thread
+pub struct RtdeRecorder {
+    sender: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<RecordingSummary>>>,
+    recipe_id: u8,
+    dropped: u64,
+}
+
+impl RtdeRecorder {
+    /// Samples queued by default before new ones are dropped
+    const CAPACITY: usize = 4096;
+
+    /// Record the output recipe to `path`, with the variable names it was set up with
+    pub fn builder<P: AsRef<Path>, S: AsRef<str>>(
+        path: P,
+        recipe: &Recipe,
+        names: &[S],
+    ) -> RecorderBuilder {
+        RecorderBuilder {
+            path: path.as_ref().to_owned(),
+            recipe_id: recipe.id(),
+            names: names.iter().map(|name| name.as_ref().to_owned()).collect(),
+            types: recipe.get_types(),
+            format: CsvFormat::Csv,
+            rotate_size: None,
+            rotate_interval: None,
+            capacity: Self::CAPACITY,
+        }
+    }
+    /// Queue a package for writing without blocking.
+    ///
+    /// Packages other than data for this recipe are ignored. Returns false if the sample
+    /// was dropped because the writer is behind or has stopped.
+    pub fn record(&mut self, package: &Payload<Vec<u8>>) -> bool {
+        if package.get_type() != PackageType::Data
+            || package.payload.first() != Some(&self.recipe_id)
+        {
+            return true;
+        }
+        let Some(sender) = &self.sender else {
+            return false;
+        };
+        match sender.try_send(package.payload.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped += 1;
+                false
+            }
+        }
+    }
+    /// Samples dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+    /// Flush the queued samples, close the file and stop the writer thread
+    pub fn finish(mut self) -> Result<RecordingSummary> {
+        self.stop()
+    }
+    fn stop(&mut self) -> Result<RecordingSummary> {
+        self.sender.take();
+        let Some(worker) = self.worker.take() else {
+            return Err(Error::Static("recorder already stopped"));
+        };
+        let mut summary = worker
+            .join()
+            .map_err(|_| Error::Static("recorder thread panicked"))??;
+        summary.dropped = self.dropped;
+        Ok(summary)
+    }
+}
+
+impl Drop for RtdeRecorder {
+    fn drop(&mut self) {
+        if self.worker.is_some() {
+            if let Err(error) = self.stop() {
+                log::error!("recording failed: {error}");
+            }
+        }
+    }
+}
+
+/// Writer thread state
+struct CsvWriter {
+    config: RecorderBuilder,
+    header: String,
+    file: BufWriter<File>,
+    opened: Instant,
+    written: u64,
+    summary: RecordingSummary,
+}
+
+impl CsvWriter {
+    fn new(config: RecorderBuilder) -> Result<Self> {
+        let header = csv_header(&config.names, &config.types).join(config.format.delimiter());
+        let path = config.path.clone();
+        let mut writer = CsvWriter {
+            header,
+            file: BufWriter::new(File::create(&path)?),
+            opened: Instant::now(),
+            written: 0,
+            summary: RecordingSummary {
+                samples: 0,
+                dropped: 0,
+                files: vec![path],
+            },
+            config,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+    fn run(&mut self, receiver: Receiver<Vec<u8>>) -> Result<RecordingSummary> {
+        let mut fields = Vec::new();
+        for payload in receiver {
+            let package = Payload::new(PackageType::Data, payload, None)?;
+            fields.clear();
+            for value in package.values(&self.config.types)? {
+                push_fields(&mut fields, &value, self.config.format);
+            }
+            self.rotate_if_due()?;
+            let line = fields.join(self.config.format.delimiter());
+            writeln!(self.file, "{line}")?;
+            self.written += line.len() as u64 + 1;
+            self.summary.samples += 1;
+        }
+        self.file.flush()?;
+        Ok(self.summary.clone())
+    }
+    fn write_header(&mut self) -> Result<()> {
+        writeln!(self.file, "{}", self.header)?;
+        self.written = self.header.len() as u64 + 1;
+        Ok(())
+    }
+    fn rotate_if_due(&mut self) -> Result<()> {
+        let size_due = self
+            .config
+            .rotate_size
+            .is_some_and(|size| self.written >= size);
+        let time_due = self
+            .config
+            .rotate_interval
+            .is_some_and(|interval| self.opened.elapsed() >= interval);
+        if !size_due && !time_due {
+            return Ok(());
+        }
+        self.file.flush()?;
+        let path = rotated_path(&self.config.path, self.summary.files.len());
+        log::debug!("recording to {}", path.display());
+        self.file = BufWriter::new(File::create(&path)?);
+        self.opened = Instant::now();
+        self.summary.files.push(path);
+        self.write_header()
+    }
+}
+
+/// `run.csv` becomes `run.1.csv`, `run.2.csv`, ...
+pub(crate) fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}.{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{index}"),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Vec6;
+    use crate::rtde::as_bytes;
+    use crate::test::temp_path;
+
+    fn package(recipe_id: u8, timestamp: f64, q: Vec6, flag: bool) -> Payload<Vec<u8>> {
+        let bytes = as_bytes((recipe_id, timestamp, q, flag)).unwrap();
+        Payload::new(PackageType::Data, bytes, None).unwrap()
+    }
+    fn recipe() -> Recipe {
+        Recipe::new(1, vec![DataType::F64, DataType::Vec6, DataType::Bool])
+    }
+
+    #[test]
+    fn test_header() {
+        let header = csv_header(
+            &["timestamp", "actual_q", "actual_tool_accelerometer"],
+            &[DataType::F64, DataType::Vec6, DataType::Vec3],
+        );
+        assert_eq!(header[0], "timestamp");
+        assert_eq!(header[1], "actual_q_0");
+        assert_eq!(header[6], "actual_q_5");
+        assert_eq!(header[9], "actual_tool_accelerometer_2");
+        assert_eq!(header.len(), 10);
+    }
+    #[test]
+    fn test_record_csv() {
+        let path = temp_path("record.csv");
+        let mut recorder =
+            RtdeRecorder::builder(&path, &recipe(), &["timestamp", "actual_q", "flag"])
+                .spawn()
+                .unwrap();
+        let q = Vec6::new(0.0, -1.5, 1.25, 0.0, 0.5, 3.0);
+        assert!(recorder.record(&package(1, 0.008, q, true)));
+        // other recipes are ignored
+        assert!(recorder.record(&package(2, 0.016, q, true)));
+        assert!(recorder.record(&package(1, 0.016, q, false)));
+        let summary = recorder.finish().unwrap();
+        assert_eq!(summary.samples, 2);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(
+            lines[0],
+            "timestamp,actual_q_0,actual_q_1,actual_q_2,actual_q_3,actual_q_4,actual_q_5,flag"
+        );
+        assert_eq!(lines[1], "0.008,0,-1.5,1.25,0,0.5,3,1");
+        assert_eq!(lines[2], "0.016,0,-1.5,1.25,0,0.5,3,0");
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_record_py_format_and_rotation() {
+        let path = temp_path("record_py.csv");
+        let mut recorder =
+            RtdeRecorder::builder(&path, &recipe(), &["timestamp", "actual_q", "flag"])
+                .format(CsvFormat::RecordPy)
+                .rotate_size(100)
+                .spawn()
+                .unwrap();
+        for index in 0..4 {
+            recorder.record(&package(1, index as f64, Vec6::default(), true));
+        }
+        let summary = recorder.finish().unwrap();
+        assert_eq!(summary.samples, 4);
+        assert!(summary.files.len() > 1);
+        assert_eq!(summary.files[1], temp_path("record_py.1.csv"));
+        let first = std::fs::read_to_string(&summary.files[0]).unwrap();
+        assert!(first.starts_with("timestamp actual_q_0"));
+        assert!(first.contains("\n0.0 0.0 0.0 0.0 0.0 0.0 0.0 True\n"));
+        for file in summary.files {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+}
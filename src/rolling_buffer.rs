@@ -12,8 +12,6 @@
 ///     assert_eq!(vec_out, iter_out);
 /// }
 /// ```
-
-#[derive(Default)]
 pub struct RollingBuffer<T>
 where
     T: Clone + Default,
@@ -23,13 +21,15 @@ where
     current_index: usize,
     count: usize,
 }
-#[allow(dead_code)]
 impl<T> RollingBuffer<T>
 where
     T: Clone + Default,
 {
     /// Create a new buffer and allocate memory for it immediately up to a size of capacity.
+    ///
+    /// The buffer holds at least one value, a capacity of 0 is raised to 1.
     pub fn new(capacity: usize) -> RollingBuffer<T> {
+        let capacity = capacity.max(1);
         RollingBuffer {
             capacity,
             buffer: vec![Default::default(); capacity],
@@ -48,6 +48,9 @@ where
     pub fn len(&self) -> usize {
         self.count
     }
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
     /// Report is the buffer has reached capacity and is now overwriting old data.
     pub fn is_full(&self) -> bool {
         self.count == self.capacity
@@ -83,6 +86,192 @@ where
     pub fn values_iter(&self) -> impl Iterator<Item = &T> {
         (0..self.count).map(move |i| self.get(i))
     }
+    /// The most recently added value
+    pub fn latest(&self) -> Option<&T> {
+        match self.count {
+            0 => None,
+            _ => Some(self.get(self.count - 1)),
+        }
+    }
+    /// View of the newest `len` values, or all of them if fewer have been added,
+    /// read through `value` for the statistics and filters in [`Window`].
+    ///
+    /// ```ignore
+    /// let mut currents = RollingBuffer::<JointHealth>::new(500);
+    /// // ...
+    /// let shoulder = currents.window_by(125, |sample| sample.currents[1]);
+    /// let slope = shoulder.derivatives(|sample| sample.timestamp).last();
+    /// ```
+    pub fn window_by<F>(&self, len: usize, value: F) -> Window<'_, T, F>
+    where
+        F: Fn(&T) -> f64,
+    {
+        let len = len.min(self.count);
+        Window {
+            buffer: self,
+            start: self.count - len,
+            len,
+            value,
+        }
+    }
+}
+
+impl<T> Default for RollingBuffer<T>
+where
+    T: Clone + Default,
+{
+    /// Buffer holding only the latest value
+    fn default() -> Self {
+        RollingBuffer::new(1)
+    }
+}
+
+impl<T> RollingBuffer<T>
+where
+    T: Clone + Default + Copy + Into<f64>,
+{
+    /// View of the newest `len` values, or all of them if fewer have been added
+    pub fn window(&self, len: usize) -> Window<'_, T, fn(&T) -> f64> {
+        self.window_by(len, |value| (*value).into())
+    }
+}
+
+/// Direction a signal passed through a threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crossing {
+    Rising,
+    Falling,
+}
+
+/// Borrowed view of the newest values in a [`RollingBuffer`], oldest first.
+///
+/// Nothing is copied out of the buffer, except by [`Window::median`] and [`Window::median_filter`]
+/// which need to sort.
+pub struct Window<'a, T, F>
+where
+    T: Clone + Default,
+{
+    buffer: &'a RollingBuffer<T>,
+    start: usize,
+    len: usize,
+    value: F,
+}
+
+impl<T, F> Window<'_, T, F>
+where
+    T: Clone + Default,
+    F: Fn(&T) -> f64,
+{
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Raw samples in the window
+    pub fn samples(&self) -> impl Iterator<Item = &T> + '_ {
+        (self.start..self.start + self.len).map(|index| self.buffer.get(index))
+    }
+    /// Values in the window
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.samples().map(&self.value)
+    }
+    pub fn mean(&self) -> Option<f64> {
+        match self.len {
+            0 => None,
+            len => Some(self.values().sum::<f64>() / len as f64),
+        }
+    }
+    pub fn min(&self) -> Option<f64> {
+        self.values().reduce(f64::min)
+    }
+    pub fn max(&self) -> Option<f64> {
+        self.values().reduce(f64::max)
+    }
+    /// Population standard deviation
+    pub fn std_dev(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        let variance = self
+            .values()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / self.len as f64;
+        Some(variance.sqrt())
+    }
+    /// Rate of change between each pair of neighbouring samples, using `time` \[s\] for the
+    /// spacing. Pairs with no time between them are skipped.
+    pub fn derivatives<'w, G>(&'w self, time: G) -> impl Iterator<Item = f64> + 'w
+    where
+        G: Fn(&T) -> f64 + Clone + 'w,
+    {
+        let points = (self.start..self.start + self.len).map(move |index| {
+            let sample = self.buffer.get(index);
+            (time(sample), (self.value)(sample))
+        });
+        let next = points.clone().skip(1);
+        points
+            .zip(next)
+            .filter(|((t0, _), (t1, _))| t1 > t0)
+            .map(|((t0, v0), (t1, v1))| (v1 - v0) / (t1 - t0))
+    }
+    /// Exponential moving average of each value, `alpha` in (0, 1\] weighs the newest value
+    pub fn low_pass(&self, alpha: f64) -> impl Iterator<Item = f64> + '_ {
+        self.values()
+            .scan(None, move |state: &mut Option<f64>, value| {
+                let filtered = match *state {
+                    None => value,
+                    Some(previous) => previous + alpha * (value - previous),
+                };
+                *state = Some(filtered);
+                Some(filtered)
+            })
+    }
+    /// Median of the window
+    pub fn median(&self) -> Option<f64> {
+        let mut values: Vec<f64> = self.values().collect();
+        median(&mut values)
+    }
+    /// Median of each run of `width` values ending at each sample, narrower at the start
+    pub fn median_filter(&self, width: usize) -> Vec<f64> {
+        let values: Vec<f64> = self.values().collect();
+        let mut scratch = Vec::with_capacity(width);
+        (0..values.len())
+            .filter_map(|end| {
+                scratch.clear();
+                scratch.extend_from_slice(&values[(end + 1).saturating_sub(width)..=end]);
+                median(&mut scratch)
+            })
+            .collect()
+    }
+    /// Where the values pass through the threshold, as the index of the first sample on the
+    /// far side, relative to the start of the window
+    pub fn crossings(&self, threshold: f64) -> impl Iterator<Item = (usize, Crossing)> + '_ {
+        self.values()
+            .zip(self.values().skip(1))
+            .enumerate()
+            .filter_map(move |(index, (previous, value))| {
+                if previous < threshold && value >= threshold {
+                    Some((index + 1, Crossing::Rising))
+                } else if previous >= threshold && value < threshold {
+                    Some((index + 1, Crossing::Falling))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Median of the values, reordering them
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    })
 }
 
 #[cfg(test)]
@@ -137,6 +326,13 @@ mod tests {
         assert_eq!(*buff.get(1000), 39);
     }
     #[test]
+    fn test_zero_capacity() {
+        let mut buff = RollingBuffer::new(0);
+        buff.add(1);
+        assert!(buff.add(2));
+        assert_eq!(buff.values(), vec![2]);
+    }
+    #[test]
     fn test_char_vec() {
         let alphabet = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
         let mut buff = RollingBuffer::new(20);
@@ -168,4 +364,64 @@ mod tests {
             assert_eq!(vec_out, iter_out);
         }
     }
+    #[test]
+    fn test_window_statistics() {
+        let mut buff = RollingBuffer::new(5);
+        assert_eq!(buff.window(3).mean(), None::<f64>);
+        for num in [9.0, 9.0, 2.0, 4.0, 4.0, 4.0, 5.0, 5.0] {
+            buff.add(num);
+        }
+        // the buffer holds 4, 4, 4, 5, 5 and the window the newest 4 of them
+        let window = buff.window(4);
+        assert_eq!(window.len(), 4);
+        assert_eq!(window.mean(), Some(4.5));
+        assert_eq!(window.min(), Some(4.0));
+        assert_eq!(window.max(), Some(5.0));
+        assert_eq!(window.std_dev(), Some(0.5));
+        assert_eq!(buff.window(100).len(), 5);
+        assert_eq!(buff.window(3).median(), Some(5.0));
+        assert_eq!(buff.latest(), Some(&5.0));
+    }
+    #[test]
+    fn test_window_by_derivatives() {
+        let mut buff = RollingBuffer::new(4);
+        for (time, position) in [(0.0, 0.0), (0.5, 1.0), (1.0, 1.5), (1.0, 2.0), (2.0, 3.0)] {
+            buff.add((time, position));
+        }
+        let window = buff.window_by(4, |sample: &(f64, f64)| sample.1);
+        let velocity: Vec<f64> = window.derivatives(|sample: &(f64, f64)| sample.0).collect();
+        // the repeated timestamp is skipped
+        assert_eq!(velocity, vec![1.0, 1.0]);
+        // the time can be read through a borrow
+        let offset = 0.5;
+        let time = |sample: &(f64, f64)| sample.0 + offset;
+        assert_eq!(window.derivatives(&time).count(), 2);
+    }
+    #[test]
+    fn test_filters() {
+        let mut buff = RollingBuffer::new(10);
+        for num in [0.0, 10.0, 0.0, 0.0, 1.0, 1.0] {
+            buff.add(num);
+        }
+        let window = buff.window(10);
+        let smoothed: Vec<f64> = window.low_pass(0.5).collect();
+        assert_eq!(smoothed, vec![0.0, 5.0, 2.5, 1.25, 1.125, 1.0625]);
+        assert_eq!(window.median_filter(3), vec![0.0, 5.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+    #[test]
+    fn test_crossings() {
+        let mut buff = RollingBuffer::new(10);
+        for num in [1_u8, 3, 5, 5, 2, 8] {
+            buff.add(num);
+        }
+        let crossings: Vec<_> = buff.window(10).crossings(5.0).collect();
+        assert_eq!(
+            crossings,
+            vec![
+                (2, Crossing::Rising),
+                (4, Crossing::Falling),
+                (5, Crossing::Rising)
+            ]
+        );
+    }
 }
@@ -12,8 +12,11 @@
 #[cfg(test)]
 mod test;
 
+pub mod capture;
 pub mod commands;
 pub mod data;
+pub mod frame;
+pub mod health;
 pub mod types;
 
 use bincode::Options;
@@ -21,23 +24,70 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use types::Header;
 
-use crate::physical::UrPort;
+use crate::audit::AuditLog;
+use crate::config::Ports;
+use crate::physical::{Connection, UrPort};
 use crate::prelude::*;
 use crate::rolling_buffer::RollingBuffer;
+use crate::{Interface, InterfaceStatus};
 
+use self::capture::{Direction, ReplayPort, SessionCapture};
 use self::data::DataType;
-use self::types::{PackageType, Payload, Protocol};
+use self::types::{PackageType, Payload, Protocol, Recipe};
 
 use std::io::{ErrorKind, Read, Write};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+
+/// Byte stream the protocol runs over
+enum Link {
+    Tcp(Connection),
+    Replay(ReplayPort),
+}
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Link::Tcp(Connection::Open(port)) => port.reader.read(buf),
+            Link::Tcp(_) => Err(ErrorKind::NotConnected.into()),
+            Link::Replay(port) => port.read(buf),
+        }
+    }
+}
+
+impl Write for Link {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Link::Tcp(Connection::Open(port)) => port.writer.write(buf),
+            Link::Tcp(_) => Err(ErrorKind::NotConnected.into()),
+            // nothing is listening to a replay
+            Link::Replay(_) => Ok(buf.len()),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Link::Tcp(Connection::Open(port)) => port.writer.flush(),
+            Link::Tcp(_) => Err(ErrorKind::NotConnected.into()),
+            Link::Replay(_) => Ok(()),
+        }
+    }
+}
 
 /// Real-time Data Exchange
 pub struct Rtde {
-    port: UrPort,
+    port: Link,
+    capture: Option<SessionCapture>,
     output: Vec<DataType>,
+    output_id: u8,
+    output_names: Vec<String>,
+    /// Input recipes set up on this connection, by their variables
+    inputs: Vec<(String, Recipe)>,
+    streaming: bool,
     frequency: f64,
     protocol: Protocol,
     messages: RollingBuffer<String>,
+    /// Payload of the latest package read
+    buffer: Vec<u8>,
+    audit: Option<AuditLog>,
 }
 
 /// Convert this payload to a bytestream ready to send to the robot.
@@ -54,19 +104,48 @@ pub fn as_bytes<T: Serialize>(payload: T) -> Result<Vec<u8>> {
     }
 }
 
+/// Convert from a bytestream to any type T required
+pub fn from_bytes<T: DeserializeOwned>(buf: &[u8]) -> Result<T> {
+    match bincode::options()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize(buf)
+    {
+        Err(error) => Err(Error::Deserialization(error.to_string())),
+        Ok(payload) => Ok(payload),
+    }
+}
+
 impl Rtde {
-    const RTDE_PORT: u16 = 30004;
-    /// Initialize connection to the dashboard server port
+    /// Initialize connection to the RTDE port
     pub fn new(host: IpAddr, timeout: Option<Duration>) -> Result<Self> {
-        let port = UrPort::new(host, timeout, Self::RTDE_PORT)?;
+        Self::connect(SocketAddr::new(host, Ports::DEFAULT.rtde), timeout)
+    }
+    /// Initialize connection to RTDE on another port, e.g. when port-forwarded
+    pub fn connect(address: SocketAddr, timeout: Option<Duration>) -> Result<Self> {
+        Self::with_connection(Connection::Open(UrPort::connect(address, None, timeout)?))
+    }
+    /// Negotiate the protocol version if the connection is open, otherwise that happens on first use
+    pub(crate) fn with_connection(connection: Connection) -> Result<Self> {
+        let open = connection.is_open();
         let mut rtde = Rtde {
-            port,
+            port: Link::Tcp(connection),
+            capture: None,
             output: Vec::new(),
+            output_id: 0,
+            output_names: Vec::new(),
+            inputs: Vec::new(),
+            streaming: false,
             frequency: 50.0,
             protocol: Protocol::V2,
             messages: RollingBuffer::new(10),
+            buffer: Vec::with_capacity(u16::MAX as usize),
+            audit: None,
         };
-        rtde.set_protocol_version(Protocol::V2)?;
+        if open {
+            rtde.set_protocol_version(Protocol::V2)?;
+        }
         Ok(rtde)
     }
     /// Convert from a bytestream to any type T required
@@ -85,25 +164,63 @@ impl Rtde {
     /// Expect the first 3 bytes to indicate the length of the full message
     /// and its type
     pub fn read(&mut self) -> Result<Payload<Vec<u8>>> {
+        let (package_type, payload_size) = self.read_package()?;
+        Payload::new(package_type, self.buffer.clone(), Some(payload_size))
+    }
+    /// Read up to the newest data package received, skipping the ones buffered before it.
+    ///
+    /// Waits for the next data package if none is buffered.
+    pub fn read_latest(&mut self) -> Result<Payload<Vec<u8>>> {
+        loop {
+            let package = self.read()?;
+            if package.is_data() && !self.is_buffered()? {
+                return Ok(package);
+            }
+        }
+    }
+    /// Has more been received than read so far?
+    fn is_buffered(&mut self) -> Result<bool> {
+        match &mut self.port {
+            Link::Tcp(Connection::Open(port)) => port.has_pending(),
+            Link::Tcp(_) => Ok(false),
+            Link::Replay(port) => Ok(port.has_pending()),
+        }
+    }
+    /// Read the next package's payload into the connection's buffer, reusing its allocation.
+    ///
+    /// Returns the package type and its size including the header
+    fn read_package(&mut self) -> Result<(PackageType, u16)> {
+        self.connect_pending()?;
+        let result = self.receive_package();
+        self.check(result)
+    }
+    fn receive_package(&mut self) -> Result<(PackageType, u16)> {
         // read response (size & type)
         let mut header_buf = [0u8; 3];
-        self.port.reader.read_exact(&mut header_buf)?;
+        match self.port.read_exact(&mut header_buf) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(Error::ConnectionLost),
+            other => other?,
+        }
 
         let payload_size: u16 = self.parse_bytes(&header_buf[..2])?;
         let package_type: PackageType = header_buf[2].try_into()?;
 
         // read payload with handling for fragmented data.
-        let mut payload_buf = vec![0; (payload_size - 3) as usize];
+        self.buffer
+            .resize(payload_size.saturating_sub(3) as usize, 0);
         let mut bytes_read = 0;
-        while bytes_read < payload_buf.len() {
-            match self.port.reader.read(&mut payload_buf[bytes_read..]) {
+        while bytes_read < self.buffer.len() {
+            match self.port.read(&mut self.buffer[bytes_read..]) {
                 Ok(0) => return Err(Error::ConnectionLost),
                 Ok(n) => bytes_read += n,
                 Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
                 Err(e) => return Err(e.into()),
             }
         }
-        Payload::new(package_type, payload_buf, Some(payload_size))
+        if let Some(capture) = &mut self.capture {
+            capture.record(Direction::Received, &[&header_buf, &self.buffer])?;
+        }
+        Ok((package_type, payload_size))
     }
     /// Write bytes to the RTDE stream.
     ///
@@ -112,14 +229,87 @@ impl Rtde {
     ///
     /// No response.
     pub fn write<T: Serialize>(&mut self, payload: T, recipe_id: u8) -> Result<()> {
-        let mut payload_bytes = as_bytes(recipe_id)?;
-        payload_bytes.append(&mut as_bytes(payload)?);
-        let header = Header::new(PackageType::Data, Some(3 + payload_bytes.len() as u16));
-        let mut bytes = as_bytes(header)?;
-        bytes.append(&mut payload_bytes);
-        // write request
-        self.port.writer.write_all(&bytes)?;
-        self.port.writer.flush()?;
+        self.audited(
+            || {
+                let values = serde_json::to_string(&payload).unwrap_or_default();
+                format!("write recipe {recipe_id} {values}")
+            },
+            |rtde| {
+                let mut payload_bytes = as_bytes(recipe_id)?;
+                payload_bytes.append(&mut as_bytes(&payload)?);
+                let header = Header::new(PackageType::Data, Some(3 + payload_bytes.len() as u16));
+                let mut bytes = as_bytes(header)?;
+                bytes.append(&mut payload_bytes);
+                // write request
+                rtde.write_bytes(&bytes)
+            },
+            |_| None,
+        )
+    }
+    /// Record every request sent on this connection, see [`crate::audit`]
+    pub fn set_audit(&mut self, audit: AuditLog) {
+        self.audit = Some(audit);
+    }
+    /// Make a request, recording it and the robot's response in the audit log if there is one.
+    ///
+    /// The command is only described when it is recorded.
+    pub(crate) fn audited<T>(
+        &mut self,
+        command: impl FnOnce() -> String,
+        request: impl FnOnce(&mut Self) -> Result<T>,
+        response: impl FnOnce(&T) -> Option<String>,
+    ) -> Result<T> {
+        let result = request(self);
+        if let Some(audit) = &self.audit {
+            let response = result.as_ref().ok().and_then(response);
+            audit.command(Interface::Rtde, &command(), response.as_deref(), &result);
+        }
+        result
+    }
+    /// Connect and negotiate the protocol version, if connecting on first use
+    fn connect_pending(&mut self) -> Result<()> {
+        if let Link::Tcp(connection) = &mut self.port {
+            if !connection.is_open() {
+                connection.open(Interface::Rtde)?;
+                self.set_protocol_version(self.protocol)?;
+            }
+        }
+        Ok(())
+    }
+    /// Drop a TCP connection that was lost or failed mid-package, so it is reconnected on next
+    /// use. The robot forgets the recipes with the connection, they need to be set up again.
+    fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Link::Tcp(connection) = &mut self.port {
+            let result = connection.check(Interface::Rtde, result);
+            if !connection.is_open() {
+                self.output.clear();
+                self.output_names.clear();
+                self.output_id = 0;
+                self.inputs.clear();
+                self.streaming = false;
+            }
+            return result;
+        }
+        result
+    }
+    pub(crate) fn status(&self) -> InterfaceStatus {
+        match &self.port {
+            Link::Tcp(connection) => connection.status(),
+            Link::Replay(_) => InterfaceStatus::Connected,
+        }
+    }
+    /// Write a complete package to the stream, capturing it if a capture is running
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.connect_pending()?;
+        let result = self
+            .port
+            .write_all(bytes)
+            .and_then(|_| self.port.flush())
+            .map_err(Error::from);
+        self.check(result)?;
+        if let Some(capture) = &mut self.capture {
+            capture.record(Direction::Sent, &[bytes])?;
+        }
         Ok(())
     }
     /// Private boilerplate function to send a bytestream to the rtde with an expected response pattern.
@@ -129,8 +319,7 @@ impl Rtde {
     where
         Res: DeserializeOwned,
     {
-        self.port.writer.write_all(&bytes)?;
-        self.port.writer.flush()?;
+        self.write_bytes(&bytes)?;
 
         // at 500Hz this can take ~960 data reads before it flushes
         // so we'll assume 2000 is enough to have flushed through.
@@ -157,11 +346,34 @@ impl Rtde {
         }
         Err(Error::MaxReads(expect))
     }
+    /// Names of the variables in the output recipe, in the order they are streamed
+    pub fn output_names(&self) -> &[String] {
+        &self.output_names
+    }
+    /// Types of the variables in the output recipe, in the order they are streamed
+    pub fn output_types(&self) -> &[DataType] {
+        &self.output
+    }
+    /// Position of a variable in the output recipe
+    pub fn output_index(&self, name: &str) -> Option<usize> {
+        self.output_names.iter().position(|output| output == name)
+    }
+    /// Output frequency of the output recipe \[Hz\]
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+    /// Has the robot been asked to start sending output updates?
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
     /// End connection to the dashboard server port
-    pub fn close(self) -> Result<Vec<String>> {
-        self.port.close()?;
+    pub fn close(mut self) -> Result<Vec<String>> {
+        self.stop_capture()?;
+        if let Link::Tcp(connection) = self.port {
+            connection.close()?;
+        }
         let messages = self.messages.values();
-        dbg!(&messages);
+        log::debug!("messages received: {messages:?}");
         Ok(messages)
     }
 }
@@ -0,0 +1,460 @@
+//! Raw session capture and replay
+//!
+//! A capture holds every package sent and received on the RTDE stream, exactly as it went over
+//! the wire, with the time since the capture started. Replaying it through [`Rtde::replay`]
+//! rebuilds the input and output recipes from the setup handshake and then serves the received
+//! packages in order, so [`Rtde::read`] and every decoder behave as they did when it was captured.
+//!
+//! ```ignore
+//! ur.rtde.start_capture("site.rtdecap")?;
+//! // ... run until the fault shows up
+//! ur.rtde.stop_capture()?;
+//!
+//! let mut rtde = Rtde::replay("site.rtdecap", false)?;
+//! while let Ok(package) = rtde.read() {
+//!     let values = package.values(rtde.output_types())?;
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+
+use super::data::DataType;
+use super::types::{Header, PackageType, Protocol, Recipe};
+use super::{as_bytes, from_bytes, Link, Rtde};
+use crate::prelude::*;
+
+/// File signature and format version
+const MAGIC: &[u8; 8] = b"RTDECAP1";
+
+/// Which way a package went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// From the robot
+    Received,
+    /// To the robot
+    Sent,
+}
+
+/// A single package as it went over the wire, header included
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    /// Time since the capture started
+    pub elapsed: Duration,
+    pub bytes: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn package_type(&self) -> Option<PackageType> {
+        self.bytes.get(2).and_then(|byte| (*byte).try_into().ok())
+    }
+    /// Package contents after the 3 byte header
+    pub fn payload(&self) -> &[u8] {
+        self.bytes.get(3..).unwrap_or_default()
+    }
+}
+
+/// Writes packages to a capture file
+pub struct SessionCapture {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl SessionCapture {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(SessionCapture {
+            file,
+            start: Instant::now(),
+        })
+    }
+    /// Append a package, given as the parts it was read or written in
+    pub fn record(&mut self, direction: Direction, parts: &[&[u8]]) -> Result<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let direction = match direction {
+            Direction::Received => 0u8,
+            Direction::Sent => 1u8,
+        };
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        self.file
+            .write_all(&as_bytes((direction, elapsed, len as u32))?)?;
+        for part in parts {
+            self.file.write_all(part)?;
+        }
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+    /// Read every record of a capture file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<CaptureRecord>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Deserialization("not an RTDE capture".to_owned()));
+        }
+        let mut records = Vec::new();
+        let mut prefix = [0u8; 13];
+        loop {
+            match file.read_exact(&mut prefix) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                other => other?,
+            }
+            let (direction, elapsed, len): (u8, u64, u32) = from_bytes(&prefix)?;
+            let mut bytes = vec![0; len as usize];
+            file.read_exact(&mut bytes)?;
+            records.push(CaptureRecord {
+                direction: match direction {
+                    0 => Direction::Received,
+                    _ => Direction::Sent,
+                },
+                elapsed: Duration::from_micros(elapsed),
+                bytes,
+            });
+        }
+        Ok(records)
+    }
+}
+
+/// Serves captured packages in place of the robot's TCP stream
+pub struct ReplayPort {
+    records: VecDeque<CaptureRecord>,
+    current: Cursor<Vec<u8>>,
+    /// When set, each package is held back until its capture time relative to this start
+    paced: Option<(Instant, Duration)>,
+}
+
+impl ReplayPort {
+    fn new(records: VecDeque<CaptureRecord>, paced: bool) -> Self {
+        let first = records.front().map(|record| record.elapsed);
+        ReplayPort {
+            records,
+            current: Cursor::new(Vec::new()),
+            paced: match (paced, first) {
+                (true, Some(first)) => Some((Instant::now(), first)),
+                _ => None,
+            },
+        }
+    }
+    /// Is there anything left to read?
+    pub(super) fn has_pending(&self) -> bool {
+        (self.current.position() as usize) < self.current.get_ref().len()
+            || !self.records.is_empty()
+    }
+}
+
+impl Read for ReplayPort {
+    /// Returns 0 bytes once the capture is exhausted
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.current.position() as usize >= self.current.get_ref().len() {
+            let Some(record) = self.records.pop_front() else {
+                return Ok(0);
+            };
+            if let Some((start, first)) = self.paced {
+                let due = record.elapsed.saturating_sub(first);
+                sleep(due.saturating_sub(start.elapsed()));
+            }
+            self.current = Cursor::new(record.bytes);
+        }
+        self.current.read(buf)
+    }
+}
+
+impl Rtde {
+    /// Start writing every package sent and received to a capture file.
+    ///
+    /// If recipes are already set up, their handshake is written first so the capture can be
+    /// replayed on its own.
+    pub fn start_capture<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut capture = SessionCapture::create(path)?;
+        for (direction, bytes) in self.handshake()? {
+            capture.record(direction, &[&bytes])?;
+        }
+        self.capture = Some(capture);
+        Ok(())
+    }
+    /// Stop capturing and flush the capture file
+    pub fn stop_capture(&mut self) -> Result<()> {
+        match self.capture.take() {
+            Some(mut capture) => capture.flush(),
+            None => Ok(()),
+        }
+    }
+    /// Replay a capture, with the protocol version, output and input recipes set up as they
+    /// were.
+    ///
+    /// [`Rtde::read`] then returns the packages received after the handshake, in order,
+    /// and [`Error::ConnectionLost`] at the end of the capture. When `paced` the packages are
+    /// released at their captured rate, otherwise as fast as they are read.
+    /// Anything written is discarded.
+    pub fn replay<P: AsRef<Path>>(path: P, paced: bool) -> Result<Rtde> {
+        let records = SessionCapture::load(path)?;
+        let mut rtde = Rtde::offline();
+        // apply the setup before the stream started, replaying from the reply that completed it
+        let mut pending: Option<&CaptureRecord> = None;
+        let mut replay_from = 0;
+        for (index, record) in records.iter().enumerate() {
+            match (record.direction, record.package_type()) {
+                (Direction::Received, Some(PackageType::Data)) => break,
+                (_, None | Some(PackageType::Data) | Some(PackageType::Message)) => {}
+                (Direction::Sent, Some(_)) => pending = Some(record),
+                (Direction::Received, Some(package_type)) => {
+                    if let Some(request) = pending.take() {
+                        rtde.apply_handshake(request, package_type, record.payload())?;
+                        replay_from = index + 1;
+                    }
+                }
+            }
+        }
+        let received = records
+            .into_iter()
+            .skip(replay_from)
+            .filter(|record| record.direction == Direction::Received)
+            .collect();
+        rtde.port = Link::Replay(ReplayPort::new(received, paced));
+        Ok(rtde)
+    }
+    /// Not set up and not connected to anything
+    fn offline() -> Rtde {
+        Rtde {
+            port: Link::Replay(ReplayPort::new(VecDeque::new(), false)),
+            capture: None,
+            output: Vec::new(),
+            output_id: 0,
+            output_names: Vec::new(),
+            inputs: Vec::new(),
+            streaming: false,
+            frequency: 50.0,
+            protocol: Protocol::V2,
+            messages: crate::rolling_buffer::RollingBuffer::new(10),
+            buffer: Vec::new(),
+            audit: None,
+        }
+    }
+    /// Streaming an output recipe whose data packages carry these payloads, for testing code
+    /// that follows the stream
+    #[cfg(test)]
+    pub(crate) fn streaming(recipe: &[(&str, DataType)], payloads: Vec<Vec<u8>>) -> Rtde {
+        let mut rtde = Rtde::offline();
+        rtde.output_names = recipe.iter().map(|(name, _)| name.to_string()).collect();
+        rtde.output = recipe.iter().map(|(_, data_type)| *data_type).collect();
+        rtde.output_id = 1;
+        rtde.streaming = true;
+        let records = payloads
+            .into_iter()
+            .map(|payload| {
+                let header = Header::new(PackageType::Data, Some(4 + payload.len() as u16));
+                let mut bytes = as_bytes(header).unwrap();
+                bytes.push(rtde.output_id);
+                bytes.extend(payload);
+                CaptureRecord {
+                    direction: Direction::Received,
+                    elapsed: Duration::ZERO,
+                    bytes,
+                }
+            })
+            .collect();
+        rtde.port = Link::Replay(ReplayPort::new(records, false));
+        rtde
+    }
+    /// Update the state from a setup request and the robot's reply to it
+    fn apply_handshake(
+        &mut self,
+        request: &CaptureRecord,
+        reply_type: PackageType,
+        reply: &[u8],
+    ) -> Result<()> {
+        match (request.package_type(), reply_type) {
+            (Some(PackageType::ProtocolVersion), PackageType::ProtocolVersion)
+                if reply.first() == Some(&1) =>
+            {
+                self.protocol = match from_bytes::<u16>(request.payload())? {
+                    1 => Protocol::V1,
+                    _ => Protocol::V2,
+                };
+            }
+            (Some(PackageType::SetupOutputs), PackageType::SetupOutputs) => {
+                let payload = request.payload();
+                self.frequency = from_bytes(payload)?;
+                let names = String::from_utf8_lossy(payload.get(8..).unwrap_or_default());
+                self.output_names = names.trim_end().split(',').map(str::to_owned).collect();
+                self.output_id = reply.first().copied().unwrap_or_default();
+                let types = String::from_utf8_lossy(reply.get(1..).unwrap_or_default());
+                self.output = types.split(',').map(DataType::new).collect();
+            }
+            (Some(PackageType::SetupInputs), PackageType::SetupInputs) => {
+                let id = reply.first().copied().unwrap_or_default();
+                // rejected recipes are not set up
+                if id != 0 {
+                    let names = String::from_utf8_lossy(request.payload());
+                    let types = String::from_utf8_lossy(reply.get(1..).unwrap_or_default());
+                    let types = types.split(',').map(DataType::new).collect();
+                    self.inputs
+                        .push((names.trim_end().to_owned(), Recipe::new(id, types)));
+                }
+            }
+            (Some(PackageType::Start), PackageType::Start) => {
+                self.streaming = reply.first() == Some(&1);
+            }
+            (Some(PackageType::Pause), PackageType::Pause) => {
+                self.streaming = reply.first() != Some(&1);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+    /// Packages recreating the current protocol version, input and output recipe setup
+    fn handshake(&self) -> Result<Vec<(Direction, Vec<u8>)>> {
+        let package = |package_type: PackageType, payload: &[u8]| -> Result<Vec<u8>> {
+            let header = Header::new(package_type, Some(3 + payload.len() as u16));
+            let mut bytes = as_bytes(header)?;
+            bytes.extend_from_slice(payload);
+            Ok(bytes)
+        };
+        let mut packages = vec![
+            (
+                Direction::Sent,
+                package(PackageType::ProtocolVersion, &as_bytes(self.protocol)?)?,
+            ),
+            (
+                Direction::Received,
+                package(PackageType::ProtocolVersion, &[1])?,
+            ),
+        ];
+        for (names, recipe) in &self.inputs {
+            let mut reply = vec![recipe.id()];
+            let types: Vec<&str> = recipe.get_types().iter().map(DataType::name).collect();
+            reply.extend_from_slice(types.join(",").as_bytes());
+            packages.push((
+                Direction::Sent,
+                package(PackageType::SetupInputs, format!("{names}\r\n").as_bytes())?,
+            ));
+            packages.push((
+                Direction::Received,
+                package(PackageType::SetupInputs, &reply)?,
+            ));
+        }
+        if self.output.is_empty() {
+            return Ok(packages);
+        }
+        let mut request = as_bytes(self.frequency)?;
+        request.extend_from_slice(format!("{}\r\n", self.output_names.join(",")).as_bytes());
+        let mut reply = vec![self.output_id];
+        let types: Vec<&str> = self.output.iter().map(DataType::name).collect();
+        reply.extend_from_slice(types.join(",").as_bytes());
+        packages.push((
+            Direction::Sent,
+            package(PackageType::SetupOutputs, &request)?,
+        ));
+        packages.push((
+            Direction::Received,
+            package(PackageType::SetupOutputs, &reply)?,
+        ));
+        if self.streaming {
+            packages.push((Direction::Sent, package(PackageType::Start, &[])?));
+            packages.push((Direction::Received, package(PackageType::Start, &[1])?));
+        }
+        Ok(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Value, Vec6};
+    use crate::test::temp_path;
+
+    fn data(timestamp: f64, q: Vec6) -> Vec<u8> {
+        let payload = as_bytes((1u8, timestamp, q)).unwrap();
+        let mut bytes = as_bytes(Header::new(
+            PackageType::Data,
+            Some(3 + payload.len() as u16),
+        ))
+        .unwrap();
+        bytes.extend(payload);
+        bytes
+    }
+    /// Capture of a session set up with `timestamp,actual_q` at 125 Hz and an input recipe of
+    /// two registers
+    fn write_capture(path: &Path) {
+        let mut rtde = Rtde::offline();
+        let registers = "input_int_register_24,input_double_register_24".to_owned();
+        let recipe = Recipe::new(1, vec![DataType::I32, DataType::F64]);
+        rtde.inputs = vec![(registers, recipe)];
+        rtde.output = vec![DataType::F64, DataType::Vec6];
+        rtde.output_names = vec!["timestamp".to_owned(), "actual_q".to_owned()];
+        rtde.output_id = 1;
+        rtde.frequency = 125.0;
+        rtde.streaming = true;
+        let mut capture = SessionCapture::create(path).unwrap();
+        for (direction, bytes) in rtde.handshake().unwrap() {
+            capture.record(direction, &[&bytes]).unwrap();
+        }
+        for index in 0..3 {
+            let q = Vec6::new(index as f64, 0.0, 0.0, 0.0, 0.0, 0.0);
+            let bytes = data(index as f64 * 0.008, q);
+            // recorded in two parts, as read
+            capture
+                .record(Direction::Received, &[&bytes[..3], &bytes[3..]])
+                .unwrap();
+        }
+        capture.flush().unwrap();
+    }
+
+    #[test]
+    fn test_replay_reconstructs_recipe() {
+        let path = temp_path("replay.rtdecap");
+        write_capture(&path);
+        let mut rtde = Rtde::replay(&path, false).unwrap();
+        assert_eq!(rtde.output_names(), ["timestamp", "actual_q"]);
+        assert_eq!(rtde.output_types(), [DataType::F64, DataType::Vec6]);
+        assert!(rtde.is_streaming());
+        for index in 0..3 {
+            let package = rtde.read().unwrap();
+            assert!(package.is_data());
+            let values = package.values(rtde.output_types()).unwrap();
+            assert_eq!(values[0], Value::F64(index as f64 * 0.008));
+        }
+        assert!(matches!(rtde.read(), Err(Error::ConnectionLost)));
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_replay_reconstructs_inputs() {
+        let path = temp_path("replay_inputs.rtdecap");
+        write_capture(&path);
+        let mut rtde = Rtde::replay(&path, false).unwrap();
+        // answered from the captured setup, without reading the stream
+        let registers = ["input_int_register_24", "input_double_register_24"];
+        let recipe = rtde.setup_input(&registers).unwrap();
+        assert_eq!(recipe, Recipe::new(1, vec![DataType::I32, DataType::F64]));
+        for _ in 0..3 {
+            assert!(rtde.read().unwrap().is_data());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_capture_a_replay() {
+        let original = temp_path("original.rtdecap");
+        let copy = temp_path("copy.rtdecap");
+        write_capture(&original);
+        let mut rtde = Rtde::replay(&original, false).unwrap();
+        rtde.start_capture(&copy).unwrap();
+        while rtde.read().is_ok() {}
+        rtde.stop_capture().unwrap();
+        let original_records = SessionCapture::load(&original).unwrap();
+        let copied_records = SessionCapture::load(&copy).unwrap();
+        // the handshake is rebuilt from the replayed state, so everything should match
+        let bytes = |records: &[CaptureRecord]| -> Vec<Vec<u8>> {
+            records.iter().map(|record| record.bytes.clone()).collect()
+        };
+        assert_eq!(bytes(&original_records), bytes(&copied_records));
+        std::fs::remove_file(original).unwrap();
+        std::fs::remove_file(copy).unwrap();
+    }
+}
@@ -1,5 +1,3 @@
-use std::io::Write;
-
 use super::{
     as_bytes,
     data::DataType,
@@ -11,9 +9,7 @@ use crate::prelude::*;
 impl UniversalRobot {
     /// Retrieves the robot's major, minor, bugfix and build number.
     pub fn get_ur_version(&mut self) -> Result<Version> {
-        let payload = Header::new(PackageType::URControlVersion, None);
-        self.rtde
-            .send(as_bytes(payload)?, PackageType::URControlVersion)
+        self.rtde.get_ur_version()
     }
     /// Send an info log message to the Robot.
     pub fn info(&mut self, message: &str, source: &str) -> Result<()> {
@@ -31,9 +27,43 @@ impl UniversalRobot {
     pub fn exception(&mut self, message: &str, source: &str) -> Result<()> {
         self.rtde.send_message(message, source, Level::Exception)
     }
+    /// Set up the output recipe and start streaming it in one call.
+    ///
+    /// Intended for the prebuilt recipes in [`crate::data`], e.g. [`crate::data::JOINT_HEALTH_OUTPUTS`],
+    /// whose samples can then be decoded with their matching struct:
+    /// ```ignore
+    /// ur.start_telemetry(&JOINT_HEALTH_OUTPUTS, 125.0)?;
+    /// let sample: JointHealth = ur.rtde.read()?.parse()?;
+    /// ```
+    ///
+    /// Fails before starting the stream if the controller doesn't recognise a variable.
+    pub fn start_telemetry(&mut self, recipe: &[&str], rate_hz: f64) -> Result<Recipe> {
+        let setup = self.rtde.setup_output(recipe, rate_hz)?;
+        let unknown: Vec<&str> = recipe
+            .iter()
+            .zip(setup.get_types())
+            .filter(|(_, var_type)| *var_type == DataType::NotFound)
+            .map(|(name, _)| *name)
+            .collect();
+        if !unknown.is_empty() {
+            // the controller won't stream it, make way for another recipe
+            self.rtde.clear_output()?;
+            return Err(Error::UnexpectedResponse(format!(
+                "output variables not found: {}",
+                unknown.join(", ")
+            )));
+        }
+        self.rtde.start()?;
+        Ok(setup)
+    }
 }
 
 impl Rtde {
+    /// Retrieves the controller's major, minor, bugfix and build number.
+    pub fn get_ur_version(&mut self) -> Result<Version> {
+        let payload = Header::new(PackageType::URControlVersion, None);
+        self.send(as_bytes(payload)?, PackageType::URControlVersion)
+    }
     /// Request the robot to work with "protocol version".
     ///
     /// 1 (success) or 0 (failed). On success, the api should speak the specified protocol version and the Robot will answer in that version.
@@ -48,25 +78,34 @@ impl Rtde {
     }
     /// Send an exception, error, warning or info message.
     pub(crate) fn send_message(&mut self, message: &str, source: &str, level: Level) -> Result<()> {
+        self.audited(
+            || format!("message {level:?} {source}: {message}"),
+            |rtde| rtde.request_message(message, source, level.clone()),
+            |_| None,
+        )
+    }
+    fn request_message(&mut self, message: &str, source: &str, level: Level) -> Result<()> {
         let message = Message::new(message, source, level);
-        println!("{:?}", message);
+        log::debug!("{message:?}");
         let mut message_bytes = message.as_bytes()?;
         let header = Header::new(PackageType::Message, Some(3 + message_bytes.len() as u16));
         let mut bytes = as_bytes(header)?;
         bytes.append(&mut message_bytes);
-        self.port.writer.write_all(&bytes)?;
-        self.port.writer.flush()?;
-        Ok(())
+        self.write_bytes(&bytes)
     }
     /// Request the robot to start sending output updates.
     ///
     /// This will fail if e.g. an output package has not been configured yet.
     pub fn start(&mut self) -> Result<()> {
+        self.audited(|| "start".to_owned(), Self::request_start, |_| None)
+    }
+    fn request_start(&mut self) -> Result<()> {
         if self.output.is_empty() {
             return Err(Error::Static("must set up at least one rtde output recipe"));
         };
         let payload = Header::new(PackageType::Start, None);
         if self.send::<bool>(as_bytes(payload)?, PackageType::Start)? {
+            self.streaming = true;
             Ok(())
         } else {
             Err(Error::Static("rtde play error"))
@@ -76,8 +115,12 @@ impl Rtde {
     ///
     /// Robot should always accept a pause command.
     pub fn pause(&mut self) -> Result<()> {
+        self.audited(|| "pause".to_owned(), Self::request_pause, |_| None)
+    }
+    fn request_pause(&mut self) -> Result<()> {
         let payload = Header::new(PackageType::Pause, None);
         if self.send::<bool>(as_bytes(payload)?, PackageType::Pause)? {
+            self.streaming = false;
             Ok(())
         } else {
             Err(Error::Static("rtde pause error"))
@@ -97,6 +140,13 @@ impl Rtde {
     /// Returns the variable types in the same order as they were
     /// supplied in the request.
     pub fn setup_output(&mut self, recipe: &[&str], rate_hz: f64) -> Result<Recipe> {
+        self.audited(
+            || format!("setup_output {} at {rate_hz} Hz", recipe.join(",")),
+            |rtde| rtde.request_output(recipe, rate_hz),
+            |setup| Some(type_names(setup)),
+        )
+    }
+    fn request_output(&mut self, recipe: &[&str], rate_hz: f64) -> Result<Recipe> {
         if !self.output.is_empty() {
             return Err(Error::Static("Cannot setup more than one output recipe"));
         }
@@ -112,8 +162,7 @@ impl Rtde {
         bytes.append(&mut rate_bytes);
         bytes.append(&mut recipe_bytes);
         // write request
-        self.port.writer.write_all(&bytes)?;
-        self.port.writer.flush()?;
+        self.write_bytes(&bytes)?;
         // read response
         let response = self.read()?;
         match response.get_type() {
@@ -122,6 +171,8 @@ impl Rtde {
                 match std::str::from_utf8(&response.payload[1..]) {
                     Ok(resp) => {
                         self.output = resp.split(',').map(DataType::new).collect();
+                        self.output_names = recipe.iter().map(|name| name.to_string()).collect();
+                        self.output_id = id;
                         self.frequency = rate_hz;
                         Ok(Recipe::new(id, self.output.clone()))
                     }
@@ -134,11 +185,37 @@ impl Rtde {
             ))),
         }
     }
+    /// Forget the output recipe, pausing the stream first, so another one can be set up
+    pub fn clear_output(&mut self) -> Result<()> {
+        if self.streaming {
+            self.pause()?;
+        }
+        self.output.clear();
+        self.output_names.clear();
+        self.output_id = 0;
+        Ok(())
+    }
     /// Setup an input recipe.
     ///
     /// These are contracts set up by the remote to send custom variables to the Robot.
     /// They allow us to specify a list of data types and a corresponding Recipe ID (index).
+    ///
+    /// A recipe already set up on this connection is returned again rather than requested, as
+    /// the robot reports its variables `IN_USE` the second time.
     pub fn setup_input(&mut self, recipe: &[&str]) -> Result<Recipe> {
+        let variables = recipe.join(",");
+        if let Some((_, setup)) = self.inputs.iter().find(|(known, _)| *known == variables) {
+            return Ok(setup.clone());
+        }
+        let setup = self.audited(
+            || format!("setup_input {variables}"),
+            |rtde| rtde.request_input(recipe),
+            |setup| Some(type_names(setup)),
+        )?;
+        self.inputs.push((variables, setup.clone()));
+        Ok(setup)
+    }
+    fn request_input(&mut self, recipe: &[&str]) -> Result<Recipe> {
         let mut recipe_bytes = recipe.join(",");
         recipe_bytes.push_str("\r\n");
         let mut recipe_bytes = recipe_bytes.as_bytes().to_vec();
@@ -149,8 +226,7 @@ impl Rtde {
         let mut bytes = as_bytes(header)?;
         bytes.append(&mut recipe_bytes);
         // write request
-        self.port.writer.write_all(&bytes)?;
-        self.port.writer.flush()?;
+        self.write_bytes(&bytes)?;
         // read response
         let response = self.read()?;
         match response.get_type() {
@@ -177,3 +253,9 @@ impl Rtde {
         }
     }
 }
+
+/// Recipe id and types as the robot sent them, for the audit log
+fn type_names(setup: &Recipe) -> String {
+    let types: Vec<&str> = setup.get_types().iter().map(DataType::name).collect();
+    format!("{} {}", setup.id(), types.join(","))
+}
@@ -4,6 +4,7 @@ use bincode::Options;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
+use super::from_bytes;
 use super::types::Payload;
 use crate::prelude::*;
 
@@ -65,6 +66,9 @@ impl Vec6 {
     }
 }
 
+/// Standard telemetry: I/O, pose, speed, joint positions and robot/safety modes.
+///
+/// Decodes into [`DefaultOutputs`].
 pub const DEFAULT_OUTPUTS: [&str; 7] = [
     "actual_digital_output_bits",
     "timestamp",
@@ -75,18 +79,210 @@ pub const DEFAULT_OUTPUTS: [&str; 7] = [
     "robot_mode",
 ];
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Decoded sample of the [`DEFAULT_OUTPUTS`] recipe
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
 pub struct DefaultOutputs {
-    digital_bits: u64,
-    timestamp: f64,
-    tcp_pose: Vec6,
-    tcp_speed: Vec6,
-    joint_positions: Vec6,
-    safety_mode: i32,
-    robot_mode: i32,
+    pub digital_bits: u64,
+    pub timestamp: f64,
+    pub tcp_pose: Vec6,
+    pub tcp_speed: Vec6,
+    pub joint_positions: Vec6,
+    pub safety_mode: i32,
+    pub robot_mode: i32,
+}
+
+/// Joint health: currents, temperatures and voltages of each joint and the safety control board.
+///
+/// Decodes into [`JointHealth`].
+pub const JOINT_HEALTH_OUTPUTS: [&str; 9] = [
+    "timestamp",
+    "actual_current",
+    "target_current",
+    "joint_temperatures",
+    "actual_joint_voltage",
+    "joint_mode",
+    "actual_main_voltage",
+    "actual_robot_voltage",
+    "actual_robot_current",
+];
+
+/// Decoded sample of the [`JOINT_HEALTH_OUTPUTS`] recipe
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct JointHealth {
+    pub timestamp: f64,
+    /// Actual joint currents \[A\]
+    pub currents: [f64; 6],
+    /// Target joint currents \[A\]
+    pub target_currents: [f64; 6],
+    /// Joint temperatures \[°C\]
+    pub temperatures: [f64; 6],
+    /// Joint voltages \[V\]
+    pub voltages: [f64; 6],
+    /// Joint control modes
+    pub modes: [i32; 6],
+    /// Safety Control Board main voltage \[V\]
+    pub main_voltage: f64,
+    /// Safety Control Board robot voltage (48V) \[V\]
+    pub robot_voltage: f64,
+    /// Safety Control Board robot current \[A\]
+    pub robot_current: f64,
+}
+
+/// Force/torque at the TCP, alongside its pose and the tool accelerometer.
+///
+/// Decodes into [`ForceTorque`].
+pub const FORCE_TORQUE_OUTPUTS: [&str; 5] = [
+    "timestamp",
+    "actual_TCP_force",
+    "tcp_force_scalar",
+    "actual_TCP_pose",
+    "actual_tool_accelerometer",
+];
+
+/// Decoded sample of the [`FORCE_TORQUE_OUTPUTS`] recipe
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct ForceTorque {
+    pub timestamp: f64,
+    /// Generalised forces \[N\] and torques \[Nm\] at the TCP, payload compensated
+    pub tcp_force: Vec6,
+    /// Norm of the TCP force \[N\]
+    pub force_scalar: f64,
+    pub tcp_pose: Vec6,
+    /// Tool x, y and z accelerometer values
+    pub accelerometer: Vec3,
+}
+
+/// Standard, analog and tool I/O of the control box.
+///
+/// Decodes into [`IoState`].
+pub const IO_OUTPUTS: [&str; 12] = [
+    "timestamp",
+    "actual_digital_input_bits",
+    "actual_digital_output_bits",
+    "analog_io_types",
+    "standard_analog_input0",
+    "standard_analog_input1",
+    "standard_analog_output0",
+    "standard_analog_output1",
+    "io_current",
+    "tool_analog_input0",
+    "tool_analog_input1",
+    "tool_output_voltage",
+];
+
+/// Decoded sample of the [`IO_OUTPUTS`] recipe
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct IoState {
+    pub timestamp: f64,
+    /// 0-7: Standard, 8-15: Configurable, 16-17: Tool
+    pub digital_inputs: u64,
+    /// 0-7: Standard, 8-15: Configurable, 16-17: Tool
+    pub digital_outputs: u64,
+    /// Bits 0-3: analog input 0, analog input 1, analog output 0, analog output 1, {0=current\[A\], 1=voltage\[V\]}
+    pub analog_io_types: u32,
+    pub analog_inputs: [f64; 2],
+    pub analog_outputs: [f64; 2],
+    /// I/O current \[A\]
+    pub io_current: f64,
+    pub tool_analog_inputs: [f64; 2],
+    /// Tool output voltage \[V\]
+    pub tool_output_voltage: i32,
+}
+
+/// Dynamically typed value of a single RTDE variable, for recipes only known at runtime.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+    Vec6(Vec6),
+    Vec3(Vec3),
+    IVec6([i32; 6]),
+    UVec6([u32; 6]),
+    F64(f64),
+    U64(u64),
+    U32(u32),
+    I32(i32),
+    Bool(bool),
+    U8(u8),
+}
+
+impl Value {
+    /// Scalar value as a float, None for vector types
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::F64(val) => Some(val),
+            Value::U64(val) => Some(val as f64),
+            Value::U32(val) => Some(val.into()),
+            Value::I32(val) => Some(val.into()),
+            Value::Bool(val) => Some(if val { 1.0 } else { 0.0 }),
+            Value::U8(val) => Some(val.into()),
+            _ => None,
+        }
+    }
+    /// Vector6D value, None for every other type
+    pub fn as_vec6(&self) -> Option<Vec6> {
+        match self {
+            Value::Vec6(val) => Some(*val),
+            _ => None,
+        }
+    }
 }
 
 impl DataType {
+    /// Number of bytes this type occupies in a data package
+    pub fn size(&self) -> usize {
+        match self {
+            DataType::Vec6 => 48,
+            DataType::Vec3 => 24,
+            DataType::IVec6 | DataType::UVec6 => 24,
+            DataType::F64 | DataType::U64 => 8,
+            DataType::U32 | DataType::I32 => 4,
+            DataType::Bool | DataType::U8 => 1,
+            DataType::NotFound => 0,
+        }
+    }
+    /// Decode a single value of this type from the front of the buffer
+    pub fn decode(&self, buf: &[u8]) -> Result<Value> {
+        if buf.len() < self.size() {
+            return Err(Error::Deserialization(format!(
+                "{} bytes is too short for {:?}",
+                buf.len(),
+                self
+            )));
+        }
+        Ok(match self {
+            DataType::Vec6 => Value::Vec6(from_bytes(buf)?),
+            DataType::Vec3 => Value::Vec3(from_bytes(buf)?),
+            DataType::IVec6 => Value::IVec6(from_bytes(buf)?),
+            DataType::UVec6 => Value::UVec6(from_bytes(buf)?),
+            DataType::F64 => Value::F64(from_bytes(buf)?),
+            DataType::U64 => Value::U64(from_bytes(buf)?),
+            DataType::U32 => Value::U32(from_bytes(buf)?),
+            DataType::I32 => Value::I32(from_bytes(buf)?),
+            DataType::Bool => Value::Bool(from_bytes(buf)?),
+            DataType::U8 => Value::U8(from_bytes(buf)?),
+            DataType::NotFound => {
+                return Err(Error::Deserialization(
+                    "cannot decode a variable that was not found".to_owned(),
+                ))
+            }
+        })
+    }
+    /// Type name used by the controller in setup replies
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataType::Vec6 => "VECTOR6D",
+            DataType::Vec3 => "VECTOR3D",
+            DataType::IVec6 => "VECTOR6INT32",
+            DataType::UVec6 => "VECTOR6UINT32",
+            DataType::F64 => "DOUBLE",
+            DataType::U64 => "UINT64",
+            DataType::U32 => "UINT32",
+            DataType::I32 => "INT32",
+            DataType::Bool => "BOOL",
+            DataType::U8 => "UINT8",
+            DataType::NotFound => "NOT_FOUND",
+        }
+    }
     /// convert from string to DataType Enum
     pub fn new(var_type: &str) -> DataType {
         match var_type.to_lowercase().as_str() {
@@ -119,4 +315,14 @@ impl Payload<Vec<u8>> {
             Err(error) => Err(Error::Deserialization(error.to_string())),
         }
     }
+    /// Decode a data package whose layout is only known at runtime, e.g. from [`super::Rtde::output_types`]
+    pub fn values(&self, types: &[DataType]) -> Result<Vec<Value>> {
+        let mut offset = if self.is_data() { 1 } else { 0 };
+        let mut values = Vec::with_capacity(types.len());
+        for var_type in types {
+            values.push(var_type.decode(self.payload.get(offset..).unwrap_or_default())?);
+            offset += var_type.size();
+        }
+        Ok(values)
+    }
 }
@@ -0,0 +1,201 @@
+//! Allocation free reading of the RTDE stream
+//!
+//! [`Rtde::read`] hands out an owned copy of every package. At 500 Hz with a large recipe that
+//! is an allocation and a copy per sample, so the stream can instead be read through the
+//! connection's own buffer and decoded straight into a sample the caller keeps.
+//!
+//! ```ignore
+//! let recipe = ur.start_telemetry(&JOINT_HEALTH_OUTPUTS, 500.0)?;
+//! let mut health = JointHealth::default();
+//! loop {
+//!     if ur.rtde.read_data_into(&mut health)? {
+//!         monitor(&health);
+//!     }
+//! }
+//! ```
+
+use bincode::Options;
+use serde::de::{Deserialize, DeserializeOwned};
+
+use super::data::{DataType, Value};
+use super::types::PackageType;
+use super::Rtde;
+use crate::prelude::*;
+
+/// Package borrowed from the connection's buffer, valid until the next read
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    package_type: PackageType,
+    payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn package_type(&self) -> PackageType {
+        self.package_type
+    }
+    pub fn is_data(&self) -> bool {
+        self.package_type == PackageType::Data
+    }
+    /// Payload after the header, including the recipe ID of data packages
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+    /// Recipe ID of a data package
+    pub fn recipe_id(&self) -> Option<u8> {
+        match self.is_data() {
+            true => self.payload.first().copied(),
+            false => None,
+        }
+    }
+    /// Decode the package, without the recipe ID of data packages
+    pub fn decode<T: Deserialize<'a>>(&self) -> Result<T> {
+        bincode::options()
+            .with_big_endian()
+            .with_fixint_encoding()
+            .deserialize(self.values_bytes())
+            .map_err(|error| Error::Deserialization(error.to_string()))
+    }
+    /// Decode a data package whose layout is only known at runtime into `values`, which must
+    /// hold one value per type
+    pub fn values_into(&self, types: &[DataType], values: &mut [Value]) -> Result<()> {
+        if types.len() != values.len() {
+            return Err(Error::Deserialization(format!(
+                "{} values cannot hold {} variables",
+                values.len(),
+                types.len()
+            )));
+        }
+        let mut buf = self.values_bytes();
+        for (var_type, value) in types.iter().zip(values) {
+            *value = var_type.decode(buf)?;
+            buf = buf.get(var_type.size()..).unwrap_or_default();
+        }
+        Ok(())
+    }
+    fn values_bytes(&self) -> &'a [u8] {
+        match self.is_data() {
+            true => self.payload.get(1..).unwrap_or_default(), // skip the recipe ID
+            false => self.payload,
+        }
+    }
+}
+
+impl Rtde {
+    /// Read the next package without copying it out of the connection's buffer
+    pub fn read_frame(&mut self) -> Result<Frame<'_>> {
+        let (package_type, _) = self.read_package()?;
+        Ok(Frame {
+            package_type,
+            payload: &self.buffer,
+        })
+    }
+    /// Read the next package and, if it is data, decode it into `sample`.
+    ///
+    /// Returns `false`, leaving `sample` untouched, for packages that are not data.
+    pub fn read_data_into<T: DeserializeOwned>(&mut self, sample: &mut T) -> Result<bool> {
+        let frame = self.read_frame()?;
+        if !frame.is_data() {
+            return Ok(false);
+        }
+        *sample = frame.decode()?;
+        Ok(true)
+    }
+    /// Read the next package and, if it is data, decode it into `values` using the output
+    /// recipe's types.
+    ///
+    /// Returns `false`, leaving `values` untouched, for packages that are not data.
+    pub fn read_values_into(&mut self, values: &mut [Value]) -> Result<bool> {
+        let (package_type, _) = self.read_package()?;
+        let frame = Frame {
+            package_type,
+            payload: &self.buffer,
+        };
+        if !frame.is_data() {
+            return Ok(false);
+        }
+        frame.values_into(&self.output, values)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::capture::{Direction, SessionCapture};
+    use crate::data::Vec6;
+    use crate::rtde::as_bytes;
+    use crate::test::temp_path;
+    use crate::types::Header;
+
+    fn package(package_type: PackageType, payload: &[u8]) -> Vec<u8> {
+        let mut bytes =
+            as_bytes(Header::new(package_type, Some(3 + payload.len() as u16))).unwrap();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+    /// Capture of `timestamp,actual_q` being set up, a message and two samples
+    fn write_capture(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let mut capture = SessionCapture::create(&path).unwrap();
+        let mut request = as_bytes(500.0_f64).unwrap();
+        request.extend_from_slice(b"timestamp,actual_q\r\n");
+        let records = [
+            (
+                Direction::Sent,
+                package(PackageType::SetupOutputs, &request),
+            ),
+            (
+                Direction::Received,
+                package(PackageType::SetupOutputs, b"\x01DOUBLE,VECTOR6D"),
+            ),
+            (Direction::Received, package(PackageType::Message, b"hello")),
+            (
+                Direction::Received,
+                package(
+                    PackageType::Data,
+                    &as_bytes((1u8, 0.002, Vec6::new(0.0, 1.0, 2.0, 3.0, 4.0, 5.0))).unwrap(),
+                ),
+            ),
+            (
+                Direction::Received,
+                package(
+                    PackageType::Data,
+                    &as_bytes((1u8, 0.004, Vec6::new(1.0, 1.0, 2.0, 3.0, 4.0, 5.0))).unwrap(),
+                ),
+            ),
+        ];
+        for (direction, bytes) in records {
+            capture.record(direction, &[&bytes]).unwrap();
+        }
+        capture.flush().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_data_into() {
+        let path = write_capture("frame_data.rtdecap");
+        let mut rtde = Rtde::replay(&path, false).unwrap();
+        let mut sample = (0.0, Vec6::default());
+        assert!(!rtde.read_data_into(&mut sample).unwrap());
+        assert!(rtde.read_data_into(&mut sample).unwrap());
+        assert_eq!(sample.0, 0.002);
+        let frame = rtde.read_frame().unwrap();
+        assert_eq!(frame.recipe_id(), Some(1));
+        assert_eq!(frame.decode::<(f64, Vec6)>().unwrap().1.x, 1.0);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_read_values_into() {
+        let path = write_capture("frame_values.rtdecap");
+        let mut rtde = Rtde::replay(&path, false).unwrap();
+        let mut values = [Value::U8(0); 2];
+        assert!(!rtde.read_values_into(&mut values).unwrap());
+        assert!(rtde.read_values_into(&mut values).unwrap());
+        assert_eq!(values[0], Value::F64(0.002));
+        assert_eq!(values[1].as_vec6().unwrap().rz, 5.0);
+        assert!(rtde.read_values_into(&mut values[..1]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! RTDE stream health
+//!
+//! When the controller is short of resources it skips output packages rather than sending them
+//! late, and nothing in the stream says so. The [`HealthMonitor`] compares the `timestamp`
+//! output against the configured frequency to count skipped cycles, measures the jitter of the
+//! packages' arrival, and, with an [`EchoRegister`], the round trip from writing an input
+//! register to seeing it in the outputs. Thresholds turn these into [`HealthAlarm`]s.
+//!
+//! ```ignore
+//! let mut echo = ur.rtde.setup_echo(24)?;
+//! let recipe = ur.start_telemetry(&["timestamp", &echo.output_name()], 500.0)?;
+//! let mut monitor = ur.rtde.health_monitor();
+//! let mut sample: (f64, i32) = Default::default();
+//! loop {
+//!     if ur.rtde.read_data_into(&mut sample)? {
+//!         monitor.echo_observed(sample.1);
+//!         for alarm in monitor.observe(sample.0) {
+//!             log::warn!("{alarm:?}");
+//!         }
+//!     }
+//!     echo.send(&mut ur.rtde, &mut monitor)?;
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::types::Recipe;
+use super::Rtde;
+use crate::prelude::*;
+use crate::rolling_buffer::RollingBuffer;
+
+/// Limits above which the monitor raises alarms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// Largest deviation of a package's arrival from the output period
+    pub max_jitter: Duration,
+    /// Number of consecutive skipped output packages
+    pub skipped_cycles: u64,
+    /// Longest echo register round trip
+    pub max_latency: Duration,
+}
+
+impl HealthThresholds {
+    /// One period of jitter, any skipped package and 20 ms of latency
+    pub fn for_frequency(frequency: f64) -> Self {
+        HealthThresholds {
+            max_jitter: Duration::from_secs_f64(1.0 / frequency),
+            skipped_cycles: 1,
+            max_latency: Duration::from_millis(20),
+        }
+    }
+}
+
+/// Threshold exceeded by the stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthAlarm {
+    /// Output packages the controller did not send
+    SkippedCycles(u64),
+    /// Deviation of a package's arrival from the output period
+    Jitter(Duration),
+    /// Round trip of the echo register
+    Latency(Duration),
+}
+
+/// Summary of the stream's timing over the monitor's window
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HealthMetrics {
+    /// Samples observed since the monitor was created
+    pub samples: u64,
+    /// Output packages skipped since the monitor was created
+    pub skipped: u64,
+    /// Samples per second according to the controller's timestamps
+    pub rate: f64,
+    /// Mean absolute deviation of arrival intervals from the period \[s\]
+    pub mean_jitter: f64,
+    /// Largest deviation of an arrival interval from the period \[s\]
+    pub max_jitter: f64,
+    /// Latest echo register round trip
+    pub latency: Option<Duration>,
+    /// Mean echo register round trip \[s\]
+    pub mean_latency: Option<f64>,
+    /// Longest echo register round trip \[s\]
+    pub max_latency: Option<f64>,
+}
+
+/// Tracks the timing of an RTDE output stream
+pub struct HealthMonitor {
+    /// Output period \[s\]
+    period: f64,
+    thresholds: HealthThresholds,
+    previous: Option<(f64, Instant)>,
+    samples: u64,
+    skipped: u64,
+    /// Controller timestamp intervals \[s\]
+    intervals: RollingBuffer<f64>,
+    /// Deviation of arrival intervals from the period \[s\]
+    jitter: RollingBuffer<f64>,
+    /// Echo register round trips \[s\]
+    latencies: RollingBuffer<f64>,
+    latency: Option<Duration>,
+    /// Echo values written and not yet seen, oldest first
+    pending: VecDeque<(i32, Instant)>,
+    subscribers: Vec<Sender<HealthAlarm>>,
+}
+
+impl HealthMonitor {
+    /// Metrics are calculated over this many of the latest samples
+    const WINDOW: usize = 500;
+    /// Echo values awaiting their round trip before the oldest are given up
+    const MAX_PENDING: usize = 64;
+
+    /// Monitor a stream configured with this output frequency \[Hz\]
+    pub fn new(frequency: f64) -> Self {
+        HealthMonitor {
+            period: 1.0 / frequency,
+            thresholds: HealthThresholds::for_frequency(frequency),
+            previous: None,
+            samples: 0,
+            skipped: 0,
+            intervals: RollingBuffer::new(Self::WINDOW),
+            jitter: RollingBuffer::new(Self::WINDOW),
+            latencies: RollingBuffer::new(Self::WINDOW),
+            latency: None,
+            pending: VecDeque::with_capacity(Self::MAX_PENDING),
+            subscribers: Vec::new(),
+        }
+    }
+    pub fn thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+    /// Receive every alarm raised from now on
+    pub fn subscribe(&mut self) -> Receiver<HealthAlarm> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+    /// Record a sample's `timestamp` output as it arrives, returning the alarms it raised
+    pub fn observe(&mut self, timestamp: f64) -> Vec<HealthAlarm> {
+        self.observe_at(timestamp, Instant::now())
+    }
+    /// Record a sample's `timestamp` output and when it arrived
+    pub fn observe_at(&mut self, timestamp: f64, arrival: Instant) -> Vec<HealthAlarm> {
+        let mut alarms = Vec::new();
+        self.samples += 1;
+        if let Some((previous, previous_arrival)) = self.previous.replace((timestamp, arrival)) {
+            let interval = timestamp - previous;
+            self.intervals.add(interval);
+            let skipped = ((interval / self.period).round() as u64).saturating_sub(1);
+            if skipped > 0 {
+                self.skipped += skipped;
+                if skipped >= self.thresholds.skipped_cycles {
+                    alarms.push(HealthAlarm::SkippedCycles(skipped));
+                }
+            }
+            // a late package after skipped ones is expected, so compare with the cycles it covers
+            let expected = self.period * (skipped + 1) as f64;
+            let arrived = arrival.saturating_duration_since(previous_arrival);
+            let jitter = (arrived.as_secs_f64() - expected).abs();
+            self.jitter.add(jitter);
+            if jitter > self.thresholds.max_jitter.as_secs_f64() {
+                alarms.push(HealthAlarm::Jitter(Duration::from_secs_f64(jitter)));
+            }
+        }
+        self.notify(&alarms);
+        alarms
+    }
+    /// Record an echo value written to the input register
+    pub fn echo_sent(&mut self, value: i32) {
+        self.echo_sent_at(value, Instant::now())
+    }
+    pub fn echo_sent_at(&mut self, value: i32, sent: Instant) {
+        if self.pending.len() == Self::MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((value, sent));
+    }
+    /// Record the echo register's value in an output sample, returning the alarm if its round
+    /// trip was too long
+    pub fn echo_observed(&mut self, value: i32) -> Option<HealthAlarm> {
+        self.echo_observed_at(value, Instant::now())
+    }
+    pub fn echo_observed_at(&mut self, value: i32, observed: Instant) -> Option<HealthAlarm> {
+        let index = self.pending.iter().position(|(sent, _)| *sent == value)?;
+        let (_, sent) = self.pending[index];
+        // anything written before it was superseded
+        self.pending.drain(..=index);
+        let latency = observed.saturating_duration_since(sent);
+        self.latency = Some(latency);
+        self.latencies.add(latency.as_secs_f64());
+        if latency <= self.thresholds.max_latency {
+            return None;
+        }
+        let alarm = HealthAlarm::Latency(latency);
+        self.notify(&[alarm]);
+        Some(alarm)
+    }
+    pub fn metrics(&self) -> HealthMetrics {
+        let intervals = self.intervals.window(Self::WINDOW);
+        let jitter = self.jitter.window(Self::WINDOW);
+        let latencies = self.latencies.window(Self::WINDOW);
+        HealthMetrics {
+            samples: self.samples,
+            skipped: self.skipped,
+            rate: intervals.mean().map_or(0.0, |interval| 1.0 / interval),
+            mean_jitter: jitter.mean().unwrap_or_default(),
+            max_jitter: jitter.max().unwrap_or_default(),
+            latency: self.latency,
+            mean_latency: latencies.mean(),
+            max_latency: latencies.max(),
+        }
+    }
+    fn notify(&mut self, alarms: &[HealthAlarm]) {
+        for alarm in alarms {
+            self.subscribers
+                .retain(|subscriber| subscriber.send(*alarm).is_ok());
+        }
+    }
+}
+
+/// Input integer register written with a counter and read back in the outputs to measure the
+/// round trip through the controller
+pub struct EchoRegister {
+    recipe: Recipe,
+    register: u8,
+    next: i32,
+}
+
+impl EchoRegister {
+    /// Output variable to add to the output recipe to read the register back
+    pub fn output_name(&self) -> String {
+        format!("input_int_register_{}", self.register)
+    }
+    /// Write the next counter value and record it with the monitor
+    pub fn send(&mut self, rtde: &mut Rtde, monitor: &mut HealthMonitor) -> Result<()> {
+        rtde.write(self.next, self.recipe.id())?;
+        monitor.echo_sent(self.next);
+        self.next = self.next.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl Rtde {
+    /// Monitor for the output stream at its configured frequency
+    pub fn health_monitor(&self) -> HealthMonitor {
+        HealthMonitor::new(self.frequency)
+    }
+    /// Set up an input recipe writing `input_int_register_<register>` for latency measurement.
+    ///
+    /// Registers 24 to 47 are free for RTDE clients, 0 to 23 are shared with the fieldbuses.
+    /// Must be set up before the output recipe is started.
+    pub fn setup_echo(&mut self, register: u8) -> Result<EchoRegister> {
+        if register > 47 {
+            return Err(Error::Static("input int registers are numbered 0 to 47"));
+        }
+        let recipe = self.setup_input(&[&format!("input_int_register_{register}")])?;
+        Ok(EchoRegister {
+            recipe,
+            register,
+            next: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skipped_cycles() {
+        let mut monitor = HealthMonitor::new(500.0);
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        assert!(monitor.observe_at(10.000, at(0.0)).is_empty());
+        assert!(monitor.observe_at(10.002, at(0.002)).is_empty());
+        // the controller skipped two packages, the next one arrives on time for its cycle
+        assert_eq!(
+            monitor.observe_at(10.008, at(0.008)),
+            vec![HealthAlarm::SkippedCycles(2)]
+        );
+        let metrics = monitor.metrics();
+        assert_eq!(metrics.samples, 3);
+        assert_eq!(metrics.skipped, 2);
+        assert!((metrics.rate - 250.0).abs() < 1e-6);
+    }
+    #[test]
+    fn test_jitter() {
+        let mut monitor = HealthMonitor::new(500.0);
+        let alarms = monitor.subscribe();
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        monitor.observe_at(1.000, at(0.0));
+        monitor.observe_at(1.002, at(0.0025));
+        // arrived 5 ms after the previous one instead of 2 ms
+        let raised = monitor.observe_at(1.004, at(0.0075));
+        assert_eq!(raised.len(), 1);
+        let HealthAlarm::Jitter(jitter) = raised[0] else {
+            panic!("expected jitter, got {raised:?}");
+        };
+        assert!((jitter.as_secs_f64() - 0.003).abs() < 1e-6);
+        assert_eq!(alarms.try_iter().collect::<Vec<_>>(), raised);
+        assert!((monitor.metrics().max_jitter - 0.003).abs() < 1e-6);
+    }
+    #[test]
+    fn test_echo_latency() {
+        let thresholds = HealthThresholds {
+            max_latency: Duration::from_millis(10),
+            ..HealthThresholds::for_frequency(500.0)
+        };
+        let mut monitor = HealthMonitor::new(500.0).thresholds(thresholds);
+        let start = Instant::now();
+        monitor.echo_sent_at(1, start);
+        monitor.echo_sent_at(2, start + Duration::from_millis(2));
+        // the register still holds the previous value
+        assert_eq!(monitor.echo_observed_at(0, start), None);
+        assert_eq!(monitor.metrics().latency, None);
+        assert_eq!(
+            monitor.echo_observed_at(2, start + Duration::from_millis(6)),
+            None
+        );
+        assert_eq!(monitor.metrics().latency, Some(Duration::from_millis(4)));
+        // 1 was superseded by 2 and is no longer pending
+        assert_eq!(
+            monitor.echo_observed_at(1, start + Duration::from_millis(30)),
+            None
+        );
+        monitor.echo_sent_at(3, start);
+        assert_eq!(
+            monitor.echo_observed_at(3, start + Duration::from_millis(15)),
+            Some(HealthAlarm::Latency(Duration::from_millis(15)))
+        );
+    }
+}
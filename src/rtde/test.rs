@@ -65,3 +65,25 @@ fn test_start_stop() {
     rtde.pause().unwrap();
     assert!(rtde.close().is_ok());
 }
+#[test]
+fn test_health_monitor() {
+    let mut rtde = init_rtde().unwrap();
+    let mut echo = rtde.setup_echo(24).unwrap();
+    rtde.setup_output(&["timestamp", &echo.output_name()], 500.0)
+        .unwrap();
+    let mut monitor = rtde.health_monitor();
+    rtde.start().unwrap();
+    let mut sample: (f64, i32) = Default::default();
+    for _ in 0..500 {
+        if rtde.read_data_into(&mut sample).unwrap() {
+            monitor.echo_observed(sample.1);
+            monitor.observe(sample.0);
+            echo.send(&mut rtde, &mut monitor).unwrap();
+        }
+    }
+    let metrics = monitor.metrics();
+    println!("{metrics:?}");
+    assert!(metrics.latency.is_some());
+    rtde.pause().unwrap();
+    assert!(rtde.close().is_ok());
+}
@@ -42,12 +42,77 @@ impl TryFrom<u8> for PackageType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Software version, ordered from major to build number
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Version {
-    major: u32,
-    minor: u32,
-    bugfix: u32,
-    build: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub bugfix: u32,
+    pub build: u32,
+}
+
+impl std::str::FromStr for Version {
+    type Err = Error;
+    /// Parse a dotted version such as `5.12.2.1101534`, missing parts are zero
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::UnexpectedResponse(format!("Invalid version: {s}"));
+        let parts = s
+            .trim()
+            .split('.')
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<u32>, _>>()
+            .map_err(|_| invalid())?;
+        if !(2..=4).contains(&parts.len()) {
+            return Err(invalid());
+        }
+        let part = |index: usize| parts.get(index).copied().unwrap_or(0);
+        Ok(Version {
+            major: part(0),
+            minor: part(1),
+            bugfix: part(2),
+            build: part(3),
+        })
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.bugfix, self.build
+        )
+    }
+}
+
+/// Program state reported by the `runtime_state` output
+#[repr(u32)]
+#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, Clone, Copy)]
+pub enum RuntimeState {
+    Stopping = 0,
+    Stopped = 1,
+    Playing = 2,
+    Pausing = 3,
+    Paused = 4,
+    Resuming = 5,
+}
+
+impl TryFrom<u32> for RuntimeState {
+    type Error = Error;
+    fn try_from(value: u32) -> Result<Self> {
+        match value {
+            0 => Ok(RuntimeState::Stopping),
+            1 => Ok(RuntimeState::Stopped),
+            2 => Ok(RuntimeState::Playing),
+            3 => Ok(RuntimeState::Pausing),
+            4 => Ok(RuntimeState::Paused),
+            5 => Ok(RuntimeState::Resuming),
+            num => Err(Error::UnexpectedResponse(format!(
+                "Unknown Runtime State: {}",
+                num
+            ))),
+        }
+    }
 }
 
 #[repr(u16)]
@@ -57,6 +122,7 @@ pub enum Protocol {
     V2 = 2,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Recipe {
     id: u8,
     var_types: Vec<DataType>,
@@ -169,3 +235,16 @@ impl Message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_parse() {
+        let version: Version = "5.12.2.1101534".parse().unwrap();
+        assert_eq!(version.to_string(), "5.12.2.1101534");
+        assert!(version > "5.9".parse().unwrap());
+        assert!("URSoftware".parse::<Version>().is_err());
+    }
+}
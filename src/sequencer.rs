@@ -0,0 +1,474 @@
+//! Guided power-up and recovery procedures
+//!
+//! Works out which of the dashboard commands are needed to get the robot from its current
+//! state to running a program, runs only those, logs each one to the robot log and reports
+//! the outcome of every step.
+//!
+//! ```ignore
+//! let report = ur.sequencer().bring_up("rtde_control_loop.urp")?;
+//! if !report.is_success() {
+//!     println!("{report}");
+//! }
+//! ```
+
+use std::fmt::Display;
+
+use crate::dashboard::types::{ProgramState, RobotMode, SafetyStatus};
+use crate::prelude::*;
+use crate::watcher::{RobotStatus, StatusField};
+
+/// Action taken by the sequencer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    ClosePopup,
+    UnlockProtectiveStop,
+    RestartSafety,
+    PowerOn,
+    ReleaseBrakes,
+    LoadProgram(String),
+    Play,
+    /// The robot is in a state that can only be cleared at the robot, e.g. an emergency stop
+    AwaitOperator(SafetyStatus),
+}
+
+/// What happened when a step was run
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepOutcome {
+    pub step: Step,
+    /// Error text if the step failed
+    pub error: Option<String>,
+    pub elapsed: Duration,
+}
+
+/// Outcome of every step taken, in order.  The sequence ends at the first failed step.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SequenceReport {
+    pub steps: Vec<StepOutcome>,
+}
+
+impl SequenceReport {
+    pub fn is_success(&self) -> bool {
+        self.steps.iter().all(|outcome| outcome.error.is_none())
+    }
+    /// The step that stopped the sequence
+    pub fn failed_step(&self) -> Option<&StepOutcome> {
+        self.steps.iter().find(|outcome| outcome.error.is_some())
+    }
+}
+
+impl Display for SequenceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for outcome in &self.steps {
+            match &outcome.error {
+                None => writeln!(f, "{:?}: done in {:?}", outcome.step, outcome.elapsed)?,
+                Some(error) => writeln!(f, "{:?}: failed, {}", outcome.step, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the bring up and recovery procedures on a robot
+pub struct Sequencer<'a> {
+    ur: &'a mut UniversalRobot,
+    timeout: Duration,
+    allow_safety_restart: bool,
+    report: SequenceReport,
+}
+
+impl<'a> Sequencer<'a> {
+    /// The controller refuses to unlock a protective stop until 5 seconds after it occurred
+    const UNLOCK_DELAY: Duration = Duration::from_secs(5);
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new(ur: &'a mut UniversalRobot) -> Self {
+        Sequencer {
+            ur,
+            timeout: Self::TIMEOUT,
+            allow_safety_restart: false,
+            report: SequenceReport::default(),
+        }
+    }
+    /// Time allowed for each step to take effect
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Allow restarting the safety system after a fault or violation.
+    ///
+    /// <b>Check the error log before allowing this, it is not done by the sequencer.</b>
+    pub fn allow_safety_restart(mut self, allow: bool) -> Self {
+        self.allow_safety_restart = allow;
+        self
+    }
+    /// Clear safety stops, power on and release the brakes, then load and play the program
+    pub fn bring_up(mut self, program: &str) -> Result<SequenceReport> {
+        if !self.clear_safety()? || !self.power_up()? {
+            return Ok(self.report);
+        }
+        let timeout = self.timeout;
+        let path = self.ur.dashboard.program_path(program);
+        if self.ur.dashboard.get_loaded_program()? != Some(path) {
+            let loaded = self.run(Step::LoadProgram(program.to_owned()), |ur| {
+                ur.load(program, timeout)
+            });
+            if !loaded {
+                return Ok(self.report);
+            }
+        }
+        if !matches!(
+            self.ur.dashboard.get_program_state()?,
+            ProgramState::Playing(_)
+        ) {
+            self.run(Step::Play, |ur| {
+                ur.dashboard.play()?;
+                ur.wait_for(
+                    &[StatusField::Program],
+                    |status| matches!(status.program, ProgramState::Playing(_)),
+                    timeout,
+                )?;
+                Ok(())
+            });
+        }
+        Ok(self.report)
+    }
+    /// Clear safety stops, power on and release the brakes, leaving the program stopped
+    pub fn recover(mut self) -> Result<SequenceReport> {
+        if self.clear_safety()? {
+            self.power_up()?;
+        }
+        Ok(self.report)
+    }
+    /// Returns false if the robot is still stopped
+    fn clear_safety(&mut self) -> Result<bool> {
+        let timeout = self.timeout;
+        let is_clear = |status: &RobotStatus| {
+            matches!(status.safety, SafetyStatus::Normal | SafetyStatus::Reduced)
+        };
+        match self.ur.dashboard.safety_status()? {
+            SafetyStatus::Normal | SafetyStatus::Reduced => Ok(true),
+            SafetyStatus::ProtectiveStop => Ok(self.run(Step::ClosePopup, |ur| {
+                ur.dashboard.safety_popup_close()?;
+                Ok(())
+            }) && self.run(Step::UnlockProtectiveStop, |ur| {
+                if let Err(error) = ur.dashboard.safety_unlock_protective_stop() {
+                    // we can't tell when the stop happened, so allow the full delay once
+                    log::debug!(
+                        "unlock refused ({error}), retrying in {:?}",
+                        Self::UNLOCK_DELAY
+                    );
+                    sleep(Self::UNLOCK_DELAY);
+                    ur.dashboard.safety_unlock_protective_stop()?;
+                }
+                ur.wait_for(&[StatusField::Safety], is_clear, timeout)?;
+                Ok(())
+            })),
+            SafetyStatus::Violation | SafetyStatus::Fault if self.allow_safety_restart => {
+                Ok(self.run(Step::ClosePopup, |ur| {
+                    ur.dashboard.safety_popup_close()?;
+                    Ok(())
+                }) && self.run(Step::RestartSafety, |ur| {
+                    ur.dashboard.safety_restart()?;
+                    ur.wait_for(&[StatusField::Safety], is_clear, timeout)?;
+                    Ok(())
+                }))
+            }
+            SafetyStatus::Violation | SafetyStatus::Fault => {
+                self.fail(Step::RestartSafety, "safety restart not allowed");
+                Ok(false)
+            }
+            other => {
+                self.fail(Step::AwaitOperator(other), "must be cleared at the robot");
+                Ok(false)
+            }
+        }
+    }
+    /// Returns false if the robot could not be powered up
+    fn power_up(&mut self) -> Result<bool> {
+        let timeout = self.timeout;
+        let mode = self.ur.dashboard.get_mode()?;
+        if matches!(mode, RobotMode::PowerOff | RobotMode::PowerOn)
+            && !self.run(Step::PowerOn, |ur| ur.power_on(timeout))
+        {
+            return Ok(false);
+        }
+        if mode != RobotMode::Running {
+            return Ok(self.run(Step::ReleaseBrakes, |ur| {
+                // brakes can only be released once the arm has finished powering on
+                ur.wait_for(
+                    &[StatusField::Mode],
+                    |status| matches!(status.mode, RobotMode::Idle | RobotMode::Running),
+                    timeout,
+                )?;
+                ur.dashboard.brake_release()?;
+                ur.wait_for(
+                    &[StatusField::Mode],
+                    |status| status.mode == RobotMode::Running,
+                    timeout,
+                )?;
+                Ok(())
+            }));
+        }
+        Ok(true)
+    }
+    /// Run a step and record its outcome, returns true if it succeeded
+    fn run<F>(&mut self, step: Step, action: F) -> bool
+    where
+        F: FnOnce(&mut UniversalRobot) -> Result<()>,
+    {
+        if let Err(error) = self.ur.dashboard.log(&format!("sequencer: {step:?}")) {
+            log::warn!("could not log step to robot: {error}");
+        }
+        let now = Instant::now();
+        let result = action(self.ur);
+        let error = result.err().map(|error| error.to_string());
+        self.report.steps.push(StepOutcome {
+            step,
+            error: error.clone(),
+            elapsed: now.elapsed(),
+        });
+        error.is_none()
+    }
+    /// Record a step that can't be taken
+    fn fail(&mut self, step: Step, reason: &str) {
+        self.report.steps.push(StepOutcome {
+            step,
+            error: Some(reason.to_owned()),
+            elapsed: Duration::ZERO,
+        });
+    }
+}
+
+impl UniversalRobot {
+    /// Guided bring up and recovery procedures for this robot
+    pub fn sequencer(&mut self) -> Sequencer<'_> {
+        Sequencer::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::config::Ports;
+    use crate::dashboard::policy::CommandCategory;
+    use crate::physical::tests::closed_ports;
+
+    /// State of a scripted robot, changed by the commands its dashboard server receives
+    struct Robot {
+        mode: &'static str,
+        safety: &'static str,
+        program: Option<&'static str>,
+        playing: bool,
+        /// Unlock requests refused before one is accepted, as if the stop just occurred
+        unlock_refusals: usize,
+    }
+
+    impl Robot {
+        fn reply(&mut self, command: &str) -> String {
+            let reply = match command {
+                "robotmode" => return format!("Robotmode: {}", self.mode),
+                "safetystatus" => return format!("Safetystatus: {}", self.safety),
+                "programState" => {
+                    let state = if self.playing { "PLAYING" } else { "STOPPED" };
+                    return format!("{state} {}", self.program.unwrap_or("<unnamed>"));
+                }
+                "get loaded program" => match self.program {
+                    Some(program) => return format!("Loaded program: {program}"),
+                    None => "No program loaded",
+                },
+                "close safety popup" => "closing safety popup",
+                "unlock protective stop" if self.unlock_refusals > 0 => {
+                    self.unlock_refusals -= 1;
+                    "Cannot unlock protective stop until 5s after occurrence"
+                }
+                "unlock protective stop" => {
+                    self.safety = "NORMAL";
+                    "Protective stop releasing"
+                }
+                "restart safety" => {
+                    (self.safety, self.mode) = ("NORMAL", "POWER_OFF");
+                    "Restarting safety"
+                }
+                "power on" => {
+                    self.mode = "IDLE";
+                    "Powering on"
+                }
+                "brake release" => {
+                    self.mode = "RUNNING";
+                    "Brake releasing"
+                }
+                "play" => {
+                    self.playing = true;
+                    "Starting program"
+                }
+                "Load pick.urp" => {
+                    (self.program, self.playing) = (Some("pick.urp"), false);
+                    "Loading program: pick.urp"
+                }
+                _ => "Added log message",
+            };
+            reply.to_owned()
+        }
+    }
+
+    /// Robot whose dashboard server plays `robot`, and the commands other than queries it sent
+    fn scripted_robot(mut robot: Robot) -> (UniversalRobot, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&commands);
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "Connected: Universal Robots Dashboard Server").unwrap();
+            for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                let Ok(command) = line else { break };
+                if CommandCategory::of(&command) != CommandCategory::Query {
+                    received.lock().unwrap().push(command.clone());
+                }
+                if writeln!(stream, "{}", robot.reply(&command)).is_err() {
+                    break;
+                }
+            }
+        });
+        let ur = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(Ports {
+                dashboard: port,
+                ..closed_ports()
+            })
+            .lazy(true)
+            .connect()
+            .unwrap();
+        (ur, commands)
+    }
+
+    fn steps(report: &SequenceReport) -> Vec<Step> {
+        report
+            .steps
+            .iter()
+            .map(|outcome| outcome.step.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_running_robot_is_left_alone() {
+        let (mut ur, commands) = scripted_robot(Robot {
+            mode: "RUNNING",
+            safety: "NORMAL",
+            program: Some("pick.urp"),
+            playing: true,
+            unlock_refusals: 0,
+        });
+        let report = ur.sequencer().bring_up("pick.urp").unwrap();
+        assert!(report.steps.is_empty(), "{report}");
+        assert!(commands.lock().unwrap().is_empty());
+    }
+    #[test]
+    fn test_bring_up_from_power_off() {
+        let (mut ur, commands) = scripted_robot(Robot {
+            mode: "POWER_OFF",
+            safety: "NORMAL",
+            program: None,
+            playing: false,
+            unlock_refusals: 0,
+        });
+        let report = ur.sequencer().bring_up("pick.urp").unwrap();
+        assert!(report.is_success(), "{report}");
+        assert_eq!(
+            steps(&report),
+            [
+                Step::PowerOn,
+                Step::ReleaseBrakes,
+                Step::LoadProgram("pick.urp".to_owned()),
+                Step::Play
+            ]
+        );
+        assert_eq!(
+            *commands.lock().unwrap(),
+            ["power on", "brake release", "Load pick.urp", "play"]
+        );
+    }
+    #[test]
+    fn test_protective_stop() {
+        let (mut ur, commands) = scripted_robot(Robot {
+            mode: "IDLE",
+            safety: "PROTECTIVE_STOP",
+            program: Some("pick.urp"),
+            playing: false,
+            unlock_refusals: 1,
+        });
+        let report = ur
+            .sequencer()
+            .timeout(Duration::from_secs(2))
+            .recover()
+            .unwrap();
+        assert!(report.is_success(), "{report}");
+        assert_eq!(
+            steps(&report),
+            [
+                Step::ClosePopup,
+                Step::UnlockProtectiveStop,
+                Step::ReleaseBrakes
+            ]
+        );
+        // the refused unlock is retried once the stop is old enough
+        assert!(report.steps[1].elapsed >= Sequencer::UNLOCK_DELAY);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                "close safety popup",
+                "unlock protective stop",
+                "unlock protective stop",
+                "brake release"
+            ]
+        );
+    }
+    #[test]
+    fn test_violation() {
+        let violation = || Robot {
+            mode: "IDLE",
+            safety: "VIOLATION",
+            program: None,
+            playing: false,
+            unlock_refusals: 0,
+        };
+        let (mut ur, commands) = scripted_robot(violation());
+        let report = ur.sequencer().recover().unwrap();
+        assert_eq!(steps(&report), [Step::RestartSafety]);
+        assert_eq!(
+            report.failed_step().unwrap().error.as_deref(),
+            Some("safety restart not allowed")
+        );
+        assert!(commands.lock().unwrap().is_empty());
+
+        let (mut ur, commands) = scripted_robot(violation());
+        let report = ur
+            .sequencer()
+            .allow_safety_restart(true)
+            .timeout(Duration::from_secs(2))
+            .recover()
+            .unwrap();
+        assert!(report.is_success(), "{report}");
+        assert_eq!(
+            steps(&report),
+            [
+                Step::ClosePopup,
+                Step::RestartSafety,
+                Step::PowerOn,
+                Step::ReleaseBrakes
+            ]
+        );
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                "close safety popup",
+                "restart safety",
+                "power on",
+                "brake release"
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Lock-free rolling buffer shared between threads
+//!
+//! One [`SharedWriter`], usually the loop reading the RTDE stream, pushes samples while any
+//! number of [`SharedReader`]s take snapshots without ever blocking it. Each slot is guarded by
+//! its own sequence number (a seqlock): the writer marks the slot busy, writes, then publishes
+//! the sample's position, and readers discard any copy whose sequence changed while they read.
+//! Samples are copied through atomic words, so a reader racing the writer never reads memory
+//! being written non-atomically. Sample types implement [`NoPadding`].
+//!
+//! ```ignore
+//! let mut writer = SharedWriter::<ForceTorque>::new(500);
+//! let reader = writer.reader();
+//! std::thread::spawn(move || loop {
+//!     ur.rtde.read_into(&mut writer).unwrap();
+//! });
+//! let forces = reader.snapshot();
+//! ```
+
+use std::marker::PhantomData;
+use std::mem::{size_of, MaybeUninit};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::data::{DefaultOutputs, ForceTorque, IoState, JointHealth, Vec3, Vec6};
+use crate::prelude::*;
+use crate::Rtde;
+
+/// Sample types that can be shared: plain values whose every byte is part of a field.
+///
+/// Samples are copied in and out of the buffer as `u64` words, which must not read padding.
+///
+/// # Safety
+///
+/// The type must have no padding bytes, e.g. a struct of fields that all have the same
+/// alignment, and must not hold references or anything that is not `Copy`.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($type:ty),*) => {
+        // SAFETY: primitives have no padding
+        $(unsafe impl NoPadding for $type {})*
+    };
+    ($($type:ty = $fields:expr),*) => {
+        $(
+            // no padding if the fields fill the whole struct
+            const _: () = assert!(size_of::<$type>() == $fields);
+            // SAFETY: checked above
+            unsafe impl NoPadding for $type {}
+        )*
+    };
+}
+no_padding!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64, bool);
+no_padding!(
+    Vec3 = 3 * 8,
+    Vec6 = 6 * 8,
+    DefaultOutputs = 8 + 8 + 3 * 48 + 2 * 4,
+    JointHealth = 8 + 4 * 48 + 6 * 4 + 3 * 8,
+    ForceTorque = 8 + 48 + 8 + 48 + 24,
+    IoState = 3 * 8 + 4 + 2 * 16 + 8 + 16 + 4
+);
+// SAFETY: array elements are laid out back to back without padding
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+const WORD: usize = size_of::<u64>();
+
+struct Slot {
+    /// `2 * position + 1` while being written, `2 * position + 2` once the sample at that
+    /// position is published
+    sequence: AtomicU64,
+    /// The sample's bytes, the last word zero-padded
+    words: Box<[AtomicU64]>,
+}
+
+struct Shared<T> {
+    slots: Box<[Slot]>,
+    /// Number of samples written so far
+    written: AtomicU64,
+    sample: PhantomData<T>,
+}
+
+impl<T: NoPadding> Shared<T> {
+    fn slot(&self, position: u64) -> &Slot {
+        &self.slots[(position % self.slots.len() as u64) as usize]
+    }
+    /// Copy of the sample at `position`, if it is published and not yet overwritten
+    fn read(&self, position: u64) -> Option<T> {
+        let slot = self.slot(position);
+        let published = 2 * position + 2;
+        if slot.sequence.load(Ordering::Acquire) != published {
+            return None;
+        }
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = value.as_mut_ptr().cast::<u8>();
+        for (index, word) in slot.words.iter().enumerate() {
+            let word = word.load(Ordering::Relaxed).to_ne_bytes();
+            let len = WORD.min(size_of::<T>() - index * WORD);
+            // SAFETY: the words hold `size_of::<T>()` bytes, so this stays inside `value`
+            unsafe { std::ptr::copy_nonoverlapping(word.as_ptr(), bytes.add(index * WORD), len) };
+        }
+        fence(Ordering::Acquire);
+        match slot.sequence.load(Ordering::Relaxed) == published {
+            // SAFETY: the sequence did not change while the words were loaded, so they are
+            // all from the sample the writer published, a valid `T`
+            true => Some(unsafe { value.assume_init() }),
+            false => None,
+        }
+    }
+    /// Store a sample's bytes in a slot
+    fn write(slot: &Slot, value: &T) {
+        let bytes = (value as *const T).cast::<u8>();
+        for (index, word) in slot.words.iter().enumerate() {
+            let mut buf = [0u8; WORD];
+            let len = WORD.min(size_of::<T>() - index * WORD);
+            // SAFETY: reads stay inside `value`, whose bytes are all initialised as `T` has
+            // no padding
+            unsafe {
+                std::ptr::copy_nonoverlapping(bytes.add(index * WORD), buf.as_mut_ptr(), len)
+            };
+            word.store(u64::from_ne_bytes(buf), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writing end of a shared rolling buffer, not cloneable so there is only ever one writer
+pub struct SharedWriter<T> {
+    shared: Arc<Shared<T>>,
+    position: u64,
+}
+
+impl<T: NoPadding> SharedWriter<T> {
+    /// Create a buffer holding the newest `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "shared buffer capacity must be at least 1");
+        let words = size_of::<T>().div_ceil(WORD);
+        let slots = (0..capacity)
+            .map(|_| Slot {
+                sequence: AtomicU64::new(0),
+                words: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            })
+            .collect();
+        SharedWriter {
+            shared: Arc::new(Shared {
+                slots,
+                written: AtomicU64::new(0),
+                sample: PhantomData,
+            }),
+            position: 0,
+        }
+    }
+    /// A new reader of this buffer
+    pub fn reader(&self) -> SharedReader<T> {
+        SharedReader {
+            shared: self.shared.clone(),
+            position: self.shared.written.load(Ordering::Acquire),
+        }
+    }
+    /// Add a sample, overwriting the oldest once full. Never blocks.
+    pub fn push(&mut self, value: T) {
+        let slot = self.shared.slot(self.position);
+        slot.sequence
+            .store(2 * self.position + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        Shared::write(slot, &value);
+        slot.sequence
+            .store(2 * self.position + 2, Ordering::Release);
+        self.position += 1;
+        self.shared.written.store(self.position, Ordering::Release);
+    }
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+    /// Number of samples pushed since the buffer was created
+    pub fn written(&self) -> u64 {
+        self.position
+    }
+}
+
+/// Reading end of a shared rolling buffer, clone it for each reading thread
+#[derive(Clone)]
+pub struct SharedReader<T> {
+    shared: Arc<Shared<T>>,
+    /// Next position returned by [`SharedReader::poll`]
+    position: u64,
+}
+
+impl<T: NoPadding> SharedReader<T> {
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+    /// Number of samples pushed since the buffer was created
+    pub fn written(&self) -> u64 {
+        self.shared.written.load(Ordering::Acquire)
+    }
+    /// The newest sample
+    pub fn latest(&self) -> Option<T> {
+        loop {
+            let written = self.written();
+            if written == 0 {
+                return None;
+            }
+            if let Some(value) = self.shared.read(written - 1) {
+                return Some(value);
+            }
+        }
+    }
+    /// The buffered samples, oldest first
+    pub fn snapshot(&self) -> Vec<T> {
+        let mut values = Vec::with_capacity(self.capacity());
+        self.snapshot_into(&mut values);
+        values
+    }
+    /// Replace the contents of `values` with the buffered samples, oldest first, reusing its
+    /// allocation.
+    ///
+    /// The samples are consecutive: if the writer overwrites the oldest ones while they are
+    /// being copied, they are left out rather than mixed with newer samples.
+    pub fn snapshot_into(&self, values: &mut Vec<T>) {
+        values.clear();
+        let written = self.written();
+        let oldest = written.saturating_sub(self.capacity() as u64);
+        // newest first, so a lapping writer only ever cuts off the oldest end
+        for position in (oldest..written).rev() {
+            match self.shared.read(position) {
+                Some(value) => values.push(value),
+                None => break,
+            }
+        }
+        values.reverse();
+    }
+    /// Append the samples pushed since the last poll, or since this reader was created, to
+    /// `values`. Returns how many were overwritten before they could be read.
+    pub fn poll(&mut self, values: &mut Vec<T>) -> u64 {
+        let written = self.written();
+        let oldest = written.saturating_sub(self.capacity() as u64);
+        let mut missed = oldest.saturating_sub(self.position);
+        for position in self.position.max(oldest)..written {
+            match self.shared.read(position) {
+                Some(value) => values.push(value),
+                None => missed += 1,
+            }
+        }
+        self.position = written;
+        missed
+    }
+}
+
+impl Rtde {
+    /// Read one package and push it to `writer` if it is data matching `T`.
+    ///
+    /// Returns `false` for packages that are not data, such as text messages.
+    pub fn read_into<T>(&mut self, writer: &mut SharedWriter<T>) -> Result<bool>
+    where
+        T: DeserializeOwned + NoPadding,
+    {
+        let frame = self.read_frame()?;
+        if !frame.is_data() {
+            return Ok(false);
+        }
+        writer.push(frame.decode()?);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_and_latest() {
+        let mut writer = SharedWriter::new(4);
+        let reader = writer.reader();
+        assert_eq!(reader.latest(), None);
+        assert!(reader.snapshot().is_empty());
+        for num in 0..6_u32 {
+            writer.push(num);
+        }
+        assert_eq!(reader.latest(), Some(5));
+        assert_eq!(reader.snapshot(), vec![2, 3, 4, 5]);
+        assert_eq!(reader.written(), 6);
+    }
+    #[test]
+    fn test_poll() {
+        let mut writer = SharedWriter::new(3);
+        let mut reader = writer.reader();
+        let mut values = Vec::new();
+        writer.push(1_u8);
+        writer.push(2);
+        assert_eq!(reader.poll(&mut values), 0);
+        assert_eq!(values, vec![1, 2]);
+        for num in 3..=7 {
+            writer.push(num);
+        }
+        values.clear();
+        // 3 and 4 were overwritten before the reader caught up
+        assert_eq!(reader.poll(&mut values), 2);
+        assert_eq!(values, vec![5, 6, 7]);
+    }
+    #[test]
+    fn test_concurrent_snapshots_are_consistent() {
+        #[derive(Clone, Copy)]
+        struct Sample {
+            index: u64,
+            check: [u64; 8],
+        }
+        // SAFETY: only u64 fields
+        unsafe impl NoPadding for Sample {}
+        let mut writer = SharedWriter::<Sample>::new(64);
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let reader = writer.reader();
+                std::thread::spawn(move || {
+                    let mut values = Vec::new();
+                    for _ in 0..2_000 {
+                        reader.snapshot_into(&mut values);
+                        for pair in values.windows(2) {
+                            assert_eq!(pair[0].index + 1, pair[1].index);
+                        }
+                        for sample in &values {
+                            assert!(sample.check.iter().all(|&check| check == sample.index));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for index in 0..200_000 {
+            writer.push(Sample {
+                index,
+                check: [index; 8],
+            });
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
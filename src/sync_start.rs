@@ -0,0 +1,416 @@
+//! Synchronised motion start across robots
+//!
+//! Each robot is armed with a program that reports ready on an output integer register and then
+//! waits, one control cycle at a time, for a go on the input integer register of the same number.
+//! Once every robot reports ready, the go is written to all of them back to back over RTDE, so
+//! they start in the same cycle. The start skew is measured from the `timestamp` of the first
+//! package each robot sends after starting.
+//!
+//! ```ignore
+//! let report = SyncStart::new(30).start(&mut [
+//!     ("left", &mut left, &reach_left),
+//!     ("right", &mut right, &reach_right),
+//! ])?;
+//! assert!(report.within_one_cycle(), "arms started {:?} apart", report.skew);
+//! ```
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dashboard::types::SafetyStatus;
+use crate::data::{DataType, Value};
+use crate::identity::ControllerGeneration;
+use crate::motion::{Motion, MotionError, MOTION_OUTPUTS};
+use crate::prelude::*;
+use crate::urscript::{Block, Call, Expr, Op, Program};
+
+/// Arms robots with their motions and releases them together
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncStart {
+    register: u8,
+    frequency: Option<f64>,
+    ready_timeout: Duration,
+}
+
+/// When each robot started its motion
+#[derive(Debug, Clone, PartialEq)]
+pub struct StartReport {
+    pub robots: Vec<RobotStart>,
+    /// Time between the first and last robot starting
+    pub skew: Duration,
+    /// Control cycle of the slowest robot, the resolution of the measurement
+    pub period: Duration,
+}
+
+impl StartReport {
+    pub fn within_one_cycle(&self) -> bool {
+        self.skew <= self.period
+    }
+}
+
+/// Start of one robot's motion
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotStart {
+    pub robot: String,
+    /// Controller `timestamp` of the cycle the motion started in \[s\]
+    pub timestamp: f64,
+    /// From writing the go to the motion starting, estimated on this computer's clock
+    pub delay: Duration,
+}
+
+/// Handshake progress of one armed robot
+struct Armed {
+    input: u8,
+    period: f64,
+    values: Vec<Value>,
+    /// Smallest difference between this computer's clock and the controller's timestamp seen,
+    /// which maps controller timestamps onto this computer's clock \[s\]
+    offset: f64,
+    ready: bool,
+    started: Option<f64>,
+}
+
+impl SyncStart {
+    /// Time allowed for every robot to report ready, and then to start
+    pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+    /// Name of the URScript function the armed motions are sent as
+    const PROGRAM_NAME: &'static str = "rust_sync_motion";
+    /// Indices in the output recipe, [`MOTION_OUTPUTS`] so the motions can be followed
+    /// afterwards, then the register
+    const TIMESTAMP: usize = 0;
+    const SAFETY_MODE: usize = 3;
+    const REGISTER: usize = 4;
+
+    /// Handshake on `output_int_register_<register>` and `input_int_register_<register>`.
+    ///
+    /// Registers 24 to 47 are free for RTDE clients, 0 to 23 are shared with the fieldbuses.
+    pub fn new(register: u8) -> Self {
+        SyncStart {
+            register,
+            frequency: None,
+            ready_timeout: Self::READY_TIMEOUT,
+        }
+    }
+    /// RTDE output frequency while arming \[Hz\], by default each robot's control frequency
+    pub fn frequency(mut self, frequency: f64) -> Self {
+        self.frequency = Some(frequency);
+        self
+    }
+    pub fn ready_timeout(mut self, timeout: Duration) -> Self {
+        self.ready_timeout = timeout;
+        self
+    }
+    /// The motion's program, run once the go with this token is written
+    pub fn program(&self, motion: &Motion, token: i32) -> Program {
+        let register = i32::from(self.register);
+        let write_output = |value: i32| {
+            Call::new("write_output_integer_register")
+                .arg(register)
+                .arg(value)
+        };
+        let go = Expr::from(Call::new("read_input_integer_register").arg(register));
+        let handshake = Block::new()
+            .call(write_output(token))
+            .while_loop(
+                go.binary(Op::Ne, token),
+                Block::new().call(Call::new("sync")),
+            )
+            .call(write_output(-token));
+        Program::new(Self::PROGRAM_NAME).body(handshake.extend(motion.to_block()))
+    }
+    /// Arm every robot with its motion, wait until all are ready, release them together and
+    /// wait until all have started.
+    ///
+    /// Replaces each robot's RTDE output recipe with [`MOTION_OUTPUTS`] and the register, unless
+    /// an earlier start set it up, and stops any program already running. If a robot fails to arm or start, the programs of all
+    /// of them are stopped.
+    pub fn start(
+        &self,
+        robots: &mut [(&str, &mut UniversalRobot, &Motion)],
+    ) -> Result<StartReport> {
+        let result = self.arm_and_release(robots);
+        if result.is_err() {
+            for (name, ur, _) in robots.iter_mut() {
+                if let Err(error) = ur.dashboard.stop() {
+                    log::error!("failed to stop {name} after a failed synchronised start: {error}");
+                }
+            }
+        }
+        result
+    }
+    fn arm_and_release(
+        &self,
+        robots: &mut [(&str, &mut UniversalRobot, &Motion)],
+    ) -> Result<StartReport> {
+        if self.register > 47 {
+            return Err(Error::Static("int registers are numbered 0 to 47"));
+        }
+        let token = token();
+        let mut armed = Vec::with_capacity(robots.len());
+        for (_, ur, motion) in robots.iter_mut() {
+            armed.push(self.arm(ur, motion, token)?);
+        }
+        let epoch = Instant::now();
+
+        let deadline = Instant::now() + self.ready_timeout;
+        while armed.iter().any(|robot| !robot.ready) {
+            self.observe(robots, &mut armed, epoch, token)?;
+            if Instant::now() > deadline {
+                let name = robots[armed.iter().position(|robot| !robot.ready).unwrap_or(0)].0;
+                return Err(MotionError::NotReady(name.to_owned(), self.ready_timeout).into());
+            }
+        }
+        let released = epoch.elapsed().as_secs_f64();
+        for ((_, ur, _), robot) in robots.iter_mut().zip(&armed) {
+            ur.rtde.write(token, robot.input)?;
+        }
+
+        let deadline = Instant::now() + self.ready_timeout;
+        while armed.iter().any(|robot| robot.started.is_none()) {
+            self.observe(robots, &mut armed, epoch, token)?;
+            if Instant::now() > deadline {
+                return Err(MotionError::NotStarted(self.ready_timeout).into());
+            }
+        }
+        let starts: Vec<RobotStart> = robots
+            .iter()
+            .zip(&armed)
+            .map(|((name, _, _), robot)| {
+                let timestamp = robot.started.unwrap_or_default();
+                RobotStart {
+                    robot: name.to_string(),
+                    timestamp,
+                    delay: Duration::from_secs_f64((timestamp + robot.offset - released).max(0.0)),
+                }
+            })
+            .collect();
+        let (earliest, latest) = armed
+            .iter()
+            .map(|robot| robot.started.unwrap_or_default() + robot.offset)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), start| {
+                (min.min(start), max.max(start))
+            });
+        let period = armed.iter().map(|robot| robot.period).fold(0.0, f64::max);
+        Ok(StartReport {
+            robots: starts,
+            skew: Duration::from_secs_f64((latest - earliest).max(0.0)),
+            period: Duration::from_secs_f64(period),
+        })
+    }
+    /// Set up the handshake recipes and send the armed program
+    fn arm(&self, ur: &mut UniversalRobot, motion: &Motion, token: i32) -> Result<Armed> {
+        let frequency = match self.frequency {
+            Some(frequency) => frequency,
+            None => match ur.identity()?.generation {
+                ControllerGeneration::CB3 => 125.0,
+                ControllerGeneration::ESeries => 500.0,
+            },
+        };
+        let register = format!("output_int_register_{}", self.register);
+        let mut outputs = MOTION_OUTPUTS.to_vec();
+        outputs.push(&register);
+        // set up on the first start, and kept for later ones
+        let input = ur
+            .rtde
+            .setup_input(&[&format!("input_int_register_{}", self.register)])?;
+        let set_up = ur.rtde.output_names().iter().eq(&outputs) && ur.rtde.frequency() == frequency;
+        if !set_up {
+            ur.rtde.clear_output()?;
+            let output = ur.rtde.setup_output(&outputs, frequency)?;
+            if output.get_types().contains(&DataType::NotFound) {
+                return Err(Error::UnexpectedResponse(format!(
+                    "{register} is not available as an RTDE output"
+                )));
+            }
+        }
+        if !ur.rtde.is_streaming() {
+            ur.rtde.start()?;
+        }
+        let program = self.program(&motion.with_tool(&ur.config().tool), token);
+        ur.send_script(&program.render()?)?;
+        Ok(Armed {
+            input: input.id(),
+            period: 1.0 / frequency,
+            values: vec![Value::U8(0); outputs.len()],
+            offset: f64::INFINITY,
+            ready: false,
+            started: None,
+        })
+    }
+    /// Read the next package of every robot
+    fn observe(
+        &self,
+        robots: &mut [(&str, &mut UniversalRobot, &Motion)],
+        armed: &mut [Armed],
+        epoch: Instant,
+        token: i32,
+    ) -> Result<()> {
+        for ((name, ur, _), robot) in robots.iter_mut().zip(armed.iter_mut()) {
+            if !ur.rtde.read_values_into(&mut robot.values)? {
+                continue;
+            }
+            let now = epoch.elapsed().as_secs_f64();
+            let timestamp = robot.values[Self::TIMESTAMP].as_f64().unwrap_or_default();
+            robot.offset = robot.offset.min(now - timestamp);
+            match robot.values[Self::SAFETY_MODE] {
+                Value::I32(mode) => match SafetyStatus::try_from(mode)? {
+                    SafetyStatus::Normal | SafetyStatus::Reduced => {}
+                    SafetyStatus::ProtectiveStop => return Err(MotionError::ProtectiveStop.into()),
+                    other => return Err(MotionError::SafetyStop(other).into()),
+                },
+                other => {
+                    return Err(Error::UnexpectedResponse(format!(
+                        "{name} safety_mode {other:?}"
+                    )))
+                }
+            }
+            match robot.values[Self::REGISTER] {
+                Value::I32(value) if value == token => robot.ready = true,
+                Value::I32(value) if value == -token && robot.started.is_none() => {
+                    robot.ready = true;
+                    robot.started = Some(timestamp);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handshake value that differs from run to run, so a register left over from an earlier run
+/// can't release a robot
+fn token() -> i32 {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    (millis % i32::MAX as u128).max(1) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Ports;
+    use crate::data::Vec6;
+    use crate::motion::{Move, Target};
+    use crate::rtde::as_bytes;
+    use crate::types::{Header, PackageType};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::sync::mpsc;
+
+    /// Types of [`MOTION_OUTPUTS`] and the register
+    const TYPES: &str = "DOUBLE,VECTOR6D,UINT32,INT32,INT32";
+
+    fn package(package_type: PackageType, payload: &[u8]) -> Vec<u8> {
+        let mut bytes =
+            as_bytes(Header::new(package_type, Some(3 + payload.len() as u16))).unwrap();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+    /// Data package with the output register set to `value`
+    fn sample(value: i32) -> Vec<u8> {
+        let normal = 1i32;
+        let payload = as_bytes((1u8, 0.0, Vec6::default(), 1u32, normal, value)).unwrap();
+        package(PackageType::Data, &payload)
+    }
+    /// Robot that reports ready for every program sent to its primary interface and starts once
+    /// the go is written over RTDE. Like a controller, it reports an input register `IN_USE`
+    /// when it is set up a second time. Returns the primary and RTDE ports.
+    fn handshaking_robot(register: u8) -> (u16, u16) {
+        let rtde = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let primary = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let ports = (
+            primary.local_addr().unwrap().port(),
+            rtde.local_addr().unwrap().port(),
+        );
+        let (connected, rtde_stream) = mpsc::channel::<TcpStream>();
+        std::thread::spawn(move || {
+            let (mut stream, _) = rtde.accept().unwrap();
+            connected.send(stream.try_clone().unwrap()).unwrap();
+            let mut inputs = 0;
+            loop {
+                let mut header = [0u8; 3];
+                if stream.read_exact(&mut header).is_err() {
+                    return;
+                }
+                let size = u16::from_be_bytes([header[0], header[1]]) as usize;
+                let mut request = vec![0; size - 3];
+                stream.read_exact(&mut request).unwrap();
+                let package_type = PackageType::try_from(header[2]).unwrap();
+                let reply = match package_type {
+                    PackageType::SetupInputs => {
+                        inputs += 1;
+                        match inputs {
+                            1 => [&[1], "INT32".as_bytes()].concat(),
+                            _ => [&[0], "IN_USE".as_bytes()].concat(),
+                        }
+                    }
+                    PackageType::SetupOutputs => [&[1], TYPES.as_bytes()].concat(),
+                    PackageType::Data => {
+                        let go = i32::from_be_bytes(request[1..5].try_into().unwrap());
+                        stream.write_all(&sample(-go)).unwrap();
+                        continue;
+                    }
+                    _ => vec![1],
+                };
+                stream.write_all(&package(package_type, &reply)).unwrap();
+            }
+        });
+        std::thread::spawn(move || {
+            let (stream, _) = primary.accept().unwrap();
+            let mut rtde = rtde_stream.recv().unwrap();
+            let ready = format!("write_output_integer_register({register}, ");
+            for line in BufReader::new(stream).lines() {
+                let token = line
+                    .unwrap()
+                    .trim()
+                    .strip_prefix(&ready)
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .and_then(|token| token.parse::<i32>().ok());
+                if let Some(token) = token.filter(|token| *token > 0) {
+                    rtde.write_all(&sample(token)).unwrap();
+                }
+            }
+        });
+        ports
+    }
+
+    #[test]
+    fn test_start_twice() {
+        let (primary, rtde) = handshaking_robot(30);
+        let mut ur = UniversalRobot::builder(Ipv4Addr::LOCALHOST.into())
+            .ports(Ports {
+                primary,
+                rtde,
+                ..Ports::DEFAULT
+            })
+            .dashboard(false)
+            .secondary(false)
+            .lazy(true)
+            .connect()
+            .unwrap();
+        let motion = Motion::new().then(Move::joint(Target::Joints(Vec6::default())));
+        let sync = SyncStart::new(30).frequency(500.0);
+        for _ in 0..2 {
+            let report = sync.start(&mut [("arm", &mut ur, &motion)]).unwrap();
+            assert_eq!(report.robots.len(), 1);
+            assert!(report.within_one_cycle());
+        }
+    }
+
+    #[test]
+    fn test_program() {
+        let motion = Motion::new().then(Move::joint(Target::Joints(Vec6::default())));
+        assert_eq!(
+            SyncStart::new(30).program(&motion, 1234).render().unwrap(),
+            "def rust_sync_motion():
+  write_output_integer_register(30, 1234)
+  while (read_input_integer_register(30) != 1234):
+    sync()
+  end
+  write_output_integer_register(30, -1234)
+  movej([0.0, 0.0, 0.0, 0.0, 0.0, 0.0], a=1.4, v=1.05, r=0.0)
+end
+"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::data::{JointHealth, Vec6, JOINT_HEALTH_OUTPUTS};
+use crate::identity::ControllerGeneration;
 use crate::prelude::UniversalRobot;
 use std::{
     net::{IpAddr, Ipv4Addr},
@@ -7,6 +9,11 @@ use std::{
 const TIMEOUT: Duration = Duration::from_secs(10);
 const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
 
+/// A file in the temporary directory, prefixed with the process id so test runs don't collide
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}_{name}", std::process::id()))
+}
+
 #[test]
 fn test_get_ur_version() {
     let mut ur = UniversalRobot::connect(ADDRESS, TIMEOUT).unwrap();
@@ -42,3 +49,43 @@ fn test_send_exception() {
     ur.exception("Hello World", "Rust").unwrap();
     ur.close().unwrap();
 }
+
+#[test]
+fn test_start_telemetry() {
+    let mut ur = UniversalRobot::connect(ADDRESS, TIMEOUT).unwrap();
+    ur.start_telemetry(&JOINT_HEALTH_OUTPUTS, 125.0).unwrap();
+    let sample: JointHealth = ur.rtde.read().unwrap().parse().unwrap();
+    println!("{:?}", sample);
+    ur.rtde.pause().unwrap();
+    ur.close().unwrap();
+}
+
+#[test]
+fn test_move_joints() {
+    let mut ur = UniversalRobot::connect(ADDRESS, TIMEOUT).unwrap();
+    ur.power_on(TIMEOUT).unwrap();
+    ur.dashboard.brake_release().unwrap();
+    let home = Vec6::new(0.0, -1.57, 1.57, -1.57, -1.57, 0.0);
+    ur.move_joints(home, 1.05, 1.4).unwrap();
+    ur.close().unwrap();
+}
+
+#[test]
+fn test_bring_up() {
+    let mut ur = UniversalRobot::connect(ADDRESS, TIMEOUT).unwrap();
+    let report = ur.sequencer().bring_up("rtde_control_loop.urp").unwrap();
+    println!("{report}");
+    assert!(report.is_success());
+    ur.dashboard.stop().unwrap();
+    ur.close().unwrap();
+}
+#[test]
+fn test_identity() {
+    let mut ur = UniversalRobot::connect(ADDRESS, TIMEOUT).unwrap();
+    let identity = ur.identity().unwrap().clone();
+    println!("{identity:?}");
+    assert_eq!(identity.generation, ControllerGeneration::ESeries);
+    assert!(identity.serial.is_some());
+    assert!(identity.dh_parameters().is_some());
+    ur.close().unwrap();
+}
@@ -0,0 +1,715 @@
+//! URScript program builder
+//!
+//! Compose programs from typed expressions and statements instead of formatting strings by hand.
+//! Rendering checks identifiers, keeps floats as floats, quotes strings and writes `p[...]`
+//! pose literals, so the output is always valid URScript text.
+//!
+//! ```ignore
+//! let program = Program::new("pick")
+//!     .body(
+//!         Block::new()
+//!             .global("target", Expr::pose(pose))
+//!             .repeat(3, Block::new().call(Call::new("movel").arg(Expr::var("target")).kwarg("v", 0.1)))
+//!             .if_else(
+//!                 Call::new("get_standard_digital_in").arg(0),
+//!                 Block::new().call(Call::new("textmsg").arg("part present")),
+//!                 Block::new().call(Call::new("popup").arg("no part")),
+//!             ),
+//!     );
+//! ur.send_script(&program.render()?)?;
+//! ```
+
+use std::fmt::Write;
+
+use crate::data::Vec6;
+use crate::prelude::*;
+
+/// URScript expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// Pose literal `p[x, y, z, rx, ry, rz]`
+    Pose(Vec6),
+    /// Joint vector literal `[q0, q1, q2, q3, q4, q5]`
+    Joints(Vec6),
+    List(Vec<Expr>),
+    Var(String),
+    Call(Call),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+/// Binary operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl Op {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::And => "and",
+            Op::Or => "or",
+        }
+    }
+}
+
+impl Expr {
+    pub fn pose(pose: Vec6) -> Self {
+        Expr::Pose(pose)
+    }
+    pub fn joints(q: Vec6) -> Self {
+        Expr::Joints(q)
+    }
+    pub fn var(name: &str) -> Self {
+        Expr::Var(name.to_owned())
+    }
+    pub fn list<T: Into<Expr>>(items: impl IntoIterator<Item = T>) -> Self {
+        Expr::List(items.into_iter().map(Into::into).collect())
+    }
+    pub fn binary(self, op: Op, rhs: impl Into<Expr>) -> Self {
+        Expr::Binary(Box::new(self), op, Box::new(rhs.into()))
+    }
+    pub fn equals(self, rhs: impl Into<Expr>) -> Self {
+        self.binary(Op::Eq, rhs)
+    }
+    pub fn lt(self, rhs: impl Into<Expr>) -> Self {
+        self.binary(Op::Lt, rhs)
+    }
+    pub fn gt(self, rhs: impl Into<Expr>) -> Self {
+        self.binary(Op::Gt, rhs)
+    }
+    pub fn and(self, rhs: impl Into<Expr>) -> Self {
+        self.binary(Op::And, rhs)
+    }
+    pub fn or(self, rhs: impl Into<Expr>) -> Self {
+        self.binary(Op::Or, rhs)
+    }
+    fn render(&self, out: &mut String) -> Result<()> {
+        match self {
+            Expr::Bool(val) => out.push_str(if *val { "True" } else { "False" }),
+            Expr::Int(val) => out.push_str(&val.to_string()),
+            Expr::Float(val) => out.push_str(&float(*val)?),
+            Expr::Str(val) => {
+                if val.contains(['"', '\n', '\r']) {
+                    return Err(Error::Script(format!("string cannot be quoted: {val:?}")));
+                }
+                let _ = write!(out, "\"{val}\"");
+            }
+            Expr::Pose(val) => {
+                out.push('p');
+                vector(val, out)?;
+            }
+            Expr::Joints(val) => vector(val, out)?,
+            Expr::List(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    item.render(out)?;
+                }
+                out.push(']');
+            }
+            Expr::Var(name) => out.push_str(identifier(name)?),
+            Expr::Call(call) => call.render(out)?,
+            Expr::Not(val) => {
+                out.push_str("(not ");
+                val.render(out)?;
+                out.push(')');
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                out.push('(');
+                lhs.render(out)?;
+                let _ = write!(out, " {} ", op.as_str());
+                rhs.render(out)?;
+                out.push(')');
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Into<Expr>> std::ops::Add<T> for Expr {
+    type Output = Expr;
+    fn add(self, rhs: T) -> Expr {
+        self.binary(Op::Add, rhs)
+    }
+}
+impl<T: Into<Expr>> std::ops::Sub<T> for Expr {
+    type Output = Expr;
+    fn sub(self, rhs: T) -> Expr {
+        self.binary(Op::Sub, rhs)
+    }
+}
+impl<T: Into<Expr>> std::ops::Mul<T> for Expr {
+    type Output = Expr;
+    fn mul(self, rhs: T) -> Expr {
+        self.binary(Op::Mul, rhs)
+    }
+}
+impl<T: Into<Expr>> std::ops::Div<T> for Expr {
+    type Output = Expr;
+    fn div(self, rhs: T) -> Expr {
+        self.binary(Op::Div, rhs)
+    }
+}
+impl std::ops::Not for Expr {
+    type Output = Expr;
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+impl From<bool> for Expr {
+    fn from(val: bool) -> Self {
+        Expr::Bool(val)
+    }
+}
+impl From<i32> for Expr {
+    fn from(val: i32) -> Self {
+        Expr::Int(val.into())
+    }
+}
+impl From<i64> for Expr {
+    fn from(val: i64) -> Self {
+        Expr::Int(val)
+    }
+}
+impl From<u32> for Expr {
+    fn from(val: u32) -> Self {
+        Expr::Int(val.into())
+    }
+}
+impl From<f64> for Expr {
+    fn from(val: f64) -> Self {
+        Expr::Float(val)
+    }
+}
+impl From<&str> for Expr {
+    fn from(val: &str) -> Self {
+        Expr::Str(val.to_owned())
+    }
+}
+impl From<String> for Expr {
+    fn from(val: String) -> Self {
+        Expr::Str(val)
+    }
+}
+impl From<Call> for Expr {
+    fn from(val: Call) -> Self {
+        Expr::Call(val)
+    }
+}
+
+/// Function call with positional and keyword arguments, e.g. `movej(q, a=1.4, v=1.05)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    name: String,
+    args: Vec<Expr>,
+    kwargs: Vec<(String, Expr)>,
+}
+
+impl Call {
+    pub fn new(name: &str) -> Self {
+        Call {
+            name: name.to_owned(),
+            args: Vec::new(),
+            kwargs: Vec::new(),
+        }
+    }
+    /// Append a positional argument
+    pub fn arg(mut self, value: impl Into<Expr>) -> Self {
+        self.args.push(value.into());
+        self
+    }
+    /// Append a keyword argument
+    pub fn kwarg(mut self, name: &str, value: impl Into<Expr>) -> Self {
+        self.kwargs.push((name.to_owned(), value.into()));
+        self
+    }
+    fn render(&self, out: &mut String) -> Result<()> {
+        out.push_str(identifier(&self.name)?);
+        out.push('(');
+        for (index, arg) in self.args.iter().enumerate() {
+            if index > 0 {
+                out.push_str(", ");
+            }
+            arg.render(out)?;
+        }
+        for (index, (name, value)) in self.kwargs.iter().enumerate() {
+            if index > 0 || !self.args.is_empty() {
+                out.push_str(", ");
+            }
+            let _ = write!(out, "{}=", identifier(name)?);
+            value.render(out)?;
+        }
+        out.push(')');
+        Ok(())
+    }
+}
+
+/// Scope of an assigned variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Plain assignment, local inside functions unless already declared global
+    Default,
+    Global,
+    Local,
+}
+
+/// URScript statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Assign {
+        name: String,
+        value: Expr,
+        scope: Scope,
+    },
+    /// Expression evaluated for its side effects, usually a [`Call`]
+    Expr(Expr),
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    While(Expr, Block),
+    /// Run the body a fixed number of times
+    Repeat(u32, Block),
+    Def(Function),
+    Thread(Thread),
+    /// `handle = run thread()`
+    Run {
+        handle: String,
+        thread: String,
+    },
+    Join(String),
+    Kill(String),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Sync,
+    Halt,
+}
+
+impl Statement {
+    /// Render this statement, indented to the block depth
+    fn render(&self, out: &mut String, depth: usize) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        out.push_str(&indent);
+        match self {
+            Statement::Assign { name, value, scope } => {
+                match scope {
+                    Scope::Default => {}
+                    Scope::Global => out.push_str("global "),
+                    Scope::Local => out.push_str("local "),
+                }
+                let _ = write!(out, "{} = ", identifier(name)?);
+                value.render(out)?;
+                out.push('\n');
+            }
+            Statement::Expr(expr) => {
+                expr.render(out)?;
+                out.push('\n');
+            }
+            Statement::If {
+                branches,
+                otherwise,
+            } => {
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(&indent);
+                        out.push_str("elif ");
+                    } else {
+                        out.push_str("if ");
+                    }
+                    condition.render(out)?;
+                    out.push_str(":\n");
+                    body.render(out, depth + 1)?;
+                }
+                if let Some(body) = otherwise {
+                    let _ = writeln!(out, "{indent}else:");
+                    body.render(out, depth + 1)?;
+                }
+                let _ = writeln!(out, "{indent}end");
+            }
+            Statement::While(condition, body) => {
+                out.push_str("while ");
+                condition.render(out)?;
+                out.push_str(":\n");
+                body.render(out, depth + 1)?;
+                let _ = writeln!(out, "{indent}end");
+            }
+            Statement::Repeat(count, body) => {
+                // counted first so that a `continue` in the body cannot loop forever
+                let counter = format!("repeat_{depth}");
+                let _ = writeln!(out, "{counter} = 0");
+                let _ = writeln!(out, "{indent}while {counter} < {count}:");
+                let _ = writeln!(out, "{indent}  {counter} = {counter} + 1");
+                body.render(out, depth + 1)?;
+                let _ = writeln!(out, "{indent}end");
+            }
+            Statement::Def(function) => function.render(out, depth)?,
+            Statement::Thread(thread) => thread.render(out, depth)?,
+            Statement::Run { handle, thread } => {
+                let _ = writeln!(
+                    out,
+                    "{} = run {}()",
+                    identifier(handle)?,
+                    identifier(thread)?
+                );
+            }
+            Statement::Join(handle) => {
+                let _ = writeln!(out, "join {}", identifier(handle)?);
+            }
+            Statement::Kill(handle) => {
+                let _ = writeln!(out, "kill {}", identifier(handle)?);
+            }
+            Statement::Return(value) => {
+                out.push_str("return");
+                if let Some(value) = value {
+                    out.push(' ');
+                    value.render(out)?;
+                }
+                out.push('\n');
+            }
+            Statement::Break => out.push_str("break\n"),
+            Statement::Continue => out.push_str("continue\n"),
+            Statement::Sync => out.push_str("sync()\n"),
+            Statement::Halt => out.push_str("halt\n"),
+        }
+        Ok(())
+    }
+}
+
+/// Sequence of statements, built up fluently
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block(pub Vec<Statement>);
+
+impl Block {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Append any statement
+    pub fn push(mut self, statement: Statement) -> Self {
+        self.0.push(statement);
+        self
+    }
+    /// Append all statements of another block
+    pub fn extend(mut self, other: Block) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+    pub fn assign(self, name: &str, value: impl Into<Expr>) -> Self {
+        self.push(Statement::Assign {
+            name: name.to_owned(),
+            value: value.into(),
+            scope: Scope::Default,
+        })
+    }
+    pub fn global(self, name: &str, value: impl Into<Expr>) -> Self {
+        self.push(Statement::Assign {
+            name: name.to_owned(),
+            value: value.into(),
+            scope: Scope::Global,
+        })
+    }
+    pub fn local(self, name: &str, value: impl Into<Expr>) -> Self {
+        self.push(Statement::Assign {
+            name: name.to_owned(),
+            value: value.into(),
+            scope: Scope::Local,
+        })
+    }
+    pub fn call(self, call: Call) -> Self {
+        self.push(Statement::Expr(Expr::Call(call)))
+    }
+    pub fn if_then(self, condition: impl Into<Expr>, then: Block) -> Self {
+        self.push(Statement::If {
+            branches: vec![(condition.into(), then)],
+            otherwise: None,
+        })
+    }
+    pub fn if_else(self, condition: impl Into<Expr>, then: Block, otherwise: Block) -> Self {
+        self.push(Statement::If {
+            branches: vec![(condition.into(), then)],
+            otherwise: Some(otherwise),
+        })
+    }
+    pub fn while_loop(self, condition: impl Into<Expr>, body: Block) -> Self {
+        self.push(Statement::While(condition.into(), body))
+    }
+    /// Run the body `count` times, using a `repeat_<depth>` counter variable
+    pub fn repeat(self, count: u32, body: Block) -> Self {
+        self.push(Statement::Repeat(count, body))
+    }
+    pub fn def(self, function: Function) -> Self {
+        self.push(Statement::Def(function))
+    }
+    pub fn thread(self, thread: Thread) -> Self {
+        self.push(Statement::Thread(thread))
+    }
+    pub fn run(self, handle: &str, thread: &str) -> Self {
+        self.push(Statement::Run {
+            handle: handle.to_owned(),
+            thread: thread.to_owned(),
+        })
+    }
+    pub fn join(self, handle: &str) -> Self {
+        self.push(Statement::Join(handle.to_owned()))
+    }
+    fn render(&self, out: &mut String, depth: usize) -> Result<()> {
+        if self.0.is_empty() {
+            // URScript doesn't allow empty bodies
+            let _ = writeln!(out, "{}sync()", "  ".repeat(depth));
+        }
+        for statement in &self.0 {
+            statement.render(out, depth)?;
+        }
+        Ok(())
+    }
+}
+
+/// Function definition `def name(params): ... end`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Block,
+}
+
+impl Function {
+    pub fn new(name: &str) -> Self {
+        Function {
+            name: name.to_owned(),
+            params: Vec::new(),
+            body: Block::new(),
+        }
+    }
+    pub fn param(mut self, name: &str) -> Self {
+        self.params.push(name.to_owned());
+        self
+    }
+    pub fn body(mut self, body: Block) -> Self {
+        self.body = body;
+        self
+    }
+    /// Render the definition, the header line is expected to be indented already
+    fn render(&self, out: &mut String, depth: usize) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        let params = self
+            .params
+            .iter()
+            .map(|param| identifier(param))
+            .collect::<Result<Vec<_>>>()?;
+        let _ = writeln!(
+            out,
+            "def {}({}):",
+            identifier(&self.name)?,
+            params.join(", ")
+        );
+        self.body.render(out, depth + 1)?;
+        let _ = writeln!(out, "{indent}end");
+        Ok(())
+    }
+}
+
+/// Thread definition `thread name(): ... end`, started with [`Block::run`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thread {
+    name: String,
+    body: Block,
+}
+
+impl Thread {
+    pub fn new(name: &str, body: Block) -> Self {
+        Thread {
+            name: name.to_owned(),
+            body,
+        }
+    }
+    /// Render the definition, the header line is expected to be indented already
+    fn render(&self, out: &mut String, depth: usize) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        let _ = writeln!(out, "thread {}():", identifier(&self.name)?);
+        self.body.render(out, depth + 1)?;
+        let _ = writeln!(out, "{indent}end");
+        Ok(())
+    }
+}
+
+/// Top level program, sent to the robot as a single function which it runs immediately
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program(Function);
+
+impl Program {
+    pub fn new(name: &str) -> Self {
+        Program(Function::new(name))
+    }
+    pub fn body(self, body: Block) -> Self {
+        Program(self.0.body(body))
+    }
+    /// Render as URScript text
+    pub fn render(&self) -> Result<String> {
+        let mut out = String::new();
+        self.0.render(&mut out, 0)?;
+        Ok(out)
+    }
+}
+
+/// URScript needs a decimal point to keep a number a float
+fn float(val: f64) -> Result<String> {
+    if !val.is_finite() {
+        return Err(Error::Script(format!("{val} is not a valid number")));
+    }
+    // `to_string` never uses an exponent, so only whole values lack the point
+    if val.fract() == 0.0 {
+        Ok(format!("{val:.1}"))
+    } else {
+        Ok(val.to_string())
+    }
+}
+
+fn vector(val: &Vec6, out: &mut String) -> Result<()> {
+    let _ = write!(
+        out,
+        "[{}, {}, {}, {}, {}, {}]",
+        float(val.x)?,
+        float(val.y)?,
+        float(val.z)?,
+        float(val.rx)?,
+        float(val.ry)?,
+        float(val.rz)?
+    );
+    Ok(())
+}
+
+fn identifier(name: &str) -> Result<&str> {
+    const KEYWORDS: [&str; 20] = [
+        "def", "end", "if", "elif", "else", "while", "thread", "run", "join", "kill", "return",
+        "break", "continue", "global", "local", "and", "or", "not", "True", "False",
+    ];
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|next| next.is_ascii_alphanumeric() || next == '_')
+        && !KEYWORDS.contains(&name);
+    if valid {
+        Ok(name)
+    } else {
+        Err(Error::Script(format!("'{name}' is not a valid identifier")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals() {
+        let render = |expr: Expr| {
+            let mut out = String::new();
+            expr.render(&mut out).map(|_| out)
+        };
+        assert_eq!(render(1.0.into()).unwrap(), "1.0");
+        assert_eq!(render((-0.125).into()).unwrap(), "-0.125");
+        assert_eq!(render(1e20.into()).unwrap(), "100000000000000000000.0");
+        assert_eq!(render(3.into()).unwrap(), "3");
+        assert_eq!(render(true.into()).unwrap(), "True");
+        assert_eq!(render("hello".into()).unwrap(), "\"hello\"");
+        assert_eq!(
+            render(Expr::pose(Vec6::new(0.1, 0.0, 0.3, 0.0, 3.11, 0.0))).unwrap(),
+            "p[0.1, 0.0, 0.3, 0.0, 3.11, 0.0]"
+        );
+        assert_eq!(
+            render(Expr::joints(Vec6::default())).unwrap(),
+            "[0.0, 0.0, 0.0, 0.0, 0.0, 0.0]"
+        );
+        assert_eq!(
+            render((!Expr::var("done")).and(Expr::var("ready"))).unwrap(),
+            "((not done) and ready)"
+        );
+        assert!(render(f64::NAN.into()).is_err());
+        assert!(render("say \"hi\"".into()).is_err());
+        assert!(render(Expr::var("1x")).is_err());
+        assert!(render(Expr::var("end")).is_err());
+    }
+    #[test]
+    fn test_program() {
+        let program = Program::new("demo").body(
+            Block::new()
+                .global("count", 0)
+                .thread(Thread::new(
+                    "counter",
+                    Block::new().while_loop(
+                        true,
+                        Block::new()
+                            .assign("count", Expr::var("count") + 1)
+                            .push(Statement::Sync),
+                    ),
+                ))
+                .run("handle", "counter")
+                .repeat(
+                    2,
+                    Block::new().call(Call::new("movej").arg(Expr::var("q")).kwarg("r", 0.0)),
+                )
+                .if_else(
+                    Expr::var("count").gt(10),
+                    Block::new().call(Call::new("textmsg").arg("done")),
+                    Block::new(),
+                )
+                .push(Statement::Kill("handle".to_owned())),
+        );
+        assert_eq!(
+            program.render().unwrap(),
+            "def demo():
+  global count = 0
+  thread counter():
+    while True:
+      count = (count + 1)
+      sync()
+    end
+  end
+  handle = run counter()
+  repeat_1 = 0
+  while repeat_1 < 2:
+    repeat_1 = repeat_1 + 1
+    movej(q, r=0.0)
+  end
+  if (count > 10):
+    textmsg(\"done\")
+  else:
+    sync()
+  end
+  kill handle
+end
+"
+        );
+    }
+}
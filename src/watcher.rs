@@ -0,0 +1,416 @@
+//! Robot status watcher
+//!
+//! Merges the dashboard state queries with the `robot_mode`, `safety_mode` and `runtime_state`
+//! RTDE outputs, turns changes between polls into [`StateEvent`]s, and waits for the robot to
+//! reach a state.
+//!
+//! ```ignore
+//! let events = ur.watcher.subscribe();
+//! ur.dashboard.power(true)?;
+//! ur.wait_for(&[StatusField::Mode], |status| status.mode == RobotMode::Idle, TIMEOUT)?;
+//! for event in events.try_iter() {
+//!     println!("{event:?}");
+//! }
+//! ```
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::dashboard::path::ProgramPath;
+use crate::dashboard::types::{ProgramState, RobotMode, SafetyStatus};
+use crate::data::Value;
+use crate::prelude::*;
+use crate::types::RuntimeState;
+use crate::Rtde;
+
+/// Snapshot of the robot state
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotStatus {
+    pub mode: RobotMode,
+    pub safety: SafetyStatus,
+    pub program: ProgramState,
+    /// Program path as reported by [`Dashboard::get_loaded_program`], `None` if none is loaded
+    pub loaded_program: Option<ProgramPath>,
+    /// Only known when merging an RTDE stream that includes `runtime_state`
+    pub runtime: Option<RuntimeState>,
+}
+
+/// Part of the status queried from the dashboard server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusField {
+    Mode,
+    Safety,
+    Program,
+    LoadedProgram,
+}
+
+impl StatusField {
+    pub const ALL: [StatusField; 4] = [
+        StatusField::Mode,
+        StatusField::Safety,
+        StatusField::Program,
+        StatusField::LoadedProgram,
+    ];
+}
+
+/// Change between two status snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateEvent {
+    ModeChanged {
+        from: RobotMode,
+        to: RobotMode,
+    },
+    SafetyChanged {
+        from: SafetyStatus,
+        to: SafetyStatus,
+    },
+    ProtectiveStop,
+    EmergencyStop(SafetyStatus),
+    ProgramLoaded(Option<ProgramPath>),
+    ProgramStarted(String),
+    ProgramPaused(String),
+    ProgramStopped(Option<String>),
+    RuntimeChanged {
+        from: Option<RuntimeState>,
+        to: Option<RuntimeState>,
+    },
+}
+
+impl StateEvent {
+    /// Events describing the changes from one status to the next
+    pub fn between(previous: &RobotStatus, next: &RobotStatus) -> Vec<StateEvent> {
+        let mut events = Vec::new();
+        if previous.mode != next.mode {
+            events.push(StateEvent::ModeChanged {
+                from: previous.mode,
+                to: next.mode,
+            });
+        }
+        if previous.safety != next.safety {
+            events.push(StateEvent::SafetyChanged {
+                from: previous.safety,
+                to: next.safety,
+            });
+            match next.safety {
+                SafetyStatus::ProtectiveStop => events.push(StateEvent::ProtectiveStop),
+                SafetyStatus::SystemEmergencyStop | SafetyStatus::RobotEmergencyStop => {
+                    events.push(StateEvent::EmergencyStop(next.safety))
+                }
+                _ => {}
+            }
+        }
+        if previous.loaded_program != next.loaded_program {
+            events.push(StateEvent::ProgramLoaded(next.loaded_program.clone()));
+        }
+        if previous.program != next.program {
+            events.push(match &next.program {
+                ProgramState::Playing(name) => StateEvent::ProgramStarted(name.clone()),
+                ProgramState::Paused(name) => StateEvent::ProgramPaused(name.clone()),
+                ProgramState::Stopped(name) => StateEvent::ProgramStopped(name.clone()),
+            });
+        }
+        if previous.runtime != next.runtime {
+            events.push(StateEvent::RuntimeChanged {
+                from: previous.runtime,
+                to: next.runtime,
+            });
+        }
+        events
+    }
+}
+
+/// Polls the robot state and reports changes to subscribers
+pub struct StateWatcher {
+    status: Option<RobotStatus>,
+    subscribers: Vec<Sender<StateEvent>>,
+    poll_interval: Duration,
+}
+
+impl Default for StateWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    pub fn new() -> Self {
+        StateWatcher {
+            status: None,
+            subscribers: Vec::new(),
+            poll_interval: Self::POLL_INTERVAL,
+        }
+    }
+    /// Time to sleep between polls while waiting
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+    /// Receive every event found by future polls
+    pub fn subscribe(&mut self) -> Receiver<StateEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+    /// Status found by the latest poll
+    pub fn status(&self) -> Option<&RobotStatus> {
+        self.status.as_ref()
+    }
+    /// Query the whole robot state and report what changed since the last poll, see
+    /// [`poll_fields`](Self::poll_fields)
+    pub fn poll(
+        &mut self,
+        dashboard: &mut Dashboard,
+        rtde: Option<&mut Rtde>,
+    ) -> Result<Vec<StateEvent>> {
+        self.poll_fields(dashboard, rtde, &StatusField::ALL)
+    }
+    /// Query these fields of the robot state and report what changed since the last poll.
+    ///
+    /// The other fields keep the values of the previous poll, the first poll queries them all.
+    /// When an RTDE stream is passed in and its output recipe includes `robot_mode`,
+    /// `safety_mode` or `runtime_state`, the stream is read up to its newest data package and
+    /// those values are used in place of the equivalent dashboard queries.
+    pub fn poll_fields(
+        &mut self,
+        dashboard: &mut Dashboard,
+        rtde: Option<&mut Rtde>,
+        fields: &[StatusField],
+    ) -> Result<Vec<StateEvent>> {
+        let sample = match rtde {
+            Some(rtde) => RtdeStatus::read(rtde)?,
+            None => RtdeStatus::default(),
+        };
+        let previous = self.status.as_ref();
+        let query = |field: StatusField| previous.is_none() || fields.contains(&field);
+        let next = RobotStatus {
+            mode: match (sample.mode, previous) {
+                (Some(mode), _) => mode,
+                (None, Some(previous)) if !query(StatusField::Mode) => previous.mode,
+                (None, _) => dashboard.get_mode()?,
+            },
+            safety: match (sample.safety, previous) {
+                (Some(safety), _) => safety,
+                (None, Some(previous)) if !query(StatusField::Safety) => previous.safety,
+                (None, _) => dashboard.safety_status()?,
+            },
+            program: match previous {
+                Some(previous) if !query(StatusField::Program) => previous.program.clone(),
+                _ => dashboard.get_program_state()?,
+            },
+            loaded_program: match previous {
+                Some(previous) if !query(StatusField::LoadedProgram) => {
+                    previous.loaded_program.clone()
+                }
+                _ => dashboard.get_loaded_program()?,
+            },
+            runtime: sample.runtime,
+        };
+        let events = match &self.status {
+            Some(previous) => StateEvent::between(previous, &next),
+            None => Vec::new(),
+        };
+        for event in &events {
+            log::debug!("robot state: {event:?}");
+        }
+        // drop subscribers that have hung up
+        self.subscribers.retain(|subscriber| {
+            events
+                .iter()
+                .all(|event| subscriber.send(event.clone()).is_ok())
+        });
+        self.status = Some(next);
+        Ok(events)
+    }
+    /// Poll the fields the predicate looks at until the status satisfies it, returning that status
+    pub fn wait_for<F>(
+        &mut self,
+        dashboard: &mut Dashboard,
+        mut rtde: Option<&mut Rtde>,
+        fields: &[StatusField],
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<RobotStatus>
+    where
+        F: FnMut(&RobotStatus) -> bool,
+    {
+        let now = Instant::now();
+        loop {
+            self.poll_fields(dashboard, rtde.as_deref_mut(), fields)?;
+            let Some(status) = &self.status else {
+                return Err(Error::Static("no status after polling"));
+            };
+            if predicate(status) {
+                return Ok(status.clone());
+            }
+            if now.elapsed() > timeout {
+                return Err(Error::Timeout(
+                    format!("{status:?}"),
+                    now.elapsed().as_secs(),
+                ));
+            }
+            sleep(self.poll_interval);
+        }
+    }
+}
+
+/// State variables found in a single RTDE data package
+#[derive(Default)]
+struct RtdeStatus {
+    mode: Option<RobotMode>,
+    safety: Option<SafetyStatus>,
+    runtime: Option<RuntimeState>,
+}
+
+impl RtdeStatus {
+    fn read(rtde: &mut Rtde) -> Result<Self> {
+        let indices = [
+            rtde.output_index("robot_mode"),
+            rtde.output_index("safety_mode"),
+            rtde.output_index("runtime_state"),
+        ];
+        if !rtde.is_streaming() || indices.iter().all(Option::is_none) {
+            return Ok(Self::default());
+        }
+        let types = rtde.output_types().to_vec();
+        let values = rtde.read_latest()?.values(&types)?;
+        let value = |index: Option<usize>| index.and_then(|index| values.get(index).copied());
+        Ok(RtdeStatus {
+            mode: match value(indices[0]) {
+                Some(Value::I32(mode)) => Some(mode.try_into()?),
+                _ => None,
+            },
+            safety: match value(indices[1]) {
+                Some(Value::I32(mode)) => Some(mode.try_into()?),
+                _ => None,
+            },
+            runtime: match value(indices[2]) {
+                Some(Value::U32(state)) => Some(state.try_into()?),
+                _ => None,
+            },
+        })
+    }
+}
+
+impl UniversalRobot {
+    /// Poll the dashboard for the fields the predicate looks at until the robot status satisfies it
+    pub fn wait_for<F>(
+        &mut self,
+        fields: &[StatusField],
+        predicate: F,
+        timeout: Duration,
+    ) -> Result<RobotStatus>
+    where
+        F: FnMut(&RobotStatus) -> bool,
+    {
+        self.watcher
+            .wait_for(&mut self.dashboard, None, fields, predicate, timeout)
+    }
+    /// Poll the robot status once, merging in the RTDE stream, and report what changed
+    pub fn poll_state(&mut self) -> Result<Vec<StateEvent>> {
+        self.watcher.poll(&mut self.dashboard, Some(&mut self.rtde))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Dashboard server of a freshly booted robot, recording the commands it receives
+    fn fresh_dashboard() -> (Dashboard, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&commands);
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "Connected: Universal Robots Dashboard Server").unwrap();
+            for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                let Ok(command) = line else { break };
+                let reply = match command.as_str() {
+                    "robotmode" => "Robotmode: POWER_OFF",
+                    "safetystatus" => "Safetystatus: NORMAL",
+                    "programState" => "STOPPED <unnamed>",
+                    "get loaded program" => "No program loaded",
+                    _ => "Added log message",
+                };
+                received.lock().unwrap().push(command);
+                if writeln!(stream, "{reply}").is_err() {
+                    break;
+                }
+            }
+        });
+        let dashboard = Dashboard::connect(address, Some(Duration::from_secs(2))).unwrap();
+        (dashboard, commands)
+    }
+
+    fn status() -> RobotStatus {
+        RobotStatus {
+            mode: RobotMode::Running,
+            safety: SafetyStatus::Normal,
+            program: ProgramState::Playing("demo.urp".to_owned()),
+            loaded_program: Some(ProgramPath::new("demo.urp")),
+            runtime: Some(RuntimeState::Playing),
+        }
+    }
+
+    #[test]
+    fn test_no_change() {
+        assert!(StateEvent::between(&status(), &status()).is_empty());
+    }
+    #[test]
+    fn test_protective_stop() {
+        let next = RobotStatus {
+            safety: SafetyStatus::ProtectiveStop,
+            program: ProgramState::Stopped(Some("demo.urp".to_owned())),
+            runtime: Some(RuntimeState::Stopped),
+            ..status()
+        };
+        assert_eq!(
+            StateEvent::between(&status(), &next),
+            vec![
+                StateEvent::SafetyChanged {
+                    from: SafetyStatus::Normal,
+                    to: SafetyStatus::ProtectiveStop
+                },
+                StateEvent::ProtectiveStop,
+                StateEvent::ProgramStopped(Some("demo.urp".to_owned())),
+                StateEvent::RuntimeChanged {
+                    from: Some(RuntimeState::Playing),
+                    to: Some(RuntimeState::Stopped)
+                },
+            ]
+        );
+    }
+    #[test]
+    fn test_emergency_stop() {
+        let next = RobotStatus {
+            mode: RobotMode::PowerOff,
+            safety: SafetyStatus::RobotEmergencyStop,
+            ..status()
+        };
+        let events = StateEvent::between(&status(), &next);
+        assert!(events.contains(&StateEvent::ModeChanged {
+            from: RobotMode::Running,
+            to: RobotMode::PowerOff
+        }));
+        assert!(events.contains(&StateEvent::EmergencyStop(SafetyStatus::RobotEmergencyStop)));
+    }
+    #[test]
+    fn test_poll_fields() {
+        let (mut dashboard, commands) = fresh_dashboard();
+        let mut watcher = StateWatcher::new();
+        watcher.poll(&mut dashboard, None).unwrap();
+        let status = watcher.status().unwrap();
+        assert_eq!(status.mode, RobotMode::PowerOff);
+        assert_eq!(status.loaded_program, None);
+        commands.lock().unwrap().clear();
+        watcher
+            .poll_fields(&mut dashboard, None, &[StatusField::Mode])
+            .unwrap();
+        assert_eq!(*commands.lock().unwrap(), ["robotmode"]);
+    }
+}